@@ -2,6 +2,7 @@ use std::{
     env,
     fmt::{self, Display},
     io::Read,
+    net::SocketAddr,
     num::NonZeroU32,
     path::PathBuf,
     str::FromStr,
@@ -15,10 +16,16 @@ use lading::{
     generator::{self, process_tree},
     inspector, observer,
     signals::Shutdown,
-    target::{self, Behavior, Output},
+    target::{
+        self, discovery,
+        limits::{self, CpuList},
+        readiness, scan, Behavior, Output,
+    },
     target_metrics,
 };
+use metrics::gauge;
 use metrics_exporter_prometheus::PrometheusBuilder;
+use nix::sys::signal::Signal;
 use rand::{rngs::StdRng, SeedableRng};
 use rustc_hash::FxHashMap;
 use tokio::{
@@ -28,6 +35,7 @@ use tokio::{
     time::{sleep, Duration},
 };
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 fn default_config_path() -> String {
     "/etc/lading/lading.yaml".to_string()
@@ -75,7 +83,15 @@ impl FromStr for CliKeyValues {
 #[clap(group(
     ArgGroup::new("target")
         .required(true)
-        .args(&["target-path", "target-pid", "no-target"]),
+        .args(&[
+            "target-path",
+            "target-pid",
+            "target-pidfile",
+            "target-process-name",
+            "target-cmdline",
+            "target-cgroup",
+            "no-target",
+        ]),
 ))]
 struct Opts {
     /// path on disk to the configuration file
@@ -87,6 +103,25 @@ struct Opts {
     /// measure an externally-launched process by PID
     #[clap(long)]
     target_pid: Option<NonZeroU32>,
+    /// measure an externally-launched process whose PID is written to this
+    /// file
+    #[clap(long, group = "target-discovery")]
+    target_pidfile: Option<PathBuf>,
+    /// measure an externally-launched process whose name matches this
+    /// regular expression
+    #[clap(long, group = "target-discovery")]
+    target_process_name: Option<String>,
+    /// measure an externally-launched process whose command line matches this
+    /// regular expression
+    #[clap(long, group = "target-discovery")]
+    target_cmdline: Option<String>,
+    /// measure the externally-launched processes in this cgroup directory
+    #[clap(long, group = "target-discovery")]
+    target_cgroup: Option<PathBuf>,
+    /// the maximum time, in seconds, to wait for a discovered target to
+    /// appear
+    #[clap(long, default_value_t = 60, requires = "target-discovery")]
+    target_discovery_timeout_seconds: u64,
     /// disable target measurement
     #[clap(long)]
     no_target: bool,
@@ -109,6 +144,72 @@ struct Opts {
     /// the path to write target's stderr
     #[clap(long, default_value_t = default_target_behavior(), requires = "binary-target")]
     target_stderr_path: Behavior,
+    /// count lines of target stdout and stderr, implied by any target output
+    /// pattern
+    #[clap(long, requires = "binary-target", action)]
+    target_output_metrics: bool,
+    /// count lines of target output matching a pattern, format NAME=REGEX;
+    /// may be repeated
+    #[clap(long, requires = "binary-target")]
+    target_output_pattern: Vec<scan::Pattern>,
+    /// abort the experiment if a line of target output matches a pattern,
+    /// format NAME=REGEX; may be repeated
+    #[clap(long, requires = "binary-target")]
+    target_output_abort_pattern: Vec<scan::Pattern>,
+    /// the maximum number of times to restart the target if it exits early,
+    /// by default the target is not restarted
+    #[clap(long, requires = "binary-target")]
+    target_restart_max: Option<u32>,
+    /// the delay, in milliseconds, before the first target restart; doubles
+    /// with each subsequent restart
+    #[clap(long, default_value_t = 1_000, requires = "target-restart-max")]
+    target_restart_backoff_ms: u64,
+    /// the maximum delay, in milliseconds, between target restarts
+    #[clap(long, default_value_t = 30_000, requires = "target-restart-max")]
+    target_restart_max_backoff_ms: u64,
+    /// wait until a TCP connection to this address succeeds before starting
+    /// load
+    #[clap(long, group = "target-readiness")]
+    target_ready_tcp: Option<SocketAddr>,
+    /// wait until an HTTP GET of this URI returns a 2xx status before starting
+    /// load
+    #[clap(long, group = "target-readiness")]
+    target_ready_http: Option<http::Uri>,
+    /// wait until a Unix socket exists at this path before starting load
+    #[clap(long, group = "target-readiness")]
+    target_ready_unix_socket: Option<PathBuf>,
+    /// wait until the target writes a line to stdout matching this regular
+    /// expression before starting load
+    #[clap(long, group = "target-readiness", requires = "binary-target")]
+    target_ready_stdout_regex: Option<String>,
+    /// the maximum time, in seconds, to wait for the target to become ready
+    #[clap(long, default_value_t = 60, requires = "target-readiness")]
+    target_ready_timeout_seconds: u64,
+    /// the signal sent to the target to stop it when lading shuts down
+    #[clap(long, default_value_t = Signal::SIGTERM, requires = "binary-target")]
+    target_stop_signal: Signal,
+    /// the time, in seconds, the target has to exit after the stop signal
+    /// before it is sent SIGKILL; by default lading waits up to
+    /// max-shutdown-delay
+    #[clap(long, requires = "binary-target")]
+    target_stop_grace_seconds: Option<u64>,
+    /// the cgroup v2 directory in which lading creates a cgroup for the
+    /// target, used if any target cgroup limit is set
+    #[clap(long, default_value = "/sys/fs/cgroup", requires = "binary-target")]
+    target_cgroup_parent: PathBuf,
+    /// the CPU quota of the target's cgroup in cores, fractions allowed
+    #[clap(long, requires = "binary-target")]
+    target_cgroup_cpus: Option<limits::CpuQuota>,
+    /// the maximum memory of the target's cgroup
+    #[clap(long, requires = "binary-target")]
+    target_cgroup_memory_max: Option<byte_unit::Byte>,
+    /// pin the target to these CPUs, format 0-3,6; by default the target
+    /// inherits lading's CPU affinity
+    #[clap(long, requires = "binary-target")]
+    target_cpu_affinity: Option<CpuList>,
+    /// pin lading's own threads to these CPUs, format 0-3,6
+    #[clap(long)]
+    lading_cpu_affinity: Option<CpuList>,
     /// the maximum amount of RSS bytes the target may consume before lading backs off load
     #[clap(long)]
     target_rss_bytes_limit: Option<byte_unit::Byte>,
@@ -130,7 +231,7 @@ struct Opts {
     /// samples
     #[clap(long, default_value_t = 30)]
     warmup_duration_seconds: u32,
-    /// whether to ignore inspector configuration, if present, and not run the inspector
+    /// whether to ignore inspector configuration, if present, and not run the inspectors
     #[clap(long)]
     disable_inspector: bool,
     /// Extra sub commands
@@ -159,6 +260,36 @@ struct ProcessTreeGen {
     config_content: Option<String>,
 }
 
+fn discovery_method(ops: &Opts) -> Option<discovery::Method> {
+    if let Some(path) = &ops.target_pidfile {
+        Some(discovery::Method::Pidfile(path.clone()))
+    } else if let Some(pattern) = &ops.target_process_name {
+        Some(discovery::Method::ProcessName(pattern.clone()))
+    } else if let Some(pattern) = &ops.target_cmdline {
+        Some(discovery::Method::Cmdline(pattern.clone()))
+    } else {
+        ops.target_cgroup.clone().map(discovery::Method::Cgroup)
+    }
+}
+
+fn output_scan(ops: &Opts) -> Option<scan::Config> {
+    let patterns: Vec<scan::Pattern> = ops
+        .target_output_pattern
+        .iter()
+        .cloned()
+        .chain(
+            ops.target_output_abort_pattern
+                .iter()
+                .cloned()
+                .map(|pattern| scan::Pattern {
+                    abort: true,
+                    ..pattern
+                }),
+        )
+        .collect();
+    (ops.target_output_metrics || !patterns.is_empty()).then_some(scan::Config { patterns })
+}
+
 fn get_config(ops: &Opts) -> Config {
     let contents = if let Ok(env_var_value) = env::var("LADING_CONFIG") {
         debug!("Using config from env var 'LADING_CONFIG'");
@@ -185,10 +316,31 @@ fn get_config(ops: &Opts) -> Config {
     if let Some(rss_bytes_limit) = ops.target_rss_bytes_limit {
         target::Meta::set_rss_bytes_limit(rss_bytes_limit).unwrap();
     }
+    let readiness_check = if let Some(addr) = ops.target_ready_tcp {
+        Some(readiness::Check::Tcp(addr))
+    } else if let Some(uri) = &ops.target_ready_http {
+        Some(readiness::Check::Http(uri.clone()))
+    } else if let Some(path) = &ops.target_ready_unix_socket {
+        Some(readiness::Check::UnixSocket(path.clone()))
+    } else {
+        ops.target_ready_stdout_regex
+            .clone()
+            .map(readiness::Check::StdoutRegex)
+    };
+    let readiness = readiness_check.map(|check| readiness::Config {
+        check,
+        timeout: Duration::from_secs(ops.target_ready_timeout_seconds),
+    });
     let target = if ops.no_target {
         None
     } else if let Some(pid) = ops.target_pid {
-        Some(target::Config::Pid(target::PidConfig { pid }))
+        Some(target::Config::Pid(target::PidConfig { pid, readiness }))
+    } else if let Some(method) = discovery_method(ops) {
+        Some(target::Config::Discover(discovery::Config {
+            method,
+            timeout: Duration::from_secs(ops.target_discovery_timeout_seconds),
+            readiness,
+        }))
     } else if let Some(path) = &ops.target_path {
         Some(target::Config::Binary(target::BinaryConfig {
             command: path.clone(),
@@ -203,6 +355,31 @@ fn get_config(ops: &Opts) -> Config {
                 stderr: ops.target_stderr_path.clone(),
                 stdout: ops.target_stdout_path.clone(),
             },
+            output_scan: output_scan(ops),
+            restart_policy: ops
+                .target_restart_max
+                .map(|max_restarts| target::RestartPolicy {
+                    max_restarts,
+                    initial_backoff: Duration::from_millis(ops.target_restart_backoff_ms),
+                    max_backoff: Duration::from_millis(ops.target_restart_max_backoff_ms),
+                }),
+            readiness,
+            stop: target::StopPolicy {
+                signal: ops.target_stop_signal,
+                grace_period: ops.target_stop_grace_seconds.map(Duration::from_secs),
+            },
+            limits: limits::Config {
+                cgroup: (ops.target_cgroup_cpus.is_some()
+                    || ops.target_cgroup_memory_max.is_some())
+                .then(|| limits::CgroupConfig {
+                    parent: ops.target_cgroup_parent.clone(),
+                    cpu_quota: ops.target_cgroup_cpus,
+                    memory_max: ops
+                        .target_cgroup_memory_max
+                        .map(|bytes| u64::try_from(bytes.get_bytes()).unwrap()),
+                }),
+                cpu_affinity: ops.target_cpu_affinity.clone(),
+            },
         }))
     } else {
         unreachable!("clap ensures that exactly one target option is selected");
@@ -248,15 +425,22 @@ async fn inner_main(
     warmup_duration: Duration,
     max_shutdown_delay: Duration,
     disable_inspector: bool,
+    lading_cpu_affinity: Option<CpuList>,
     config: Config,
 ) {
     let shutdown = Shutdown::new();
+    // The capture manager is shut down only once all other tasks have, so that
+    // anything they record while shutting down -- the target's shutdown
+    // duration, say -- makes it into the capture.
+    let capture_shutdown = Shutdown::new();
 
     // Set up the telemetry sub-system.
     //
     // We support two methods to exflitrate telemetry about the target from rig:
     // a passive prometheus export and an active log file. Only one can be
-    // active at a time.
+    // active at a time. Artifacts written during the run are associated with
+    // the run ID of the capture file, if there is one.
+    let mut run_id = Uuid::new_v4();
     match config.telemetry {
         Telemetry::Prometheus {
             prometheus_addr,
@@ -272,14 +456,18 @@ async fn inner_main(
             path,
             global_labels,
         } => {
-            let mut capture_manager = CaptureManager::new(path, shutdown.clone()).await;
+            let mut capture_manager = CaptureManager::new(path, capture_shutdown.clone()).await;
             capture_manager.install();
+            run_id = capture_manager.run_id();
             for (k, v) in global_labels {
                 capture_manager.add_global_label(k, v);
             }
             let _capmgr = tokio::spawn(capture_manager.run());
         }
     }
+    if let Some(cpus) = lading_cpu_affinity {
+        gauge!("lading_cpu_affinity_count", cpus.len() as f64);
+    }
 
     // Set up the application servers. These are, depending on configuration:
     //
@@ -295,6 +483,9 @@ async fn inner_main(
     //   detail to the capture log
 
     let (tgt_snd, _tgt_rcv) = broadcast::channel(1);
+    // The target PID is only transmitted once the target is ready for load, at
+    // which point the warmup clock starts.
+    let mut tgt_ready_rcv = tgt_snd.subscribe();
 
     //
     // GENERATOR
@@ -308,12 +499,17 @@ async fn inner_main(
     //
     // INSPECTOR
     //
-    if let Some(inspector_conf) = config.inspector {
-        if !disable_inspector {
+    if !disable_inspector {
+        for inspector_conf in config.inspector.into_iter().chain(config.inspectors) {
             let tgt_rcv = tgt_snd.subscribe();
             let inspector_server =
-                inspector::Server::new(inspector_conf, shutdown.clone()).unwrap();
-            let _isrv = tokio::spawn(inspector_server.run(tgt_rcv));
+                inspector::Server::new(inspector_conf, run_id, shutdown.clone()).unwrap();
+            let _isrv = tokio::spawn(async {
+                match inspector_server.run(tgt_rcv).await {
+                    Ok(()) => debug!("inspector shut down successfully"),
+                    Err(err) => warn!("inspector failed with {:?}", err),
+                }
+            });
         }
     }
 
@@ -353,7 +549,16 @@ async fn inner_main(
     // Observer is not used when there is no target.
     let tsrv = if let Some(target) = config.target {
         let obs_rcv = tgt_snd.subscribe();
-        let observer_server = observer::Server::new(config.observer, shutdown.clone()).unwrap();
+        let mut observer_config = config.observer;
+        // A target discovered by cgroup is observed as the whole cgroup.
+        if let target::Config::Discover(discovery::Config {
+            method: discovery::Method::Cgroup(path),
+            ..
+        }) = &target
+        {
+            observer_config.cgroup.get_or_insert_with(|| path.clone());
+        }
+        let observer_server = observer::Server::new(observer_config, shutdown.clone()).unwrap();
         let _osrv = tokio::spawn(observer_server.run(obs_rcv));
 
         //
@@ -371,6 +576,12 @@ async fn inner_main(
     };
 
     let experiment_sleep = async move {
+        if tgt_ready_rcv.recv().await.is_err() {
+            // The target server exited without the target becoming ready.
+            // That failure is handled below.
+            futures::future::pending::<()>().await;
+        }
+        drop(tgt_ready_rcv);
         info!("target is running, now sleeping for warmup");
         sleep(warmup_duration).await;
         info!("warmup completed, collecting samples");
@@ -404,6 +615,8 @@ async fn inner_main(
         max_shutdown_delay.as_secs(),
     );
     shutdown.wait(max_shutdown_delay).await;
+    capture_shutdown.signal().unwrap();
+    capture_shutdown.wait(max_shutdown_delay).await;
 }
 
 fn run_process_tree(opts: ProcessTreeGen) {
@@ -469,6 +682,12 @@ fn main() {
     let max_shutdown_delay = Duration::from_secs(opts.max_shutdown_delay.into()) / 2;
     let disable_inspector = opts.disable_inspector;
 
+    // Pin lading before any runtime threads are created, as threads inherit
+    // the affinity of the thread that creates them.
+    if let Some(cpus) = &opts.lading_cpu_affinity {
+        limits::pin_current_thread(cpus).unwrap();
+    }
+
     let runtime = Builder::new_multi_thread()
         .enable_io()
         .enable_time()
@@ -479,6 +698,7 @@ fn main() {
         warmup_duration,
         max_shutdown_delay,
         disable_inspector,
+        opts.lading_cpu_affinity,
         config,
     ));
     // The splunk_hec generator spawns long running tasks that are not plugged
@@ -10,6 +10,7 @@ use serde::Deserialize;
 use crate::signals::Shutdown;
 
 pub mod http;
+pub mod prometheus_remote_write;
 pub mod splunk_hec;
 pub mod sqs;
 pub mod tcp;
@@ -24,6 +25,8 @@ pub enum Error {
     Tcp(tcp::Error),
     /// See [`crate::blackhole::http::Error`] for details.
     Http(http::Error),
+    /// See [`crate::blackhole::prometheus_remote_write::Error`] for details.
+    PrometheusRemoteWrite(prometheus_remote_write::Error),
     /// See [`crate::blackhole::splunk_hec::Error`] for details.
     SplunkHec(splunk_hec::Error),
     /// See [`crate::blackhole::udp::Error`] for details.
@@ -64,6 +67,8 @@ pub enum Inner {
     Tcp(tcp::Config),
     /// See [`crate::blackhole::http::Config`] for details.
     Http(http::Config),
+    /// See [`crate::blackhole::prometheus_remote_write::Config`] for details.
+    PrometheusRemoteWrite(prometheus_remote_write::Config),
     /// See [`crate::blackhole::splunk_hec::Config`] for details.
     SplunkHec(splunk_hec::Config),
     /// See [`crate::blackhole::udp::Config`] for details.
@@ -86,6 +91,8 @@ pub enum Server {
     Tcp(tcp::Tcp),
     /// See [`crate::blackhole::http::Http`] for details.
     Http(http::Http),
+    /// See [`crate::blackhole::prometheus_remote_write::PrometheusRemoteWrite`] for details.
+    PrometheusRemoteWrite(prometheus_remote_write::PrometheusRemoteWrite),
     /// See [`crate::blackhole::splunk_hec::SplunkHec`] for details.
     SplunkHec(splunk_hec::SplunkHec),
     /// See [`crate::blackhole::udp::Udp`] for details.
@@ -114,6 +121,13 @@ impl Server {
             Inner::Http(conf) => {
                 Self::Http(http::Http::new(config.general, &conf, shutdown).map_err(Error::Http)?)
             }
+            Inner::PrometheusRemoteWrite(conf) => {
+                Self::PrometheusRemoteWrite(prometheus_remote_write::PrometheusRemoteWrite::new(
+                    config.general,
+                    &conf,
+                    shutdown,
+                ))
+            }
             Inner::Udp(conf) => Self::Udp(udp::Udp::new(config.general, &conf, shutdown)),
             Inner::UnixStream(conf) => {
                 Self::UnixStream(unix_stream::UnixStream::new(config.general, conf, shutdown))
@@ -144,6 +158,9 @@ impl Server {
         match self {
             Server::Tcp(inner) => inner.run().await.map_err(Error::Tcp),
             Server::Http(inner) => inner.run().await.map_err(Error::Http),
+            Server::PrometheusRemoteWrite(inner) => {
+                inner.run().await.map_err(Error::PrometheusRemoteWrite)
+            }
             Server::Udp(inner) => Box::pin(inner.run()).await.map_err(Error::Udp),
             Server::UnixStream(inner) => inner.run().await.map_err(Error::UnixStream),
             Server::UnixDatagram(inner) => Box::pin(inner.run()).await.map_err(Error::UnixDatagram),
@@ -0,0 +1,247 @@
+//! The Prometheus remote write speaking blackhole.
+//!
+//! Incoming requests are snappy-decompressed and decoded as a `WriteRequest`
+//! so that series and samples can be counted.
+//!
+//! ## Metrics
+//!
+//! `bytes_received`: Total bytes received
+//! `requests_received`: Total requests received
+//! `series_received`: Total series received
+//! `samples_received`: Total samples received
+//! `decode_failure`: Requests that could not be decompressed or decoded
+//!
+
+use std::{net::SocketAddr, time::Duration};
+
+use hyper::{
+    body,
+    server::conn::{AddrIncoming, AddrStream},
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use lading_payload::prometheus_remote_write::WriteRequest;
+use metrics::{register_counter, Counter};
+use prost::Message;
+use serde::Deserialize;
+use tower::ServiceBuilder;
+use tracing::{debug, error, info};
+
+use crate::signals::Shutdown;
+
+use super::General;
+
+fn default_concurrent_requests_max() -> usize {
+    100
+}
+
+/// Errors produced by [`PrometheusRemoteWrite`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Wrapper for [`hyper::Error`].
+    #[error("HTTP server error: {0}")]
+    Hyper(hyper::Error),
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for [`PrometheusRemoteWrite`]
+pub struct Config {
+    /// number of concurrent HTTP connections to allow
+    #[serde(default = "default_concurrent_requests_max")]
+    pub concurrent_requests_max: usize,
+    /// address -- IP plus port -- to bind to
+    pub binding_addr: SocketAddr,
+}
+
+#[derive(Clone)]
+struct Counters {
+    bytes_received: Counter,
+    requests_received: Counter,
+    series_received: Counter,
+    samples_received: Counter,
+    decode_failure: Counter,
+}
+
+async fn srv(counters: Counters, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    counters.requests_received.increment(1);
+
+    let bytes = body::to_bytes(req.into_body()).await?;
+    counters.bytes_received.increment(bytes.len() as u64);
+
+    let request = snap::raw::Decoder::new()
+        .decompress_vec(&bytes)
+        .ok()
+        .and_then(|buf| WriteRequest::decode(buf.as_slice()).ok());
+    let mut response = Response::default();
+    if let Some(request) = request {
+        counters
+            .series_received
+            .increment(request.timeseries.len() as u64);
+        let samples: usize = request.timeseries.iter().map(|ts| ts.samples.len()).sum();
+        counters.samples_received.increment(samples as u64);
+        *response.status_mut() = StatusCode::NO_CONTENT;
+    } else {
+        counters.decode_failure.increment(1);
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+    }
+    Ok(response)
+}
+
+#[derive(Debug)]
+/// The Prometheus remote write blackhole.
+pub struct PrometheusRemoteWrite {
+    httpd_addr: SocketAddr,
+    concurrency_limit: usize,
+    shutdown: Shutdown,
+    metric_labels: Vec<(String, String)>,
+}
+
+impl PrometheusRemoteWrite {
+    /// Create a new [`PrometheusRemoteWrite`] server instance
+    #[must_use]
+    pub fn new(general: General, config: &Config, shutdown: Shutdown) -> Self {
+        let mut metric_labels = vec![
+            ("component".to_string(), "blackhole".to_string()),
+            (
+                "component_name".to_string(),
+                "prometheus_remote_write".to_string(),
+            ),
+        ];
+        if let Some(id) = general.id {
+            metric_labels.push(("id".to_string(), id));
+        }
+
+        Self {
+            httpd_addr: config.binding_addr,
+            concurrency_limit: config.concurrent_requests_max,
+            shutdown,
+            metric_labels,
+        }
+    }
+
+    /// Run [`PrometheusRemoteWrite`] to completion
+    ///
+    /// This function runs the HTTP server forever, unless a shutdown signal is
+    /// received or an unrecoverable error is encountered.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if binding to the configured address
+    /// fails or the server exits unexpectedly.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let counters = Counters {
+            bytes_received: register_counter!("bytes_received", &self.metric_labels),
+            requests_received: register_counter!("requests_received", &self.metric_labels),
+            series_received: register_counter!("series_received", &self.metric_labels),
+            samples_received: register_counter!("samples_received", &self.metric_labels),
+            decode_failure: register_counter!("decode_failure", &self.metric_labels),
+        };
+        let service = make_service_fn(|_: &AddrStream| {
+            let counters = counters.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request| {
+                    debug!("REQUEST: {:?}", request);
+                    srv(counters.clone(), request)
+                }))
+            }
+        });
+        let svc = ServiceBuilder::new()
+            .load_shed()
+            .concurrency_limit(self.concurrency_limit)
+            .timeout(Duration::from_secs(1))
+            .service(service);
+
+        let addr = AddrIncoming::bind(&self.httpd_addr)
+            .map(|mut addr| {
+                addr.set_keepalive(Some(Duration::from_secs(60)));
+                addr
+            })
+            .map_err(Error::Hyper)?;
+
+        let server = Server::builder(addr).serve(svc);
+        tokio::select! {
+            res = server => {
+                error!("server shutdown unexpectedly");
+                res.map_err(Error::Hyper)
+            }
+            _ = self.shutdown.recv() => {
+                info!("shutdown signal received");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Request, StatusCode};
+    use lading_payload::prometheus_remote_write::{Label, Sample, TimeSeries, WriteRequest};
+    use metrics::register_counter;
+    use prost::Message;
+
+    use super::{srv, Counters};
+    use crate::common::recorded::{self, install};
+
+    fn counters() -> Counters {
+        Counters {
+            bytes_received: register_counter!("bytes_received"),
+            requests_received: register_counter!("requests_received"),
+            series_received: register_counter!("series_received"),
+            samples_received: register_counter!("samples_received"),
+            decode_failure: register_counter!("decode_failure"),
+        }
+    }
+
+    fn series(name: &str, samples: i64) -> TimeSeries {
+        TimeSeries {
+            labels: vec![Label {
+                name: "__name__".to_string(),
+                value: name.to_string(),
+            }],
+            samples: (0..samples)
+                .map(|timestamp| Sample {
+                    value: 1.0,
+                    timestamp,
+                })
+                .collect(),
+        }
+    }
+
+    async fn post(body: Vec<u8>) -> StatusCode {
+        let request = Request::post("/").body(Body::from(body)).unwrap();
+        srv(counters(), request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn write_request_round_trip() {
+        install();
+        let request = WriteRequest {
+            timeseries: vec![series("up", 2), series("requests_total", 3)],
+        };
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .unwrap();
+        let body_bytes = body.len() as u64;
+
+        assert_eq!(post(body).await, StatusCode::NO_CONTENT);
+        assert_eq!(recorded::counter("requests_received", &[]), Some(1));
+        assert_eq!(recorded::counter("bytes_received", &[]), Some(body_bytes));
+        assert_eq!(recorded::counter("series_received", &[]), Some(2));
+        assert_eq!(recorded::counter("samples_received", &[]), Some(5));
+        assert_eq!(recorded::counter("decode_failure", &[]), Some(0));
+
+        // Not snappy compressed.
+        assert_eq!(post(request.encode_to_vec()).await, StatusCode::BAD_REQUEST);
+        // Snappy compressed but not a `WriteRequest`.
+        let garbage = snap::raw::Encoder::new().compress_vec(&[0xff; 16]).unwrap();
+        assert_eq!(post(garbage).await, StatusCode::BAD_REQUEST);
+
+        assert_eq!(recorded::counter("requests_received", &[]), Some(3));
+        assert_eq!(recorded::counter("series_received", &[]), Some(2));
+        assert_eq!(recorded::counter("decode_failure", &[]), Some(2));
+    }
+}
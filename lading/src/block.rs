@@ -13,12 +13,18 @@ use tokio::sync::mpsc::{self, error::SendError, Sender};
 pub(crate) enum SpinError {
     #[error(transparent)]
     Send(#[from] SendError<Block>),
+    #[error(transparent)]
+    Block(#[from] Error),
 }
 
-#[derive(Debug, thiserror::Error, Clone, Copy)]
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Chunk error: {0}")]
     Chunk(#[from] ChunkError),
+    #[error("Template error: {0}")]
+    Template(#[from] payload::template::Error),
+    #[error("JSON Schema error: {0}")]
+    JsonSchema(#[from] payload::json_schema::Error),
 }
 
 #[derive(Debug, thiserror::Error, Clone, Copy)]
@@ -75,6 +81,119 @@ pub(crate) enum Cache {
     },
 }
 
+/// Evaluate `$body` with `$pyld` bound to the serializer that `$payload`
+/// configures, constructed from `$rng`. Shared by the fixed and streaming
+/// caches so that both construct every payload alike.
+macro_rules! with_serializer {
+    ($payload:expr, $rng:ident, |$pyld:ident| $body:expr) => {
+        match $payload {
+            payload::Config::TraceAgent(enc) => {
+                let $pyld = match enc {
+                    payload::Encoding::Json => payload::TraceAgent::json(&mut $rng),
+                    payload::Encoding::MsgPack => payload::TraceAgent::msg_pack(&mut $rng),
+                };
+                $body
+            }
+            payload::Config::Syslog5424(config) => {
+                let $pyld = payload::Syslog5424::new(*config);
+                $body
+            }
+            payload::Config::Syslog3164(config) => {
+                let $pyld = payload::Syslog3164::new(*config);
+                $body
+            }
+            payload::Config::DogStatsD(config) => {
+                let $pyld = dogstatsd(config, &mut $rng);
+                $body
+            }
+            payload::Config::Prometheus(config) => {
+                let $pyld = payload::Prometheus::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::PrometheusRemoteWrite(config) => {
+                let $pyld = payload::PrometheusRemoteWrite::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::InfluxLineProtocol(config) => {
+                let $pyld = payload::InfluxLineProtocol::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::Graphite(config) => {
+                let $pyld = payload::Graphite::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::Gelf(config) => {
+                let $pyld = payload::Gelf::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::Cef(config) => {
+                let $pyld = payload::Cef::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::Leef(config) => {
+                let $pyld = payload::Leef::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::Cri(config) => {
+                let $pyld = payload::Cri::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::DockerJsonFile(config) => {
+                let $pyld = payload::DockerJsonFile::new(config, &mut $rng);
+                $body
+            }
+            payload::Config::Template(config) => {
+                let $pyld = payload::Template::new(config, &mut $rng).map_err(Error::from)?;
+                $body
+            }
+            payload::Config::Fluent => {
+                let $pyld = payload::Fluent::new(&mut $rng);
+                $body
+            }
+            payload::Config::SplunkHec { encoding } => {
+                let $pyld = payload::SplunkHec::new(*encoding);
+                $body
+            }
+            payload::Config::ApacheCommon => {
+                let $pyld = payload::ApacheCommon::new(&mut $rng);
+                $body
+            }
+            payload::Config::Ascii => {
+                let $pyld = payload::Ascii::new(&mut $rng);
+                $body
+            }
+            payload::Config::DatadogLog => {
+                let $pyld = payload::DatadogLog::new(&mut $rng);
+                $body
+            }
+            payload::Config::Json => {
+                let $pyld = payload::Json;
+                $body
+            }
+            payload::Config::JsonSchema(config) => {
+                let $pyld = payload::JsonSchema::new(config, &mut $rng).map_err(Error::from)?;
+                $body
+            }
+            payload::Config::Static { ref static_path } => {
+                let $pyld = payload::Static::new(static_path);
+                $body
+            }
+            payload::Config::OpentelemetryTraces => {
+                let $pyld = payload::OpentelemetryTraces::new(&mut $rng);
+                $body
+            }
+            payload::Config::OpentelemetryLogs => {
+                let $pyld = payload::OpentelemetryLogs::new(&mut $rng);
+                $body
+            }
+            payload::Config::OpentelemetryMetrics => {
+                let $pyld = payload::OpentelemetryMetrics::new(&mut $rng);
+                $body
+            }
+        }
+    };
+}
+
 impl Cache {
     /// Construct a streaming `Cache`.
     ///
@@ -84,8 +203,9 @@ impl Cache {
     ///
     /// # Errors
     ///
-    /// Function will return an error if `block_byte_sizes` is empty or if a member
-    /// of `block_byte_sizes` is large than `total_bytes`.
+    /// Function will return an error if `block_byte_sizes` is empty, if a member
+    /// of `block_byte_sizes` is large than `total_bytes` or if the payload
+    /// cannot be constructed from `payload`.
     pub(crate) fn stream(
         seed: [u8; 32],
         total_bytes: NonZeroUsize,
@@ -95,6 +215,17 @@ impl Cache {
         let mut rng = StdRng::from_seed(seed);
 
         let block_chunks = chunk_bytes(&mut rng, total_bytes, block_byte_sizes)?;
+        // The streaming thread has no way to report a payload that fails to
+        // construct, check those here.
+        match &payload {
+            payload::Config::Template(config) => {
+                payload::Template::new(config, &mut rng)?;
+            }
+            payload::Config::JsonSchema(config) => {
+                payload::JsonSchema::new(config, &mut rng)?;
+            }
+            _ => {}
+        }
         Ok(Self::Stream {
             seed,
             total_bytes: total_bytes.get(),
@@ -112,8 +243,9 @@ impl Cache {
     ///
     /// # Errors
     ///
-    /// Function will return an error if `block_byte_sizes` is empty or if a member
-    /// of `block_byte_sizes` is large than `total_bytes`.
+    /// Function will return an error if `block_byte_sizes` is empty, if a member
+    /// of `block_byte_sizes` is large than `total_bytes` or if the payload
+    /// cannot be constructed from `payload`.
     pub(crate) fn fixed<R>(
         mut rng: &mut R,
         total_bytes: NonZeroUsize,
@@ -124,104 +256,9 @@ impl Cache {
         R: Rng + ?Sized,
     {
         let block_chunks = chunk_bytes(&mut rng, total_bytes, block_byte_sizes)?;
-        let blocks = match payload {
-            payload::Config::TraceAgent(enc) => {
-                let ta = match enc {
-                    payload::Encoding::Json => payload::TraceAgent::json(&mut rng),
-                    payload::Encoding::MsgPack => payload::TraceAgent::msg_pack(&mut rng),
-                };
-
-                construct_block_cache_inner(&mut rng, &ta, &block_chunks)
-            }
-            payload::Config::Syslog5424 => construct_block_cache_inner(
-                &mut rng,
-                &payload::Syslog5424::default(),
-                &block_chunks,
-            ),
-            payload::Config::DogStatsD(payload::dogstatsd::Config {
-                contexts_minimum,
-                contexts_maximum,
-                name_length_minimum,
-                name_length_maximum,
-                tag_key_length_minimum,
-                tag_key_length_maximum,
-                tag_value_length_minimum,
-                tag_value_length_maximum,
-                tags_per_msg_minimum,
-                tags_per_msg_maximum,
-                // TODO -- Validate user input for multivalue_pack_probability.
-                multivalue_pack_probability,
-                multivalue_count_minimum,
-                multivalue_count_maximum,
-                kind_weights,
-                metric_weights,
-                value,
-            }) => {
-                let context_range = *contexts_minimum..*contexts_maximum;
-                let tags_per_msg_range = *tags_per_msg_minimum..*tags_per_msg_maximum;
-                let name_length_range = *name_length_minimum..*name_length_maximum;
-                let tag_key_length_range = *tag_key_length_minimum..*tag_key_length_maximum;
-                let tag_value_length_range = *tag_value_length_minimum..*tag_value_length_maximum;
-                let multivalue_count_range = *multivalue_count_minimum..*multivalue_count_maximum;
-
-                let serializer = payload::DogStatsD::new(
-                    context_range,
-                    name_length_range,
-                    tag_key_length_range,
-                    tag_value_length_range,
-                    tags_per_msg_range,
-                    multivalue_count_range,
-                    *multivalue_pack_probability,
-                    *kind_weights,
-                    *metric_weights,
-                    *value,
-                    &mut rng,
-                );
-
-                construct_block_cache_inner(&mut rng, &serializer, &block_chunks)
-            }
-            payload::Config::Fluent => {
-                let pyld = payload::Fluent::new(&mut rng);
-                construct_block_cache_inner(&mut rng, &pyld, &block_chunks)
-            }
-            payload::Config::SplunkHec { encoding } => construct_block_cache_inner(
-                &mut rng,
-                &payload::SplunkHec::new(*encoding),
-                &block_chunks,
-            ),
-            payload::Config::ApacheCommon => {
-                let pyld = payload::ApacheCommon::new(&mut rng);
-                construct_block_cache_inner(&mut rng, &pyld, &block_chunks)
-            }
-            payload::Config::Ascii => {
-                let pyld = payload::Ascii::new(&mut rng);
-                construct_block_cache_inner(&mut rng, &pyld, &block_chunks)
-            }
-            payload::Config::DatadogLog => {
-                let serializer = payload::DatadogLog::new(&mut rng);
-                construct_block_cache_inner(&mut rng, &serializer, &block_chunks)
-            }
-            payload::Config::Json => {
-                construct_block_cache_inner(&mut rng, &payload::Json, &block_chunks)
-            }
-            payload::Config::Static { ref static_path } => construct_block_cache_inner(
-                &mut rng,
-                &payload::Static::new(static_path),
-                &block_chunks,
-            ),
-            payload::Config::OpentelemetryTraces => {
-                let pyld = payload::OpentelemetryTraces::new(&mut rng);
-                construct_block_cache_inner(rng, &pyld, &block_chunks)
-            }
-            payload::Config::OpentelemetryLogs => {
-                let pyld = payload::OpentelemetryLogs::new(&mut rng);
-                construct_block_cache_inner(rng, &pyld, &block_chunks)
-            }
-            payload::Config::OpentelemetryMetrics => {
-                let pyld = payload::OpentelemetryMetrics::new(&mut rng);
-                construct_block_cache_inner(rng, &pyld, &block_chunks)
-            }
-        };
+        let blocks = with_serializer!(payload, rng, |pyld| {
+            construct_block_cache_inner(&mut rng, &pyld, &block_chunks)
+        });
         Ok(Self::Fixed { idx: 0, blocks })
     }
 
@@ -258,102 +295,9 @@ fn stream_inner(
 ) -> Result<(), SpinError> {
     let mut rng = StdRng::from_seed(seed);
 
-    match payload {
-        payload::Config::TraceAgent(enc) => {
-            let ta = match enc {
-                payload::Encoding::Json => payload::TraceAgent::json(&mut rng),
-                payload::Encoding::MsgPack => payload::TraceAgent::msg_pack(&mut rng),
-            };
-
-            stream_block_inner(&mut rng, total_bytes, &ta, block_chunks, &snd)
-        }
-        payload::Config::Syslog5424 => {
-            let pyld = payload::Syslog5424::default();
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::DogStatsD(payload::dogstatsd::Config {
-            contexts_minimum,
-            contexts_maximum,
-            name_length_minimum,
-            name_length_maximum,
-            tag_key_length_minimum,
-            tag_key_length_maximum,
-            tag_value_length_minimum,
-            tag_value_length_maximum,
-            tags_per_msg_minimum,
-            tags_per_msg_maximum,
-            // TODO -- Validate user input for multivalue_pack_probability.
-            multivalue_pack_probability,
-            multivalue_count_minimum,
-            multivalue_count_maximum,
-            kind_weights,
-            metric_weights,
-            value,
-        }) => {
-            let context_range = *contexts_minimum..*contexts_maximum;
-            let tags_per_msg_range = *tags_per_msg_minimum..*tags_per_msg_maximum;
-            let name_length_range = *name_length_minimum..*name_length_maximum;
-            let tag_key_length_range = *tag_key_length_minimum..*tag_key_length_maximum;
-            let tag_value_length_range = *tag_value_length_minimum..*tag_value_length_maximum;
-            let multivalue_count_range = *multivalue_count_minimum..*multivalue_count_maximum;
-
-            let pyld = payload::DogStatsD::new(
-                context_range,
-                name_length_range,
-                tag_key_length_range,
-                tag_value_length_range,
-                tags_per_msg_range,
-                multivalue_count_range,
-                *multivalue_pack_probability,
-                *kind_weights,
-                *metric_weights,
-                *value,
-                &mut rng,
-            );
-
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::Fluent => {
-            let pyld = payload::Fluent::new(&mut rng);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::SplunkHec { encoding } => {
-            let pyld = payload::SplunkHec::new(*encoding);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::ApacheCommon => {
-            let pyld = payload::ApacheCommon::new(&mut rng);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::Ascii => {
-            let pyld = payload::Ascii::new(&mut rng);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::DatadogLog => {
-            let pyld = payload::DatadogLog::new(&mut rng);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::Json => {
-            let pyld = payload::Json;
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::Static { ref static_path } => {
-            let pyld = payload::Static::new(static_path);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::OpentelemetryTraces => {
-            let pyld = payload::OpentelemetryTraces::new(&mut rng);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::OpentelemetryLogs => {
-            let pyld = payload::OpentelemetryLogs::new(&mut rng);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-        payload::Config::OpentelemetryMetrics => {
-            let pyld = payload::OpentelemetryMetrics::new(&mut rng);
-            stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
-        }
-    }
+    with_serializer!(payload, rng, |pyld| {
+        stream_block_inner(&mut rng, total_bytes, &pyld, block_chunks, &snd)
+    })
 }
 
 /// Construct a vec of block sizes that fit into `total_bytes`.
@@ -408,6 +352,52 @@ where
     Ok(chunks)
 }
 
+/// Construct the `DogStatsD` payload described by `config`.
+fn dogstatsd<R>(config: &payload::dogstatsd::Config, rng: &mut R) -> payload::DogStatsD
+where
+    R: Rng + ?Sized,
+{
+    let payload::dogstatsd::Config {
+        contexts_minimum,
+        contexts_maximum,
+        name_length_minimum,
+        name_length_maximum,
+        tag_key_length_minimum,
+        tag_key_length_maximum,
+        tag_value_length_minimum,
+        tag_value_length_maximum,
+        tags_per_msg_minimum,
+        tags_per_msg_maximum,
+        // TODO -- Validate user input for multivalue_pack_probability.
+        multivalue_pack_probability,
+        multivalue_count_minimum,
+        multivalue_count_maximum,
+        kind_weights,
+        metric_weights,
+        value,
+    } = config;
+    let context_range = *contexts_minimum..*contexts_maximum;
+    let tags_per_msg_range = *tags_per_msg_minimum..*tags_per_msg_maximum;
+    let name_length_range = *name_length_minimum..*name_length_maximum;
+    let tag_key_length_range = *tag_key_length_minimum..*tag_key_length_maximum;
+    let tag_value_length_range = *tag_value_length_minimum..*tag_value_length_maximum;
+    let multivalue_count_range = *multivalue_count_minimum..*multivalue_count_maximum;
+
+    payload::DogStatsD::new(
+        context_range,
+        name_length_range,
+        tag_key_length_range,
+        tag_value_length_range,
+        tags_per_msg_range,
+        multivalue_count_range,
+        *multivalue_pack_probability,
+        *kind_weights,
+        *metric_weights,
+        *value,
+        rng,
+    )
+}
+
 /// Construct a new block cache of form defined by `serializer`.
 ///
 /// A "block cache" is a pre-made vec of serialized arbitrary instances of the
@@ -521,12 +511,13 @@ where
 
 #[cfg(test)]
 mod test {
-    use std::num::NonZeroUsize;
+    use std::{collections::BTreeMap, num::NonZeroUsize, path::PathBuf};
 
+    use lading_payload as payload;
     use proptest::{collection, prelude::*};
     use rand::{rngs::SmallRng, SeedableRng};
 
-    use crate::block::{chunk_bytes, ChunkError, Error};
+    use crate::block::{chunk_bytes, Cache, ChunkError, Error};
 
     /// Construct our `block_bytes_sizes` vector and the `total_bytes` value. We are
     /// careful to never generate an empty vector nor a `total_bytes` that is less
@@ -577,4 +568,47 @@ mod test {
             }
         }
     }
+
+    // A payload that fails to construct is an error, whichever the cache
+    // method, never a panic.
+    #[test]
+    fn invalid_json_schema_is_error() {
+        let config = payload::Config::JsonSchema(payload::json_schema::Config {
+            schema_path: PathBuf::from("/nonexistent/schema.json"),
+            optional_property_probability: 0.5,
+            maximum_depth: 8,
+        });
+        let total_bytes = NonZeroUsize::new(4096).unwrap();
+        let block_sizes = [NonZeroUsize::new(1024).unwrap()];
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(matches!(
+            Cache::fixed(&mut rng, total_bytes, &block_sizes, &config),
+            Err(Error::JsonSchema(_))
+        ));
+        assert!(matches!(
+            Cache::stream([0; 32], total_bytes, &block_sizes, config),
+            Err(Error::JsonSchema(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_template_is_error() {
+        let config = payload::Config::Template(payload::template::Config {
+            format: String::from("{unknown}"),
+            fields: BTreeMap::new(),
+        });
+        let total_bytes = NonZeroUsize::new(4096).unwrap();
+        let block_sizes = [NonZeroUsize::new(1024).unwrap()];
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(matches!(
+            Cache::fixed(&mut rng, total_bytes, &block_sizes, &config),
+            Err(Error::Template(_))
+        ));
+        assert!(matches!(
+            Cache::stream([0; 32], total_bytes, &block_sizes, config),
+            Err(Error::Template(_))
+        ));
+    }
 }
@@ -82,6 +82,12 @@ impl CaptureManager {
         metrics::set_boxed_recorder(Box::new(recorder)).unwrap();
     }
 
+    /// The run ID recorded in every line of the capture file
+    #[must_use]
+    pub fn run_id(&self) -> Uuid {
+        self.run_id
+    }
+
     /// Add a global label to all metrics managed by [`CaptureManager`].
     pub fn add_global_label<K, V>(&mut self, key: K, value: V)
     where
@@ -60,6 +60,22 @@ pub(crate) fn stdio(behavior: &Behavior) -> Stdio {
     }
 }
 
+/// As [`stdio`] but appends to, rather than truncates, any existing log. Used
+/// when a sub-process is restarted so that output from previous runs is kept.
+pub(crate) fn stdio_append(behavior: &Behavior) -> Stdio {
+    match behavior {
+        Behavior::Quiet => Stdio::null(),
+        Behavior::Log(path) => {
+            let fp = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap();
+            Stdio::from(fp)
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct PeekableReceiver<T> {
     receiver: mpsc::Receiver<T>,
@@ -89,3 +105,53 @@ impl<T> PeekableReceiver<T> {
         self.buffer.as_ref()
     }
 }
+
+/// Inspection of the metrics recorded by tests
+#[cfg(test)]
+pub(crate) mod recorded {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+
+    /// Record metrics into a per-thread registry, every test runs on its own
+    /// thread.
+    pub(crate) fn install() {
+        // Only the first install succeeds, later ones share its registry.
+        let _ = DebuggingRecorder::per_thread().install();
+    }
+
+    /// The value recorded on this thread for `name` with exactly `labels`.
+    fn value(name: &str, labels: &[(&str, &str)]) -> Option<DebugValue> {
+        let mut labels: Vec<(String, String)> = labels
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect();
+        labels.sort();
+        Snapshotter::current_thread_snapshot()?
+            .into_vec()
+            .into_iter()
+            .find_map(|(key, _, _, value)| {
+                let key = key.key();
+                let mut recorded: Vec<(String, String)> = key
+                    .labels()
+                    .map(|l| (l.key().to_owned(), l.value().to_owned()))
+                    .collect();
+                recorded.sort();
+                (key.name() == name && recorded == labels).then_some(value)
+            })
+    }
+
+    /// The gauge recorded on this thread for `name` with exactly `labels`.
+    pub(crate) fn gauge(name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        match value(name, labels)? {
+            DebugValue::Gauge(value) => Some(value.into_inner()),
+            _ => None,
+        }
+    }
+
+    /// The counter recorded on this thread for `name` with exactly `labels`.
+    pub(crate) fn counter(name: &str, labels: &[(&str, &str)]) -> Option<u64> {
+        match value(name, labels)? {
+            DebugValue::Counter(value) => Some(value),
+            _ => None,
+        }
+    }
+}
@@ -19,7 +19,7 @@ pub struct Config {
     #[serde(with = "serde_yaml::with::singleton_map_recursive")]
     pub generator: Vec<generator::Config>,
     /// The observer that watches the target
-    #[serde(skip_deserializing)]
+    #[serde(default)]
     pub observer: observer::Config,
     /// The program being targetted by this rig
     #[serde(skip_deserializing)]
@@ -34,6 +34,9 @@ pub struct Config {
     pub target_metrics: Option<Vec<target_metrics::Config>>,
     /// The target inspector sub-program
     pub inspector: Option<inspector::Config>,
+    /// Further target inspector sub-programs, run alongside `inspector`
+    #[serde(default)]
+    pub inspectors: Vec<inspector::Config>,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -151,8 +154,94 @@ blackhole:
                 telemetry: crate::config::Telemetry::default(),
                 observer: observer::Config::default(),
                 inspector: Option::default(),
+                inspectors: Vec::default(),
                 target_metrics: Option::default(),
             },
         );
     }
+
+    #[test]
+    fn observer_config_deserializes() {
+        let contents = r#"
+observer:
+  sample_period_milliseconds: 250
+  metrics:
+    cpu: false
+  process_filter: "^agent$"
+"#;
+        let config: Config = serde_yaml::from_str(contents).unwrap();
+        assert_eq!(
+            config.observer,
+            observer::Config {
+                sample_period_milliseconds: std::num::NonZeroU64::new(250).unwrap(),
+                metrics: observer::Metrics {
+                    cpu: false,
+                    memory: true,
+                    process_count: true,
+                    host: true,
+                },
+                process_filter: Some(String::from("^agent$")),
+                cgroup: None,
+            }
+        );
+    }
+
+    #[test]
+    fn inspectors_deserialize() {
+        let contents = r#"
+inspector:
+  command: "/usr/bin/true"
+  arguments: []
+  environment_variables: {}
+  output: {}
+inspectors:
+  - id: "perf"
+    command: "/usr/bin/perf"
+    arguments: ["record"]
+    environment_variables: {}
+    output:
+      stdout: "/tmp/perf.stdout"
+    schedule:
+      start_offset_seconds: 30
+      duration_seconds: 10
+      period_seconds: 60
+  - snapshot:
+      directory: "/tmp/snapshots"
+    schedule:
+      period_seconds: 10
+"#;
+        let config: Config = serde_yaml::from_str(contents).unwrap();
+        assert_eq!(
+            config.inspector.unwrap().schedule,
+            inspector::Schedule::default()
+        );
+        assert_eq!(
+            config.inspectors[0],
+            inspector::Config {
+                id: Some(String::from("perf")),
+                mode: inspector::Mode::Program(inspector::Program {
+                    command: PathBuf::from("/usr/bin/perf"),
+                    arguments: vec![String::from("record")],
+                    environment_variables: FxHashMap::default(),
+                    output: crate::common::Output {
+                        stderr: crate::common::Behavior::Quiet,
+                        stdout: crate::common::Behavior::Log(PathBuf::from("/tmp/perf.stdout")),
+                    },
+                }),
+                schedule: inspector::Schedule {
+                    start_offset_seconds: 30,
+                    duration_seconds: std::num::NonZeroU64::new(10),
+                    period_seconds: std::num::NonZeroU64::new(60),
+                },
+            }
+        );
+        assert_eq!(
+            config.inspectors[1].mode,
+            inspector::Mode::Snapshot {
+                snapshot: inspector::snapshot::Config {
+                    directory: PathBuf::from("/tmp/snapshots"),
+                }
+            }
+        );
+    }
 }
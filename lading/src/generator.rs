@@ -19,7 +19,10 @@ pub mod file_gen;
 pub mod file_tree;
 pub mod grpc;
 pub mod http;
+pub mod http_pull;
+pub mod kubernetes_logs;
 pub mod process_tree;
+pub mod prometheus_remote_write;
 pub mod splunk_hec;
 pub mod tcp;
 pub mod udp;
@@ -38,6 +41,9 @@ pub enum Error {
     /// See [`crate::generator::http::Error`] for details.
     #[error(transparent)]
     Http(#[from] http::Error),
+    /// See [`crate::generator::http_pull::Error`] for details.
+    #[error(transparent)]
+    HttpPull(#[from] http_pull::Error),
     /// See [`crate::generator::splunk_hec::Error`] for details.
     #[error(transparent)]
     SplunkHec(#[from] splunk_hec::Error),
@@ -47,6 +53,9 @@ pub enum Error {
     /// See [`crate::generator::file_tree::Error`] for details.
     #[error(transparent)]
     FileTree(#[from] file_tree::Error),
+    /// See [`crate::generator::kubernetes_logs::Error`] for details.
+    #[error(transparent)]
+    KubernetesLogs(#[from] kubernetes_logs::Error),
     /// See [`crate::generator::grpc::Error`] for details.
     #[error(transparent)]
     Grpc(#[from] grpc::Error),
@@ -59,6 +68,9 @@ pub enum Error {
     /// See [`crate::generator::process_tree::Error`] for details.
     #[error(transparent)]
     ProcessTree(#[from] process_tree::Error),
+    /// See [`crate::generator::prometheus_remote_write::Error`] for details.
+    #[error(transparent)]
+    PrometheusRemoteWrite(#[from] prometheus_remote_write::Error),
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -91,12 +103,16 @@ pub enum Inner {
     Udp(udp::Config),
     /// See [`crate::generator::http::Config`] for details.
     Http(http::Config),
+    /// See [`crate::generator::http_pull::Config`] for details.
+    HttpPull(http_pull::Config),
     /// See [`crate::generator::splunk_hec::Config`] for details.
     SplunkHec(splunk_hec::Config),
     /// See [`crate::generator::file_gen::Config`] for details.
     FileGen(file_gen::Config),
     /// See [`crate::generator::file_tree::Config`] for details.
     FileTree(file_tree::Config),
+    /// See [`crate::generator::kubernetes_logs::Config`] for details.
+    KubernetesLogs(kubernetes_logs::Config),
     /// See [`crate::generator::grpc::Config`] for details.
     Grpc(grpc::Config),
     /// See [`crate::generator::unix_stream::Config`] for details.
@@ -105,6 +121,8 @@ pub enum Inner {
     UnixDatagram(unix_datagram::Config),
     /// See [`crate::generator::process_tree::Config`] for details.
     ProcessTree(process_tree::Config),
+    /// See [`crate::generator::prometheus_remote_write::Config`] for details.
+    PrometheusRemoteWrite(prometheus_remote_write::Config),
 }
 
 #[derive(Debug)]
@@ -119,12 +137,16 @@ pub enum Server {
     Udp(udp::Udp),
     /// See [`crate::generator::http::Http`] for details.
     Http(http::Http),
+    /// See [`crate::generator::http_pull::HttpPull`] for details.
+    HttpPull(http_pull::HttpPull),
     /// See [`crate::generator::splunk_hec::SplunkHec`] for details.
     SplunkHec(splunk_hec::SplunkHec),
     /// See [`crate::generator::file_gen::FileGen`] for details.
     FileGen(file_gen::FileGen),
     /// See [`crate::generator::file_tree::FileTree`] for details.
     FileTree(file_tree::FileTree),
+    /// See [`crate::generator::kubernetes_logs::KubernetesLogs`] for details.
+    KubernetesLogs(kubernetes_logs::KubernetesLogs),
     /// See [`crate::generator::grpc::Grpc`] for details.
     Grpc(grpc::Grpc),
     /// See [`crate::generator::unix_stream::UnixStream`] for details.
@@ -133,6 +155,8 @@ pub enum Server {
     UnixDatagram(unix_datagram::UnixDatagram),
     /// See [`crate::generator::process_tree::ProcessTree`] for details.
     ProcessTree(process_tree::ProcessTree),
+    /// See [`crate::generator::prometheus_remote_write::PrometheusRemoteWrite`] for details.
+    PrometheusRemoteWrite(prometheus_remote_write::PrometheusRemoteWrite),
 }
 
 impl Server {
@@ -150,6 +174,9 @@ impl Server {
             Inner::Tcp(conf) => Self::Tcp(tcp::Tcp::new(config.general, &conf, shutdown)?),
             Inner::Udp(conf) => Self::Udp(udp::Udp::new(config.general, &conf, shutdown)?),
             Inner::Http(conf) => Self::Http(http::Http::new(config.general, conf, shutdown)?),
+            Inner::HttpPull(conf) => {
+                Self::HttpPull(http_pull::HttpPull::new(config.general, conf, shutdown)?)
+            }
             Inner::SplunkHec(conf) => {
                 Self::SplunkHec(splunk_hec::SplunkHec::new(config.general, conf, shutdown)?)
             }
@@ -157,6 +184,9 @@ impl Server {
                 Self::FileGen(file_gen::FileGen::new(config.general, conf, shutdown)?)
             }
             Inner::FileTree(conf) => Self::FileTree(file_tree::FileTree::new(&conf, shutdown)?),
+            Inner::KubernetesLogs(conf) => Self::KubernetesLogs(
+                kubernetes_logs::KubernetesLogs::new(config.general, conf, shutdown)?,
+            ),
             Inner::Grpc(conf) => Self::Grpc(grpc::Grpc::new(config.general, conf, shutdown)?),
             Inner::UnixStream(conf) => Self::UnixStream(unix_stream::UnixStream::new(
                 config.general,
@@ -171,6 +201,13 @@ impl Server {
             Inner::ProcessTree(conf) => {
                 Self::ProcessTree(process_tree::ProcessTree::new(&conf, shutdown)?)
             }
+            Inner::PrometheusRemoteWrite(conf) => {
+                Self::PrometheusRemoteWrite(prometheus_remote_write::PrometheusRemoteWrite::new(
+                    config.general,
+                    conf,
+                    shutdown,
+                )?)
+            }
         };
         Ok(srv)
     }
@@ -195,13 +232,16 @@ impl Server {
             Server::Tcp(inner) => inner.spin().await?,
             Server::Udp(inner) => inner.spin().await?,
             Server::Http(inner) => inner.spin().await?,
+            Server::HttpPull(inner) => inner.spin().await?,
             Server::SplunkHec(inner) => inner.spin().await?,
             Server::FileGen(inner) => inner.spin().await?,
             Server::FileTree(inner) => inner.spin().await?,
+            Server::KubernetesLogs(inner) => inner.spin().await?,
             Server::Grpc(inner) => inner.spin().await?,
             Server::UnixStream(inner) => inner.spin().await?,
             Server::UnixDatagram(inner) => inner.spin().await?,
             Server::ProcessTree(inner) => inner.spin().await?,
+            Server::PrometheusRemoteWrite(inner) => inner.spin().await?,
         };
 
         Ok(())
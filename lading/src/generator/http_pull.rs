@@ -0,0 +1,354 @@
+//! The pull-mode HTTP generator.
+//!
+//! Unlike the other generators this one does not push load into the target.
+//! Instead it binds an HTTP endpoint and serves the next block from its cache
+//! on every GET, for targets that scrape or poll. Because blocks rotate the
+//! content served changes between requests.
+//!
+//! ## Metrics
+//!
+//! `requests_received`: Total requests received
+//! `requests_served`: Requests answered with a block
+//! `bytes_served`: Total bytes of block served
+//!
+
+use std::{net::SocketAddr, num::NonZeroUsize, sync::Arc, thread, time::Duration};
+
+use byte_unit::{Byte, ByteUnit};
+use hyper::{
+    header,
+    server::conn::{AddrIncoming, AddrStream},
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Method, Request, Response, Server, StatusCode,
+};
+use metrics::{register_counter, Counter};
+use rand::{prelude::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::{mpsc, Mutex};
+use tower::ServiceBuilder;
+use tracing::{error, info};
+
+use crate::{
+    block::{self, Block},
+    signals::Shutdown,
+};
+
+use super::General;
+
+fn default_concurrent_requests_max() -> usize {
+    100
+}
+
+fn default_headers() -> HeaderMap {
+    let mut map = HeaderMap::new();
+    map.insert(
+        header::CONTENT_TYPE,
+        "text/plain; version=0.0.4".parse().unwrap(),
+    );
+    map
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// address -- IP plus port -- to bind to
+    pub binding_addr: SocketAddr,
+    /// The payload generator to use for this target
+    pub variant: lading_payload::Config,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// Whether to use a fixed or streaming block cache
+    #[serde(default = "crate::block::default_cache_method")]
+    pub block_cache_method: block::CacheMethod,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// Headers to include in the response; default is the Prometheus text
+    /// exposition content type
+    #[serde(with = "http_serde::header_map", default = "default_headers")]
+    pub headers: HeaderMap,
+    /// number of concurrent HTTP connections to allow
+    #[serde(default = "default_concurrent_requests_max")]
+    pub concurrent_requests_max: usize,
+}
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`HttpPull`].
+pub enum Error {
+    /// Wrapper around [`std::io::Error`].
+    #[error("Io error: {0}")]
+    Io(#[from] ::std::io::Error),
+    /// Creation of payload blocks failed.
+    #[error("Block creation error: {0}")]
+    Block(#[from] block::Error),
+    /// Wrapper around [`hyper::Error`].
+    #[error("Hyper error: {0}")]
+    Hyper(#[from] hyper::Error),
+}
+
+#[derive(Debug)]
+/// The pull-mode HTTP generator.
+///
+/// This generator is responsible for serving blocks to the target when it
+/// makes a GET request. Other methods are refused.
+pub struct HttpPull {
+    httpd_addr: SocketAddr,
+    headers: HeaderMap,
+    concurrency_limit: usize,
+    block_cache: block::Cache,
+    metric_labels: Vec<(String, String)>,
+    shutdown: Shutdown,
+}
+
+impl HttpPull {
+    /// Create a new [`HttpPull`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be constructed.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(general: General, config: Config, shutdown: Shutdown) -> Result<Self, Error> {
+        let mut rng = StdRng::from_seed(config.seed);
+        let block_sizes: Vec<NonZeroUsize> = config
+            .block_sizes
+            .unwrap_or_else(|| {
+                vec![
+                    Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                    Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                    Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                    Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                ]
+            })
+            .iter()
+            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+            .collect();
+        let mut labels = vec![
+            ("component".to_string(), "generator".to_string()),
+            ("component_name".to_string(), "http_pull".to_string()),
+        ];
+        if let Some(id) = general.id {
+            labels.push(("id".to_string(), id));
+        }
+
+        let total_bytes =
+            NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+                .expect("bytes must be non-zero");
+        let block_cache = match config.block_cache_method {
+            block::CacheMethod::Streaming => {
+                block::Cache::stream(config.seed, total_bytes, &block_sizes, config.variant)?
+            }
+            block::CacheMethod::Fixed => {
+                block::Cache::fixed(&mut rng, total_bytes, &block_sizes, &config.variant)?
+            }
+        };
+
+        Ok(Self {
+            httpd_addr: config.binding_addr,
+            headers: config.headers,
+            concurrency_limit: config.concurrent_requests_max,
+            block_cache,
+            metric_labels: labels,
+            shutdown,
+        })
+    }
+
+    /// Run [`HttpPull`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if binding to the configured address
+    /// fails or the server exits unexpectedly.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let requests_received = register_counter!("requests_received", &self.metric_labels);
+        let requests_served = register_counter!("requests_served", &self.metric_labels);
+        let bytes_served = register_counter!("bytes_served", &self.metric_labels);
+
+        // Move the block_cache into an OS thread, exposing a channel between it
+        // and the request handlers. Every request takes the next block.
+        let block_cache = self.block_cache;
+        let (snd, rcv) = mpsc::channel(1024);
+        let rcv: Arc<Mutex<mpsc::Receiver<Block>>> = Arc::new(Mutex::new(rcv));
+        thread::Builder::new().spawn(|| block_cache.spin(snd))?;
+
+        let headers = self.headers;
+        let service = make_service_fn(|_: &AddrStream| {
+            let rcv = Arc::clone(&rcv);
+            let headers = headers.clone();
+            let requests_received = requests_received.clone();
+            let requests_served = requests_served.clone();
+            let bytes_served = bytes_served.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request| {
+                    srv(
+                        request,
+                        Arc::clone(&rcv),
+                        headers.clone(),
+                        requests_received.clone(),
+                        requests_served.clone(),
+                        bytes_served.clone(),
+                    )
+                }))
+            }
+        });
+        let svc = ServiceBuilder::new()
+            .load_shed()
+            .concurrency_limit(self.concurrency_limit)
+            .timeout(Duration::from_secs(1))
+            .service(service);
+
+        let addr = AddrIncoming::bind(&self.httpd_addr)
+            .map(|mut addr| {
+                addr.set_keepalive(Some(Duration::from_secs(60)));
+                addr
+            })
+            .map_err(Error::Hyper)?;
+
+        let server = Server::builder(addr).serve(svc);
+        tokio::select! {
+            res = server => {
+                error!("server shutdown unexpectedly");
+                res.map_err(Error::Hyper)
+            }
+            _ = self.shutdown.recv() => {
+                info!("shutdown signal received");
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn srv(
+    req: Request<Body>,
+    rcv: Arc<Mutex<mpsc::Receiver<Block>>>,
+    headers: HeaderMap,
+    requests_received: Counter,
+    requests_served: Counter,
+    bytes_served: Counter,
+) -> Result<Response<Body>, hyper::Error> {
+    requests_received.increment(1);
+
+    if req.method() != Method::GET {
+        let mut response = Response::default();
+        *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        return Ok(response);
+    }
+
+    let blk = rcv.lock().await.recv().await;
+    let Some(blk) = blk else {
+        // The block cache thread has exited, there is nothing left to serve.
+        let mut response = Response::default();
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        return Ok(response);
+    };
+
+    requests_served.increment(1);
+    bytes_served.increment(blk.bytes.len() as u64);
+
+    let mut response = Response::new(Body::from(blk.bytes));
+    *response.headers_mut() = headers;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use byte_unit::{Byte, ByteUnit};
+    use hyper::{
+        body::{self, Bytes},
+        Body, Client, Method, Request, StatusCode,
+    };
+
+    use super::{Config, HttpPull};
+    use crate::{
+        block,
+        common::recorded::{self, install},
+        generator::General,
+        signals::Shutdown,
+    };
+
+    const LABELS: [(&str, &str); 2] = [("component", "generator"), ("component_name", "http_pull")];
+
+    #[tokio::test]
+    async fn serves_next_block_per_get() {
+        install();
+        // Reserve a free port: the generator binds its configured address
+        // itself.
+        let binding_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = Config {
+            seed: [7; 32],
+            binding_addr,
+            variant: lading_payload::Config::Ascii,
+            maximum_prebuild_cache_size_bytes: Byte::from_unit(256.0, ByteUnit::KiB).unwrap(),
+            block_cache_method: block::CacheMethod::Fixed,
+            block_sizes: Some(vec![Byte::from_unit(32.0, ByteUnit::KiB).unwrap()]),
+            headers: super::default_headers(),
+            concurrent_requests_max: 4,
+        };
+        let shutdown = Shutdown::new();
+        let pull = HttpPull::new(General { id: None }, config, shutdown.clone()).unwrap();
+        let block::Cache::Fixed { blocks, .. } = &pull.block_cache else {
+            panic!("expected a fixed block cache");
+        };
+        assert!(blocks.len() > 2);
+        let expected: Vec<_> = blocks[..2].iter().map(|blk| blk.bytes.clone()).collect();
+        assert_ne!(expected[0], expected[1]);
+        let server = tokio::spawn(pull.spin());
+
+        let client = Client::new();
+        let uri = format!("http://{binding_addr}/");
+        let request = |method| {
+            Request::builder()
+                .method(method)
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+        // The server binds once spawned, retry until it accepts.
+        let mut response = loop {
+            match client.request(request(Method::GET)).await {
+                Ok(response) => break response,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        for blk in &expected {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()["content-type"],
+                "text/plain; version=0.0.4"
+            );
+            assert_eq!(&body::to_bytes(response.body_mut()).await.unwrap(), blk);
+            response = client.request(request(Method::GET)).await.unwrap();
+        }
+
+        let refused = client.request(request(Method::POST)).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let last = body::to_bytes(response.body_mut()).await.unwrap();
+        let total_bytes = expected
+            .iter()
+            .chain([&last])
+            .map(Bytes::len)
+            .sum::<usize>();
+        assert_eq!(recorded::counter("requests_received", &LABELS), Some(4));
+        assert_eq!(recorded::counter("requests_served", &LABELS), Some(3));
+        assert_eq!(
+            recorded::counter("bytes_served", &LABELS),
+            Some(total_bytes as u64)
+        );
+
+        shutdown.signal().unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
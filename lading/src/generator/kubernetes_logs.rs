@@ -0,0 +1,527 @@
+//! The Kubernetes pod log generator.
+//!
+//! Like the file generator this generator does not "connect" to the target but
+//! instead, without coordination, writes files on disk. Files are laid out the
+//! way the kubelet lays out container logs:
+//!
+//! ```text
+//! <root>/pods/<namespace>_<pod>_<uid>/<container>/0.log
+//! <root>/containers/<pod>_<namespace>_<container>-<container id>.log -> ../pods/.../0.log
+//! ```
+//!
+//! Pods are deleted and replaced at a configured churn rate, removing their
+//! directory and symlinks and creating new ones. When a container's `0.log`
+//! grows past `maximum_bytes_per_file` it is renamed to `1.log`, replacing any
+//! previous `1.log`, and a fresh `0.log` is created in its place.
+//!
+//! ## Metrics
+//!
+//! `bytes_written`: Total bytes written
+//! `bytes_per_second`: Configured rate to send data
+//! `pods_created`: Total pods created
+//! `pods_deleted`: Total pods deleted
+//! `files_rotated`: Total log files rotated
+//!
+//! Additional metrics may be emitted by this generator's [throttle].
+//!
+
+use std::{
+    num::{NonZeroU16, NonZeroU32, NonZeroU8, NonZeroUsize},
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use lading_throttle::Throttle;
+use metrics::{gauge, register_counter};
+use rand::{prelude::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::{
+    fs,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+use tracing::info;
+
+use crate::{
+    block::{self, Block},
+    common::PeekableReceiver,
+    signals::Shutdown,
+};
+
+use super::General;
+
+/// Characters of namespace, application and container names.
+const NAME_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+/// Characters of the random suffixes the Kubernetes API server appends to
+/// generated pod names.
+const SUFFIX_ALPHABET: &[u8] = b"bcdfghjklmnpqrstvwxz2456789";
+/// Characters of container IDs.
+const HEX_ALPHABET: &[u8] = b"0123456789abcdef";
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`KubernetesLogs`].
+pub enum Error {
+    /// Wrapper around [`std::io::Error`].
+    #[error("Io error: {0}")]
+    Io(#[from] ::std::io::Error),
+    /// Creation of payload blocks failed.
+    #[error("Block creation error: {0}")]
+    Block(#[from] block::Error),
+}
+
+fn default_root() -> PathBuf {
+    PathBuf::from("/var/log")
+}
+
+fn default_namespaces() -> NonZeroU16 {
+    NonZeroU16::new(4).unwrap()
+}
+
+fn default_pods() -> NonZeroU16 {
+    NonZeroU16::new(16).unwrap()
+}
+
+fn default_containers_per_pod() -> NonZeroU8 {
+    NonZeroU8::new(2).unwrap()
+}
+
+fn default_pod_churn_per_minute() -> u32 {
+    6
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+/// Configuration of [`KubernetesLogs`]
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The directory holding `pods` and `containers`, by default `/var/log`
+    #[serde(default = "default_root")]
+    pub root: PathBuf,
+    /// Number of distinct namespaces pods are placed in
+    #[serde(default = "default_namespaces")]
+    pub namespaces: NonZeroU16,
+    /// Number of pods alive at any one time
+    #[serde(default = "default_pods")]
+    pub pods: NonZeroU16,
+    /// Number of containers in each pod
+    #[serde(default = "default_containers_per_pod")]
+    pub containers_per_pod: NonZeroU8,
+    /// Number of pods deleted and replaced by a new pod each minute. Zero
+    /// disables churn.
+    #[serde(default = "default_pod_churn_per_minute")]
+    pub pod_churn_per_minute: u32,
+    /// Sets the [`crate::payload::Config`] of the container logs.
+    pub variant: lading_payload::Config,
+    /// Sets the **soft** maximum bytes to be written into a container's
+    /// `0.log` before it is rotated to `1.log`.
+    pub maximum_bytes_per_file: Byte,
+    /// Defines the number of bytes written per second across all containers.
+    pub bytes_per_second: Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// Defines the maximum internal cache of this log target. The generator
+    /// will pre-build its outputs up to the byte capacity specified here.
+    pub maximum_prebuild_cache_size_bytes: Byte,
+    /// Whether to use a fixed or streaming block cache
+    #[serde(default = "crate::block::default_cache_method")]
+    pub block_cache_method: block::CacheMethod,
+    /// The load throttle configuration
+    #[serde(default)]
+    pub throttle: lading_throttle::Config,
+}
+
+#[derive(Debug)]
+struct Container {
+    symlink: PathBuf,
+    directory: PathBuf,
+    fp: BufWriter<fs::File>,
+    bytes_written: u64,
+}
+
+#[derive(Debug)]
+struct Pod {
+    directory: PathBuf,
+    containers: Vec<Container>,
+}
+
+/// Where and how pods are created.
+#[derive(Debug)]
+struct Layout {
+    pods_root: PathBuf,
+    containers_root: PathBuf,
+    namespaces: Vec<String>,
+    containers_per_pod: usize,
+    rng: StdRng,
+}
+
+#[derive(Debug)]
+/// The Kubernetes pod log generator.
+///
+/// This generator writes container logs to disk in the kubelet's layout,
+/// rotating them and churning pods as it goes. It does this without
+/// coordination to the target.
+pub struct KubernetesLogs {
+    layout: Layout,
+    pods: usize,
+    churn_period: Option<Duration>,
+    maximum_bytes_per_file: u64,
+    throttle: Throttle,
+    block_cache: block::Cache,
+    metric_labels: Vec<(String, String)>,
+    shutdown: Shutdown,
+}
+
+impl KubernetesLogs {
+    /// Create a new [`KubernetesLogs`]
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the payload blocks cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if `bytes_per_second` or
+    /// `maximum_prebuild_cache_size_bytes` is zero.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(general: General, config: Config, shutdown: Shutdown) -> Result<Self, Error> {
+        let mut rng = StdRng::from_seed(config.seed);
+        let block_sizes: Vec<NonZeroUsize> = config
+            .block_sizes
+            .unwrap_or_else(|| {
+                vec![
+                    Byte::from_unit(1_f64, ByteUnit::KB).unwrap(),
+                    Byte::from_unit(4_f64, ByteUnit::KB).unwrap(),
+                    Byte::from_unit(16_f64, ByteUnit::KB).unwrap(),
+                    Byte::from_unit(64_f64, ByteUnit::KB).unwrap(),
+                    Byte::from_unit(256_f64, ByteUnit::KB).unwrap(),
+                ]
+            })
+            .iter()
+            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+            .collect();
+        let mut metric_labels = vec![
+            ("component".to_string(), "generator".to_string()),
+            ("component_name".to_string(), "kubernetes_logs".to_string()),
+        ];
+        if let Some(id) = general.id {
+            metric_labels.push(("id".to_string(), id));
+        }
+
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        gauge!(
+            "bytes_per_second",
+            f64::from(bytes_per_second.get()),
+            &metric_labels
+        );
+        let throttle = Throttle::new_with_config(config.throttle, bytes_per_second);
+
+        let total_bytes =
+            NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+                .expect("bytes must be non-zero");
+        let block_cache = match config.block_cache_method {
+            block::CacheMethod::Streaming => {
+                block::Cache::stream(config.seed, total_bytes, &block_sizes, config.variant)?
+            }
+            block::CacheMethod::Fixed => {
+                block::Cache::fixed(&mut rng, total_bytes, &block_sizes, &config.variant)?
+            }
+        };
+
+        let mut namespaces: Vec<String> = Vec::with_capacity(config.namespaces.get().into());
+        while namespaces.len() < usize::from(config.namespaces.get()) {
+            let namespace = random_name(&mut rng, NAME_ALPHABET, 4..=16);
+            if !namespaces.contains(&namespace) {
+                namespaces.push(namespace);
+            }
+        }
+
+        let churn_period = (config.pod_churn_per_minute > 0)
+            .then(|| Duration::from_secs(60) / config.pod_churn_per_minute);
+
+        let layout = Layout {
+            pods_root: config.root.join("pods"),
+            containers_root: config.root.join("containers"),
+            namespaces,
+            containers_per_pod: usize::from(config.containers_per_pod.get()),
+            rng,
+        };
+
+        Ok(Self {
+            layout,
+            pods: usize::from(config.pods.get()),
+            churn_period,
+            maximum_bytes_per_file: config.maximum_bytes_per_file.get_bytes() as u64,
+            throttle,
+            block_cache,
+            metric_labels,
+            shutdown,
+        })
+    }
+
+    /// Run [`KubernetesLogs`] to completion or until a shutdown signal is
+    /// received.
+    ///
+    /// In this loop blocks are written to the `0.log` of randomly chosen
+    /// containers, rotating the files as they fill, while pods are deleted and
+    /// created at the configured churn rate.
+    ///
+    /// # Errors
+    ///
+    /// This function will terminate with an error if file permissions are not
+    /// correct, if a file cannot be written to etc. Any error from
+    /// `std::io::Error` is possible.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if the block cache thread exits.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        fs::create_dir_all(&self.layout.pods_root).await?;
+        fs::create_dir_all(&self.layout.containers_root).await?;
+
+        let bytes_written = register_counter!("bytes_written", &self.metric_labels);
+        let pods_created = register_counter!("pods_created", &self.metric_labels);
+        let pods_deleted = register_counter!("pods_deleted", &self.metric_labels);
+        let files_rotated = register_counter!("files_rotated", &self.metric_labels);
+
+        let mut pods: Vec<Pod> = Vec::with_capacity(self.pods);
+        for _ in 0..self.pods {
+            pods.push(self.layout.create_pod().await?);
+            pods_created.increment(1);
+        }
+
+        // Move the block_cache into an OS thread, exposing a channel between it
+        // and this async context.
+        let block_cache = self.block_cache;
+        let (snd, rcv) = mpsc::channel(1024);
+        let mut rcv: PeekableReceiver<Block> = PeekableReceiver::new(rcv);
+        thread::Builder::new().spawn(|| block_cache.spin(snd))?;
+
+        let churn_enabled = self.churn_period.is_some();
+        let mut churn = tokio::time::interval(self.churn_period.unwrap_or(Duration::from_secs(60)));
+        // The first tick of an interval is immediate, pods are only just
+        // created.
+        churn.tick().await;
+
+        loop {
+            let blk = rcv.peek().await.unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = self.throttle.wait_for(total_bytes) => {
+                    let blk = rcv.next().await.unwrap(); // actually advance through the blocks
+                    let total_bytes = u64::from(total_bytes.get());
+
+                    let pod = pods.choose_mut(&mut self.layout.rng).unwrap();
+                    let container = pod.containers.choose_mut(&mut self.layout.rng).unwrap();
+                    container.fp.write_all(&blk.bytes).await?;
+                    container.bytes_written += total_bytes;
+                    bytes_written.increment(total_bytes);
+
+                    if container.bytes_written > self.maximum_bytes_per_file {
+                        container.rotate().await?;
+                        files_rotated.increment(1);
+                    }
+                }
+                _ = churn.tick(), if churn_enabled => {
+                    let idx = self.layout.rng.gen_range(0..pods.len());
+                    let pod = pods.swap_remove(idx);
+                    pod.delete().await?;
+                    pods_deleted.increment(1);
+                    pods.push(self.layout.create_pod().await?);
+                    pods_created.increment(1);
+                }
+                _ = self.shutdown.recv() => {
+                    for pod in &mut pods {
+                        for container in &mut pod.containers {
+                            container.fp.flush().await?;
+                        }
+                    }
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+impl Layout {
+    /// Create the directories, log files and symlinks of a new pod.
+    async fn create_pod(&mut self) -> Result<Pod, Error> {
+        let namespace = self.namespaces.choose(&mut self.rng).unwrap().clone();
+        let application = random_name(&mut self.rng, NAME_ALPHABET, 4..=16);
+        let pod_name = format!(
+            "{application}-{}-{}",
+            random_name(&mut self.rng, SUFFIX_ALPHABET, 10..=10),
+            random_name(&mut self.rng, SUFFIX_ALPHABET, 5..=5)
+        );
+        let uid = uuid::Builder::from_random_bytes(self.rng.gen()).into_uuid();
+
+        let pod_directory = format!("{namespace}_{pod_name}_{uid}");
+        let directory = self.pods_root.join(&pod_directory);
+        fs::create_dir_all(&directory).await?;
+
+        let mut containers = Vec::with_capacity(self.containers_per_pod);
+        let mut container_names: Vec<String> = Vec::with_capacity(self.containers_per_pod);
+        while container_names.len() < self.containers_per_pod {
+            let name = random_name(&mut self.rng, NAME_ALPHABET, 4..=16);
+            if !container_names.contains(&name) {
+                container_names.push(name);
+            }
+        }
+        for name in container_names {
+            let container_directory = directory.join(&name);
+            fs::create_dir(&container_directory).await?;
+            let log = container_directory.join("0.log");
+            let fp = BufWriter::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .truncate(true)
+                    .write(true)
+                    .open(&log)
+                    .await?,
+            );
+            let container_id = random_name(&mut self.rng, HEX_ALPHABET, 64..=64);
+            let symlink = self
+                .containers_root
+                .join(format!("{pod_name}_{namespace}_{name}-{container_id}.log"));
+            // The link is relative so that it resolves whether `root` is
+            // absolute or not.
+            let target: PathBuf = ["..", "pods", &pod_directory, &name, "0.log"]
+                .iter()
+                .collect();
+            fs::symlink(&target, &symlink).await?;
+            containers.push(Container {
+                symlink,
+                directory: container_directory,
+                fp,
+                bytes_written: 0,
+            });
+        }
+
+        Ok(Pod {
+            directory,
+            containers,
+        })
+    }
+}
+
+impl Container {
+    /// Rotate `0.log` to `1.log` and open a fresh `0.log`. The symlink points
+    /// at `0.log` by name and so follows to the new file.
+    async fn rotate(&mut self) -> Result<(), Error> {
+        self.fp.flush().await?;
+        let log = self.directory.join("0.log");
+        fs::rename(&log, self.directory.join("1.log")).await?;
+        self.fp = BufWriter::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(&log)
+                .await?,
+        );
+        self.bytes_written = 0;
+        Ok(())
+    }
+}
+
+impl Pod {
+    /// Remove the symlinks and directory of this pod. Any open file handles
+    /// held by the target remain valid.
+    async fn delete(mut self) -> Result<(), Error> {
+        for container in &mut self.containers {
+            container.fp.flush().await?;
+            fs::remove_file(&container.symlink).await?;
+        }
+        fs::remove_dir_all(&self.directory).await?;
+        Ok(())
+    }
+}
+
+/// Generate a name drawn from `alphabet` with a length in `length`.
+fn random_name<R>(rng: &mut R, alphabet: &[u8], length: std::ops::RangeInclusive<usize>) -> String
+where
+    R: Rng + ?Sized,
+{
+    let length = rng.gen_range(length);
+    (0..length)
+        .map(|_| char::from(*alphabet.choose(rng).unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use tokio::{fs, io::AsyncWriteExt};
+
+    use super::Layout;
+
+    fn layout(root: &Path) -> Layout {
+        Layout {
+            pods_root: root.join("pods"),
+            containers_root: root.join("containers"),
+            namespaces: vec![String::from("default")],
+            containers_per_pod: 2,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    // Pods are laid out as the kubelet does, with container logs reachable
+    // through the symlinks even when the root is relative, rotated in place
+    // and removed entirely on deletion.
+    #[tokio::test]
+    async fn pod_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let up: PathBuf = std::env::current_dir()
+            .unwrap()
+            .components()
+            .skip(1)
+            .map(|_| "..")
+            .collect();
+        let root = up.join(dir.path().strip_prefix("/").unwrap());
+        assert!(root.is_relative());
+
+        let mut layout = layout(&root);
+        fs::create_dir_all(&layout.pods_root).await.unwrap();
+        fs::create_dir_all(&layout.containers_root).await.unwrap();
+        let mut pod = layout.create_pod().await.unwrap();
+
+        let pod_name = pod.directory.file_name().unwrap().to_str().unwrap();
+        let parts: Vec<&str> = pod_name.split('_').collect();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], "default");
+        assert_eq!(pod.directory.parent().unwrap(), layout.pods_root);
+        assert_eq!(pod.containers.len(), 2);
+
+        for container in &mut pod.containers {
+            let name = container.directory.file_name().unwrap().to_str().unwrap();
+            let link = container.symlink.file_name().unwrap().to_str().unwrap();
+            assert!(link.starts_with(&format!("{}_default_{name}-", parts[1])));
+            assert_eq!(container.symlink.extension().unwrap(), "log");
+            assert_eq!(container.symlink.parent().unwrap(), layout.containers_root);
+
+            container.fp.write_all(b"hello\n").await.unwrap();
+            container.bytes_written = 6;
+            container.fp.flush().await.unwrap();
+            assert_eq!(fs::read(&container.symlink).await.unwrap(), b"hello\n");
+
+            container.rotate().await.unwrap();
+            assert_eq!(container.bytes_written, 0);
+            let rotated = container.directory.join("1.log");
+            assert_eq!(fs::read(&rotated).await.unwrap(), b"hello\n");
+            assert!(fs::read(&container.symlink).await.unwrap().is_empty());
+        }
+
+        let directory = pod.directory.clone();
+        let symlinks: Vec<PathBuf> = pod.containers.iter().map(|c| c.symlink.clone()).collect();
+        pod.delete().await.unwrap();
+        assert!(!directory.exists());
+        for symlink in symlinks {
+            assert!(fs::symlink_metadata(&symlink).await.is_err());
+        }
+    }
+}
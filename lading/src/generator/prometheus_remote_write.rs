@@ -0,0 +1,248 @@
+//! The Prometheus remote write generator.
+//!
+//! Every block is a snappy-compressed `WriteRequest`, sent as a POST with the
+//! headers the remote write specification requires.
+//!
+//! ## Metrics
+//!
+//! `requests_sent`: Total number of requests sent
+//! `request_ok`: Successful requests
+//! `request_failure`: Failed requests
+//! `bytes_written`: Total bytes written
+//! `bytes_per_second`: Configured rate to send data
+//!
+//! Additional metrics may be emitted by this generator's [throttle].
+//!
+
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    sync::Arc,
+    thread,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use hyper::{
+    client::{Client, HttpConnector},
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT},
+    Body, Method, Request, Uri,
+};
+use lading_throttle::Throttle;
+use metrics::{counter, gauge};
+use rand::{prelude::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::info;
+
+use crate::{
+    block::{self, Block},
+    common::PeekableReceiver,
+    signals::Shutdown,
+};
+
+use super::General;
+
+const REMOTE_WRITE_VERSION_HEADER: &str = "x-prometheus-remote-write-version";
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+
+#[derive(Debug, Deserialize, PartialEq)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The URI for the target, must be a valid URI
+    #[serde(with = "http_serde::uri")]
+    pub target_uri: Uri,
+    /// The remote write payload configuration
+    #[serde(default)]
+    pub payload: lading_payload::prometheus_remote_write::Config,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// Whether to use a fixed or streaming block cache
+    #[serde(default = "crate::block::default_cache_method")]
+    pub block_cache_method: block::CacheMethod,
+    /// The bytes per second to send or receive from the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The total number of parallel connections to maintain
+    pub parallel_connections: u16,
+    /// The load throttle configuration
+    #[serde(default)]
+    pub throttle: lading_throttle::Config,
+}
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`PrometheusRemoteWrite`].
+pub enum Error {
+    /// Wrapper around [`std::io::Error`].
+    #[error("Io error: {0}")]
+    Io(#[from] ::std::io::Error),
+    /// Creation of payload blocks failed.
+    #[error("Block creation error: {0}")]
+    Block(#[from] block::Error),
+}
+
+/// The Prometheus remote write generator.
+///
+/// This generator is responsible for sending remote write requests to the
+/// target.
+#[derive(Debug)]
+pub struct PrometheusRemoteWrite {
+    uri: Uri,
+    parallel_connections: u16,
+    throttle: Throttle,
+    block_cache: block::Cache,
+    metric_labels: Vec<(String, String)>,
+    shutdown: Shutdown,
+}
+
+impl PrometheusRemoteWrite {
+    /// Create a new [`PrometheusRemoteWrite`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be constructed.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(general: General, config: Config, shutdown: Shutdown) -> Result<Self, Error> {
+        let mut rng = StdRng::from_seed(config.seed);
+        let block_sizes: Vec<NonZeroUsize> = config
+            .block_sizes
+            .unwrap_or_else(|| {
+                vec![
+                    Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                    Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                    Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                    Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                ]
+            })
+            .iter()
+            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+            .collect();
+        let mut labels = vec![
+            ("component".to_string(), "generator".to_string()),
+            (
+                "component_name".to_string(),
+                "prometheus_remote_write".to_string(),
+            ),
+        ];
+        if let Some(id) = general.id {
+            labels.push(("id".to_string(), id));
+        }
+
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        gauge!(
+            "bytes_per_second",
+            f64::from(bytes_per_second.get()),
+            &labels
+        );
+
+        let variant = lading_payload::Config::PrometheusRemoteWrite(config.payload);
+        let total_bytes =
+            NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+                .expect("bytes must be non-zero");
+        let block_cache = match config.block_cache_method {
+            block::CacheMethod::Streaming => {
+                block::Cache::stream(config.seed, total_bytes, &block_sizes, variant)?
+            }
+            block::CacheMethod::Fixed => {
+                block::Cache::fixed(&mut rng, total_bytes, &block_sizes, &variant)?
+            }
+        };
+
+        Ok(Self {
+            uri: config.target_uri,
+            parallel_connections: config.parallel_connections,
+            throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
+            block_cache,
+            metric_labels: labels,
+            shutdown,
+        })
+    }
+
+    /// Run [`PrometheusRemoteWrite`] to completion or until a shutdown signal
+    /// is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the block cache thread cannot be
+    /// spawned.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if it is unable to create HTTP requests for the
+    /// target.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let client: Client<HttpConnector, Body> = Client::builder()
+            .pool_max_idle_per_host(self.parallel_connections as usize)
+            .retry_canceled_requests(false)
+            .set_host(false)
+            .build_http();
+        let semaphore = Arc::new(Semaphore::new(self.parallel_connections as usize));
+        let uri = self.uri;
+
+        let labels = self.metric_labels;
+        // Move the block_cache into an OS thread, exposing a channel between it
+        // and this async context.
+        let block_cache = self.block_cache;
+        let (snd, rcv) = mpsc::channel(1024);
+        let mut rcv: PeekableReceiver<Block> = PeekableReceiver::new(rcv);
+        thread::Builder::new().spawn(|| block_cache.spin(snd))?;
+
+        loop {
+            let blk = rcv.next().await.unwrap();
+            let total_bytes = blk.total_bytes;
+            let block_length = blk.bytes.len();
+
+            let request: Request<Body> = Request::builder()
+                .method(Method::POST)
+                .uri(&uri)
+                .header(CONTENT_LENGTH, block_length)
+                .header(CONTENT_ENCODING, "snappy")
+                .header(CONTENT_TYPE, "application/x-protobuf")
+                .header(USER_AGENT, "lading")
+                .header(REMOTE_WRITE_VERSION_HEADER, REMOTE_WRITE_VERSION)
+                .body(Body::from(blk.bytes))
+                .unwrap();
+
+            tokio::select! {
+                _ = self.throttle.wait_for(total_bytes) => {
+                    let client = client.clone();
+                    let labels = labels.clone();
+
+                    let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+                    tokio::spawn(async move {
+                        counter!("requests_sent", 1, &labels);
+                        match client.request(request).await {
+                            Ok(response) => {
+                                counter!("bytes_written", block_length as u64, &labels);
+                                let status = response.status();
+                                let mut status_labels = labels.clone();
+                                status_labels
+                                    .push(("status_code".to_string(), status.as_u16().to_string()));
+                                counter!("request_ok", 1, &status_labels);
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels.push(("error".to_string(), err.to_string()));
+                                counter!("request_failure", 1, &error_labels);
+                            }
+                        }
+                        drop(permit);
+                    });
+                },
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    // Acquire all available connections, meaning that we have
+                    // no outstanding tasks in flight.
+                    let _permits = semaphore.acquire_many(u32::from(self.parallel_connections)).await.unwrap();
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
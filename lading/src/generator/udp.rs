@@ -6,12 +6,14 @@
 //! `packets_sent`: Packets written successfully
 //! `request_failure`: Number of failed writes; each occurrence causes a socket re-bind
 //! `connection_failure`: Number of socket bind failures
+//! `gelf_message_too_large`: GELF messages dropped for needing over 128 chunks
 //! `bytes_per_second`: Configured rate to send data
 //!
 //! Additional metrics may be emitted by this generator's [throttle].
 //!
 
 use std::{
+    io::Write,
     net::{SocketAddr, ToSocketAddrs},
     num::{NonZeroU32, NonZeroUsize},
     thread,
@@ -19,9 +21,14 @@ use std::{
 };
 
 use byte_unit::{Byte, ByteUnit};
+use bytes::Bytes;
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
 use lading_throttle::Throttle;
-use metrics::{counter, gauge, register_counter};
-use rand::{rngs::StdRng, SeedableRng};
+use metrics::{counter, gauge, register_counter, Counter};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::Deserialize;
 use tokio::{net::UdpSocket, sync::mpsc};
 use tracing::{debug, info, trace};
@@ -34,6 +41,51 @@ use crate::{
 
 use super::General;
 
+/// The magic bytes opening every GELF chunk.
+const GELF_CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+/// The length of a GELF chunk header: magic, message ID, sequence number and
+/// sequence count.
+const GELF_CHUNK_HEADER_BYTES: usize = 2 + 8 + 1 + 1;
+/// GELF receivers discard messages of more chunks than this.
+const GELF_CHUNKS_MAXIMUM: usize = 128;
+/// The largest UDP payload an IPv4 datagram can carry.
+const DATAGRAM_MAXIMUM_BYTES: usize = 65507;
+
+fn gelf_chunk_size_bytes() -> u16 {
+    1420
+}
+
+/// Compression applied to GELF messages before chunking
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GelfCompression {
+    /// Messages are sent as they are
+    None,
+    /// Messages are gzip compressed
+    Gzip,
+    /// Messages are zlib compressed
+    Zlib,
+}
+
+impl Default for GelfCompression {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for sending each block as a GELF message, compressed and
+/// split into GELF chunks when it does not fit in one datagram. Pair with the
+/// `gelf` payload in `datagram` framing.
+pub struct Gelf {
+    /// The maximum size of a datagram, chunk header included
+    #[serde(default = "gelf_chunk_size_bytes")]
+    pub chunk_size_bytes: u16,
+    /// Compression applied to messages before chunking
+    #[serde(default)]
+    pub compression: GelfCompression,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 /// Configuration of this generator.
 pub struct Config {
@@ -52,6 +104,8 @@ pub struct Config {
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// Send blocks as GELF messages, chunked if needed
+    pub gelf: Option<Gelf>,
 }
 
 /// Errors produced by [`Udp`].
@@ -63,6 +117,12 @@ pub enum Error {
     /// Generic IO error
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    /// GELF messages were configured without a suitable payload.
+    #[error("GELF messages require the `gelf` payload in `datagram` framing")]
+    GelfPayload,
+    /// The GELF chunk size cannot hold a chunk or does not fit a datagram.
+    #[error("GELF chunk size {0} must exceed the chunk header and be at most {DATAGRAM_MAXIMUM_BYTES} bytes")]
+    GelfChunkSize(u16),
 }
 
 #[derive(Debug)]
@@ -73,6 +133,8 @@ pub struct Udp {
     addr: SocketAddr,
     throttle: Throttle,
     block_cache: block::Cache,
+    gelf: Option<Gelf>,
+    rng: StdRng,
     metric_labels: Vec<(String, String)>,
     shutdown: Shutdown,
 }
@@ -82,7 +144,9 @@ impl Udp {
     ///
     /// # Errors
     ///
-    /// Creation will fail if the underlying governor capacity exceeds u32.
+    /// Creation will fail if the underlying governor capacity exceeds u32, or
+    /// if GELF messages are configured with a chunk size that does not fit a
+    /// datagram or with a payload other than `gelf` in `datagram` framing.
     ///
     /// # Panics
     ///
@@ -124,7 +188,24 @@ impl Udp {
             &labels
         );
 
-        let block_cache = block::Cache::fixed(
+        if let Some(gelf) = config.gelf {
+            let is_datagram = matches!(
+                config.variant,
+                lading_payload::Config::Gelf(lading_payload::gelf::Config {
+                    framing: lading_payload::gelf::Framing::Datagram,
+                    ..
+                })
+            );
+            if !is_datagram {
+                return Err(Error::GelfPayload);
+            }
+            let size = usize::from(gelf.chunk_size_bytes);
+            if size <= GELF_CHUNK_HEADER_BYTES || size > DATAGRAM_MAXIMUM_BYTES {
+                return Err(Error::GelfChunkSize(gelf.chunk_size_bytes));
+            }
+        }
+
+        let mut block_cache = block::Cache::fixed(
             &mut rng,
             NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
                 .expect("bytes must be non-zero"),
@@ -139,9 +220,19 @@ impl Udp {
             .next()
             .unwrap();
 
+        // GELF messages are compressed once, here, rather than on every send.
+        // The throttle still counts the bytes of the uncompressed block.
+        if let (Some(gelf), block::Cache::Fixed { blocks, .. }) = (config.gelf, &mut block_cache) {
+            for block in blocks {
+                block.bytes = gelf_compress(&block.bytes, gelf.compression)?;
+            }
+        }
+
         Ok(Self {
             addr,
             block_cache,
+            gelf: config.gelf,
+            rng: StdRng::from_seed(rng.gen()),
             throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
             metric_labels: labels,
             shutdown,
@@ -170,12 +261,14 @@ impl Udp {
 
         let bytes_written = register_counter!("bytes_written", &self.metric_labels);
         let packets_sent = register_counter!("packets_sent", &self.metric_labels);
+        let gelf_message_too_large =
+            register_counter!("gelf_message_too_large", &self.metric_labels);
 
         loop {
             let blk = rcv.peek().await.unwrap();
             let total_bytes = blk.total_bytes;
             assert!(
-                total_bytes.get() <= 65507,
+                self.gelf.is_some() || total_bytes.get() <= 65507,
                 "UDP packet too large (over 65507 B)"
             );
 
@@ -199,21 +292,23 @@ impl Udp {
                 _ = self.throttle.wait_for(total_bytes), if connection.is_some() => {
                     let sock = connection.unwrap();
                     let blk = rcv.next().await.unwrap(); // actually advance through the blocks
-                    match sock.send_to(&blk.bytes, self.addr).await {
-                        Ok(bytes) => {
-                            bytes_written.increment(bytes as u64);
-                            packets_sent.increment(1);
-                            connection = Some(sock);
+                    connection = match self.gelf {
+                        None => send(sock, self.addr, &blk.bytes, &self.metric_labels, &bytes_written, &packets_sent).await,
+                        Some(gelf) => {
+                            let message_id: u64 = self.rng.gen();
+                            if let Some(datagrams) = gelf_datagrams(&blk.bytes, gelf.chunk_size_bytes, message_id) {
+                                let mut sock = Some(sock);
+                                for datagram in datagrams {
+                                    let Some(s) = sock.take() else { break };
+                                    sock = send(s, self.addr, &datagram, &self.metric_labels, &bytes_written, &packets_sent).await;
+                                }
+                                sock
+                            } else {
+                                gelf_message_too_large.increment(1);
+                                Some(sock)
+                            }
                         }
-                        Err(err) => {
-                            debug!("write failed: {}", err);
-
-                            let mut error_labels = self.metric_labels.clone();
-                            error_labels.push(("error".to_string(), err.to_string()));
-                            counter!("request_failure", 1, &error_labels);
-                            connection = None;
-                        }
-                    }
+                    };
                 }
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal received");
@@ -223,3 +318,194 @@ impl Udp {
         }
     }
 }
+
+/// Send `bytes` as one datagram to `addr`, returning the socket if it is still
+/// fit for use.
+async fn send(
+    sock: UdpSocket,
+    addr: SocketAddr,
+    bytes: &[u8],
+    metric_labels: &[(String, String)],
+    bytes_written: &Counter,
+    packets_sent: &Counter,
+) -> Option<UdpSocket> {
+    match sock.send_to(bytes, addr).await {
+        Ok(bytes) => {
+            bytes_written.increment(bytes as u64);
+            packets_sent.increment(1);
+            Some(sock)
+        }
+        Err(err) => {
+            debug!("write failed: {}", err);
+
+            let mut error_labels = metric_labels.to_vec();
+            error_labels.push(("error".to_string(), err.to_string()));
+            counter!("request_failure", 1, &error_labels);
+            None
+        }
+    }
+}
+
+/// Compress a GELF message.
+fn gelf_compress(message: &[u8], compression: GelfCompression) -> Result<Bytes, std::io::Error> {
+    let message = match compression {
+        GelfCompression::None => return Ok(Bytes::copy_from_slice(message)),
+        GelfCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(message)?;
+            encoder.finish()?
+        }
+        GelfCompression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(message)?;
+            encoder.finish()?
+        }
+    };
+    Ok(Bytes::from(message))
+}
+
+/// Split an already compressed `message` into GELF chunks of at most
+/// `chunk_size_bytes`. A message that fits in one datagram is not chunked.
+/// Result is `None` if the message needs more chunks than GELF allows.
+#[allow(clippy::cast_possible_truncation)]
+fn gelf_datagrams(message: &[u8], chunk_size_bytes: u16, message_id: u64) -> Option<Vec<Vec<u8>>> {
+    let chunk_size = usize::from(chunk_size_bytes);
+    if message.len() <= chunk_size {
+        return Some(vec![message.to_vec()]);
+    }
+
+    let chunks: Vec<&[u8]> = message
+        .chunks(chunk_size - GELF_CHUNK_HEADER_BYTES)
+        .collect();
+    if chunks.len() > GELF_CHUNKS_MAXIMUM {
+        return None;
+    }
+    // Sequence numbers and count fit in a u8, there are no more than 128
+    // chunks.
+    let sequence_count = chunks.len() as u8;
+    let datagrams = chunks
+        .into_iter()
+        .enumerate()
+        .map(|(sequence_number, chunk)| {
+            let mut datagram = Vec::with_capacity(GELF_CHUNK_HEADER_BYTES + chunk.len());
+            datagram.extend_from_slice(&GELF_CHUNK_MAGIC);
+            datagram.extend_from_slice(&message_id.to_be_bytes());
+            datagram.push(sequence_number as u8);
+            datagram.push(sequence_count);
+            datagram.extend_from_slice(chunk);
+            datagram
+        })
+        .collect();
+    Some(datagrams)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use byte_unit::{Byte, ByteUnit};
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use lading_payload::gelf::Framing;
+
+    use super::{
+        gelf_compress, gelf_datagrams, Config, Error, Gelf, GelfCompression, Udp,
+        GELF_CHUNK_HEADER_BYTES,
+    };
+    use crate::{generator::General, signals::Shutdown};
+
+    #[test]
+    fn small_gelf_message_is_not_chunked() {
+        let datagrams = gelf_datagrams(b"{}", 1420, 7).unwrap();
+        assert_eq!(datagrams, vec![b"{}".to_vec()]);
+    }
+
+    #[test]
+    fn large_gelf_message_is_chunked() {
+        let message = vec![b'a'; 1000];
+        let datagrams = gelf_datagrams(&message, 100, 7).unwrap();
+
+        let payload_bytes = 100 - GELF_CHUNK_HEADER_BYTES;
+        assert_eq!(datagrams.len(), (1000 + payload_bytes - 1) / payload_bytes);
+        let mut reassembled = Vec::new();
+        for (idx, datagram) in datagrams.iter().enumerate() {
+            assert!(datagram.len() <= 100);
+            assert_eq!(&datagram[..2], &[0x1e, 0x0f]);
+            assert_eq!(&datagram[2..10], &7_u64.to_be_bytes());
+            assert_eq!(usize::from(datagram[10]), idx);
+            assert_eq!(usize::from(datagram[11]), datagrams.len());
+            reassembled.extend_from_slice(&datagram[GELF_CHUNK_HEADER_BYTES..]);
+        }
+        assert_eq!(reassembled, message);
+    }
+
+    #[test]
+    fn oversize_gelf_message_is_refused() {
+        assert!(gelf_datagrams(&[0; 129], 13, 7).is_none());
+    }
+
+    #[test]
+    fn gelf_compression_round_trips() {
+        let message = br#"{"version":"1.1","host":"h","short_message":"m"}"#;
+        assert_eq!(
+            &gelf_compress(message, GelfCompression::None).unwrap()[..],
+            message
+        );
+
+        let mut decompressed = Vec::new();
+        let compressed = gelf_compress(message, GelfCompression::Gzip).unwrap();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, message);
+
+        decompressed.clear();
+        let compressed = gelf_compress(message, GelfCompression::Zlib).unwrap();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, message);
+    }
+
+    fn config(variant: lading_payload::Config, chunk_size_bytes: u16) -> Config {
+        Config {
+            seed: [7; 32],
+            addr: "127.0.0.1:12201".to_string(),
+            variant,
+            bytes_per_second: Byte::from_unit(1.0, ByteUnit::MiB).unwrap(),
+            block_sizes: Some(vec![Byte::from_unit(1.0, ByteUnit::KiB).unwrap()]),
+            maximum_prebuild_cache_size_bytes: Byte::from_unit(8.0, ByteUnit::KiB).unwrap(),
+            throttle: lading_throttle::Config::default(),
+            gelf: Some(Gelf {
+                chunk_size_bytes,
+                compression: GelfCompression::Gzip,
+            }),
+        }
+    }
+
+    #[test]
+    fn gelf_configuration_is_validated() {
+        let new = |config: &Config| Udp::new(General { id: None }, config, Shutdown::new());
+        let gelf = |framing| {
+            lading_payload::Config::Gelf(lading_payload::gelf::Config {
+                framing,
+                ..lading_payload::gelf::Config::default()
+            })
+        };
+        let datagram = || gelf(Framing::Datagram);
+
+        assert!(new(&config(datagram(), 1420)).is_ok());
+        assert!(new(&config(datagram(), 65507)).is_ok());
+        for variant in [gelf(Framing::NullDelimited), lading_payload::Config::Ascii] {
+            assert!(matches!(
+                new(&config(variant, 1420)),
+                Err(Error::GelfPayload)
+            ));
+        }
+        for chunk_size_bytes in [0, 12, 65508] {
+            assert!(matches!(
+                new(&config(datagram(), chunk_size_bytes)),
+                Err(Error::GelfChunkSize(size)) if size == chunk_size_bytes
+            ));
+        }
+    }
+}
@@ -7,13 +7,28 @@
 //! sub-process and that's the responsibility of the inspector. Consider that
 //! you can get a Linux `perf` sample of the target by means of having inspector
 //! run an appropriate shell script, or take samples of the target's CPU use.
+//!
+//! Several inspectors may be configured. Each runs once, as soon as the target
+//! is ready, unless given a [`Schedule`]: it may start at an offset into the
+//! experiment, be stopped after a duration and be run again periodically, say
+//! `perf record` for 10 seconds every 60 seconds. Every run's exit status and
+//! runtime are recorded as `inspector_exit_status` and
+//! `inspector_runtime_seconds`.
+//!
+//! Rather than a program an inspector may run one of lading's built-in
+//! inspectors, see [`snapshot`].
 
 use std::{
     io,
+    num::NonZeroU64,
+    os::unix::process::ExitStatusExt,
     path::PathBuf,
     process::{ExitStatus, Stdio},
+    time::Duration,
 };
 
+use metrics::{counter, gauge};
+
 use nix::{
     errno::Errno,
     sys::signal::{kill, SIGTERM},
@@ -21,15 +36,22 @@ use nix::{
 };
 use rustc_hash::FxHashMap;
 use serde::Deserialize;
-use tokio::process::Command;
-use tracing::{error, info};
+use tokio::{
+    process::{Child, Command},
+    sync::broadcast::error::RecvError,
+    time::{interval, sleep, Instant, MissedTickBehavior},
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
 use crate::{
-    common::{stdio, Output},
+    common::{stdio, stdio_append, Output},
     signals::Shutdown,
     target::TargetPidReceiver,
 };
 
+pub mod snapshot;
+
 #[derive(Debug)]
 /// Errors produced by [`Server`]
 pub enum Error {
@@ -43,6 +65,35 @@ pub enum Error {
 #[serde(rename_all = "snake_case")]
 /// Configuration for [`Server`]
 pub struct Config {
+    /// The identifier of this inspector, recorded as the `id` label of its
+    /// metrics.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// What the inspector runs.
+    #[serde(flatten)]
+    pub mode: Mode,
+    /// When the inspector runs.
+    #[serde(default)]
+    pub schedule: Schedule,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+/// What an inspector runs
+pub enum Mode {
+    /// Run a user supplied program.
+    Program(Program),
+    /// Snapshot the target with the built-in [`snapshot`] inspector.
+    Snapshot {
+        /// See [`snapshot::Config`] for details.
+        snapshot: snapshot::Config,
+    },
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// A user supplied inspector program
+pub struct Program {
     /// The path to the inspector executable.
     pub command: PathBuf,
     /// Arguments for the inspector sub-process.
@@ -54,6 +105,36 @@ pub struct Config {
     pub output: Output,
 }
 
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// When an inspector runs
+///
+/// All times are relative to the moment the target is ready, that is the
+/// start of warmup.
+pub struct Schedule {
+    /// Seconds to wait before the first run.
+    #[serde(default)]
+    pub start_offset_seconds: u64,
+    /// If set, each run of a program is terminated after this many seconds.
+    #[serde(default)]
+    pub duration_seconds: Option<NonZeroU64>,
+    /// If set, a run is started this many seconds after the start of the
+    /// previous one, unless that run is still going in which case the next
+    /// starts once it exits. Otherwise the inspector runs only once.
+    #[serde(default)]
+    pub period_seconds: Option<NonZeroU64>,
+}
+
+/// The reason a run of the inspector ended
+enum RunEnd {
+    /// The run completed or reached its configured duration.
+    Finished,
+    /// The target restarted, with this PID.
+    TargetRestarted(u32),
+    /// Lading is shutting down.
+    Shutdown,
+}
+
 #[derive(Debug)]
 /// The inspector sub-process server.
 ///
@@ -64,6 +145,7 @@ pub struct Config {
 /// there are no protections for that.
 pub struct Server {
     config: Config,
+    run_id: Uuid,
     shutdown: Shutdown,
 }
 
@@ -71,44 +153,221 @@ impl Server {
     /// Create a new [`Server`] instance
     ///
     /// The inspector `Server` is responsible for investigating the
-    /// [`crate::target::Server`] sub-process.
+    /// [`crate::target::Server`] sub-process. Artifacts it writes are
+    /// associated with `run_id`, the run ID of the capture file.
     ///
     /// # Errors
     ///
     /// Function will error if the path to the sub-process is not valid or if
     /// the path is valid but is not to file executable by this program.
-    pub fn new(config: Config, shutdown: Shutdown) -> Result<Self, Error> {
-        Ok(Self { config, shutdown })
+    pub fn new(config: Config, run_id: Uuid, shutdown: Shutdown) -> Result<Self, Error> {
+        Ok(Self {
+            config,
+            run_id,
+            shutdown,
+        })
     }
 
     /// Run this [`Server`] to completion
     ///
-    /// This function runs the user supplied program to its completion, or until
-    /// a shutdown signal is received. Child exit status does not currently
-    /// propagate. This is less than ideal.
+    /// This function runs the inspector according to its
+    /// [`Schedule`] until the schedule is exhausted or a shutdown signal is
+    /// received. The exit status and runtime of each run are recorded as
+    /// metrics.
     ///
     /// Target server will use the `TargetPidReceiver` passed here to transmit
     /// its PID. This PID is passed to the sub-process in the `TARGET_PID`
-    /// environment variable. This variable is not set in no-target mode.
+    /// environment variable. This variable is not set in no-target mode. If
+    /// the target is restarted a running sub-process is terminated and run
+    /// again with the new PID.
     ///
     /// # Errors
     ///
-    /// Function will return an error if the underlying program cannot be waited
-    /// on or will not shutdown when signaled to.
+    /// Function will return an error if the underlying program cannot be
+    /// spawned, waited on or will not shutdown when signaled to.
     ///
     /// # Panics
     ///
     /// None are known.
-    pub async fn run(mut self, mut pid_snd: TargetPidReceiver) -> Result<ExitStatus, Error> {
-        let target_pid = pid_snd
+    pub async fn run(mut self, pid_snd: TargetPidReceiver) -> Result<(), Error> {
+        let mut pid_snd = Some(pid_snd);
+        let mut target_pid = pid_snd
+            .as_mut()
+            .expect("just set")
             .recv()
             .await
             .expect("target failed to transmit PID, catastrophic failure");
-        drop(pid_snd);
+        let schedule = self.config.schedule;
+
+        if schedule.start_offset_seconds > 0 {
+            let offset = sleep(Duration::from_secs(schedule.start_offset_seconds));
+            tokio::pin!(offset);
+            loop {
+                tokio::select! {
+                    _ = &mut offset => break,
+                    pid = Self::restarted(&mut pid_snd) => target_pid = Some(pid),
+                    _ = self.shutdown.recv() => {
+                        info!("shutdown signal received");
+                        return Ok(());
+                    }
+                }
+            }
+        }
 
-        let config = self.config;
+        let mut period = schedule.period_seconds.map(|secs| {
+            let mut period = interval(Duration::from_secs(secs.get()));
+            period.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            period
+        });
+        let mut restart = false;
+        loop {
+            if let Some(period) = period.as_mut() {
+                loop {
+                    tokio::select! {
+                        _ = period.tick() => break,
+                        pid = Self::restarted(&mut pid_snd) => target_pid = Some(pid),
+                        _ = self.shutdown.recv() => {
+                            info!("shutdown signal received");
+                            return Ok(());
+                        }
+                    }
+                }
+            }
 
-        let mut target_cmd = Command::new(config.command);
+            // A run interrupted by a target restart is immediately run again.
+            loop {
+                let end = self.run_once(target_pid, restart, &mut pid_snd).await?;
+                restart = true;
+                match end {
+                    RunEnd::Finished => break,
+                    RunEnd::TargetRestarted(pid) => {
+                        info!("target restarted with PID {pid}, restarting inspector");
+                        target_pid = Some(pid);
+                    }
+                    RunEnd::Shutdown => return Ok(()),
+                }
+            }
+
+            if period.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Run the inspector once, recording its exit status and runtime.
+    async fn run_once(
+        &mut self,
+        target_pid: Option<u32>,
+        restart: bool,
+        pid_snd: &mut Option<TargetPidReceiver>,
+    ) -> Result<RunEnd, Error> {
+        let snapshot = match &self.config.mode {
+            Mode::Program(_) => return self.run_program(target_pid, restart, pid_snd).await,
+            Mode::Snapshot { snapshot } => snapshot,
+        };
+        let start = Instant::now();
+        // A failed snapshot is recorded but does not stop later ones.
+        let res = match target_pid {
+            Some(pid) => snapshot::snapshot(snapshot, self.run_id, pid).await,
+            None => Err(snapshot::Error::NoTarget),
+        };
+        let code = match res {
+            Ok(dir) => {
+                info!("target snapshot written to {}", dir.display());
+                0
+            }
+            Err(err) => {
+                warn!("unable to snapshot target: {err}");
+                1
+            }
+        };
+        self.record(code, start.elapsed());
+        Ok(RunEnd::Finished)
+    }
+
+    /// Run the inspector program once.
+    async fn run_program(
+        &mut self,
+        target_pid: Option<u32>,
+        restart: bool,
+        pid_snd: &mut Option<TargetPidReceiver>,
+    ) -> Result<RunEnd, Error> {
+        let mut child = self.spawn(target_pid, restart)?;
+        let start = Instant::now();
+        let duration = self.config.schedule.duration_seconds;
+        let duration = async move {
+            match duration {
+                Some(secs) => sleep(Duration::from_secs(secs.get())).await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        let (status, end) = tokio::select! {
+            res = child.wait() => {
+                let status = res.map_err(Error::Io)?;
+                info!("inspector exited with status: {status}");
+                (status, RunEnd::Finished)
+            }
+            _ = duration => {
+                info!("inspector reached its configured duration, terminating");
+                (Self::terminate(child).await?, RunEnd::Finished)
+            }
+            pid = Self::restarted(pid_snd) => {
+                (Self::terminate(child).await?, RunEnd::TargetRestarted(pid))
+            }
+            _ = self.shutdown.recv() => {
+                info!("shutdown signal received");
+                (Self::terminate(child).await?, RunEnd::Shutdown)
+            }
+        };
+        if !status.success() {
+            error!("inspector exited with status: {status}");
+        }
+        // An exit by signal is recorded as 128 plus the signal number, as
+        // shells do.
+        let code = status
+            .code()
+            .or_else(|| status.signal().map(|signal| 128 + signal))
+            .unwrap_or(-1);
+        self.record(code, start.elapsed());
+        Ok(end)
+    }
+
+    /// Wait for the target to restart, returning its new PID. Never returns if
+    /// the target will not restart.
+    async fn restarted(pid_snd: &mut Option<TargetPidReceiver>) -> u32 {
+        loop {
+            let Some(rcv) = pid_snd.as_mut() else {
+                return futures::future::pending().await;
+            };
+            match rcv.recv().await {
+                Ok(Some(pid)) => return pid,
+                Ok(None) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => *pid_snd = None,
+            }
+        }
+    }
+
+    /// Record the outcome of a run.
+    fn record(&self, code: i32, runtime: Duration) {
+        let mut labels = vec![("component".to_string(), "inspector".to_string())];
+        if let Some(id) = &self.config.id {
+            labels.push(("id".to_string(), id.clone()));
+        }
+        counter!("inspector_runs", 1, &labels);
+        gauge!("inspector_exit_status", f64::from(code), &labels);
+        gauge!("inspector_runtime_seconds", runtime.as_secs_f64(), &labels);
+    }
+
+    /// Spawn the inspector sub-process, informing it of `target_pid`. If
+    /// `restart` is set output logs are appended to rather than truncated.
+    fn spawn(&self, target_pid: Option<u32>, restart: bool) -> Result<Child, Error> {
+        let Mode::Program(config) = &self.config.mode else {
+            unreachable!("only programs are spawned");
+        };
+        let output = if restart { stdio_append } else { stdio };
+
+        let mut target_cmd = Command::new(&config.command);
         let mut environment_variables = config.environment_variables.clone();
         if let Some(pid) = target_pid {
             environment_variables.insert(String::from("TARGET_PID"), pid.to_string());
@@ -118,38 +377,122 @@ impl Server {
 
         target_cmd
             .stdin(Stdio::null())
-            .stdout(stdio(&config.output.stdout))
-            .stderr(stdio(&config.output.stderr))
+            .stdout(output(&config.output.stdout))
+            .stderr(output(&config.output.stderr))
             .env_clear()
             .kill_on_drop(true)
-            .args(config.arguments)
+            .args(&config.arguments)
             .envs(environment_variables.iter());
-        let mut target_child = target_cmd.spawn().map_err(Error::Io)?;
-
-        let target_wait = target_child.wait();
-        tokio::select! {
-            res = target_wait => {
-                match res {
-                    Ok(status) => {
-                        error!("child exited with status: {}", status);
-                        Ok(status)
-                    }
-                    Err(err) => {
-                        error!("child exited with error: {}", err);
-                        Err(Error::Io(err))
-                    }
-                }
+        target_cmd.spawn().map_err(Error::Io)
+    }
+
+    /// Gracefully terminate the inspector sub-process, waiting for it to exit.
+    async fn terminate(mut target_child: Child) -> Result<ExitStatus, Error> {
+        // Note that `Child::kill` sends SIGKILL which is not what we want. We
+        // instead send SIGTERM so that the child has a chance to clean up.
+        let pid: Pid = Pid::from_raw(target_child.id().unwrap().try_into().unwrap());
+        kill(pid, SIGTERM).map_err(Error::Errno)?;
+        let res = target_child.wait().await.map_err(Error::Io)?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::common::{
+        recorded::{self, install},
+        Behavior,
+    };
+
+    const LABELS: [(&str, &str); 1] = [("component", "inspector")];
+
+    fn server(mode: Mode, schedule: Schedule, shutdown: Shutdown) -> Server {
+        let config = Config {
+            id: None,
+            mode,
+            schedule,
+        };
+        Server::new(config, Uuid::new_v4(), shutdown).unwrap()
+    }
+
+    /// The number of runs recorded once the clock has reached `secs`.
+    async fn runs_at(secs: u64, start: Instant) -> Option<u64> {
+        tokio::time::sleep_until(start + Duration::from_secs(secs)).await;
+        recorded::counter("inspector_runs", &LABELS)
+    }
+
+    // The clock is paused so that the schedule is exact. In no-target mode a
+    // snapshot fails at once, without touching the filesystem.
+    #[tokio::test(start_paused = true)]
+    async fn schedule_offset_and_period() {
+        install();
+        let directory = tempfile::tempdir().unwrap();
+        let mode = Mode::Snapshot {
+            snapshot: snapshot::Config {
+                directory: directory.path().to_path_buf(),
             },
-            _ = self.shutdown.recv() => {
-                info!("shutdown signal received");
-                // Note that `Child::kill` sends SIGKILL which is not what we
-                // want. We instead send SIGTERM so that the child has a chance
-                // to clean up.
-                let pid: Pid = Pid::from_raw(target_child.id().unwrap().try_into().unwrap());
-                kill(pid, SIGTERM).map_err(Error::Errno)?;
-                let res = target_child.wait().await.map_err(Error::Io)?;
-                Ok(res)
-            }
-        }
+        };
+        let schedule = Schedule {
+            start_offset_seconds: 10,
+            duration_seconds: None,
+            period_seconds: NonZeroU64::new(30),
+        };
+        let shutdown = Shutdown::new();
+        let (pid_snd, pid_rcv) = broadcast::channel(1);
+        pid_snd.send(None).unwrap();
+
+        let start = Instant::now();
+        let run = tokio::spawn(server(mode, schedule, shutdown.clone()).run(pid_rcv));
+        assert_eq!(runs_at(9, start).await, None);
+        assert_eq!(runs_at(11, start).await, Some(1));
+        assert_eq!(runs_at(39, start).await, Some(1));
+        assert_eq!(runs_at(41, start).await, Some(2));
+        assert_eq!(runs_at(71, start).await, Some(3));
+        assert_eq!(recorded::gauge("inspector_exit_status", &LABELS), Some(1.0));
+
+        shutdown.signal().unwrap();
+        run.await.unwrap().unwrap();
+        assert_eq!(runs_at(200, start).await, Some(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn duration_terminates_program() {
+        install();
+        let mode = Mode::Program(Program {
+            command: PathBuf::from("/bin/sleep"),
+            arguments: vec!["100".to_owned()],
+            environment_variables: FxHashMap::default(),
+            output: Output {
+                stderr: Behavior::Quiet,
+                stdout: Behavior::Quiet,
+            },
+        });
+        let schedule = Schedule {
+            start_offset_seconds: 0,
+            duration_seconds: NonZeroU64::new(5),
+            period_seconds: None,
+        };
+        let (pid_snd, pid_rcv) = broadcast::channel(1);
+        pid_snd.send(None).unwrap();
+
+        // Without a period the inspector runs once, returning after it is
+        // terminated.
+        server(mode, schedule, Shutdown::new())
+            .run(pid_rcv)
+            .await
+            .unwrap();
+        assert_eq!(recorded::counter("inspector_runs", &LABELS), Some(1));
+        // Terminated by SIGTERM.
+        assert_eq!(
+            recorded::gauge("inspector_exit_status", &LABELS),
+            Some(143.0)
+        );
+        assert_eq!(
+            recorded::gauge("inspector_runtime_seconds", &LABELS),
+            Some(5.0)
+        );
     }
 }
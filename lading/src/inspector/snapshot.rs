@@ -0,0 +1,167 @@
+//! Built-in snapshot inspector
+//!
+//! By the time anyone looks into a memory spike the target has usually exited
+//! or moved on. This inspector copies the target's `/proc/<pid>/maps`,
+//! `smaps` and `status`, along with the kernel stack of each of its threads,
+//! into a directory per snapshot, `<directory>/<run_id>/<milliseconds since
+//! the epoch>/`, laid out as in procfs. The run ID is that of the capture
+//! file. Files that cannot be read are skipped: reading kernel stacks, in
+//! particular, usually requires root.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use tokio::fs;
+use tracing::debug;
+use uuid::Uuid;
+
+/// The files copied from `/proc/<pid>`.
+const FILES: [&str; 3] = ["maps", "smaps", "status"];
+
+/// Errors produced while taking a snapshot
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// There is no target to snapshot in no-target mode
+    #[error("no target to snapshot")]
+    NoTarget,
+    /// Wrapper for [`std::io::Error`]
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Configuration for the snapshot inspector
+pub struct Config {
+    /// The directory beneath which snapshots are written.
+    pub directory: PathBuf,
+}
+
+/// Snapshot the process `pid`, returning the directory written to.
+///
+/// # Errors
+///
+/// Function will return an error if the snapshot directory cannot be created
+/// or written to, or if no file of the process could be read.
+pub(crate) async fn snapshot(config: &Config, run_id: Uuid, pid: u32) -> Result<PathBuf, Error> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is after the epoch")
+        .as_millis();
+    let dir = config
+        .directory
+        .join(run_id.to_string())
+        .join(timestamp.to_string());
+
+    // Read before creating anything so that a process that has gone leaves no
+    // empty snapshot directory behind.
+    let proc = PathBuf::from(format!("/proc/{pid}"));
+    let mut contents = Vec::with_capacity(FILES.len());
+    for file in FILES {
+        match fs::read(proc.join(file)).await {
+            Ok(content) => contents.push((file, content)),
+            Err(err) => debug!("unable to read {}/{file}: {err}", proc.display()),
+        }
+    }
+    if contents.is_empty() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("unable to read {}", proc.display()),
+        )));
+    }
+
+    fs::create_dir_all(&dir).await?;
+    for (file, content) in contents {
+        fs::write(dir.join(file), content).await?;
+    }
+
+    match fs::read_dir(proc.join("task")).await {
+        Ok(mut tasks) => {
+            while let Some(task) = tasks.next_entry().await? {
+                let thread = task.file_name();
+                let task_dir = dir.join("task").join(&thread);
+                fs::create_dir_all(&task_dir).await?;
+                copy(&task.path().join("stack"), &task_dir.join("stack")).await?;
+            }
+        }
+        Err(err) => debug!("unable to list tasks of {pid}: {err}"),
+    }
+    Ok(dir)
+}
+
+/// Copy `from` to `to`, returning whether `from` could be read. Files in
+/// procfs report a size of zero so are read whole rather than copied.
+async fn copy(from: &Path, to: &Path) -> Result<bool, Error> {
+    match fs::read(from).await {
+        Ok(contents) => {
+            fs::write(to, contents).await?;
+            Ok(true)
+        }
+        Err(err) => {
+            debug!("unable to read {}: {err}", from.display());
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use uuid::Uuid;
+
+    use super::{copy, snapshot, Config, Error};
+
+    #[tokio::test]
+    async fn snapshot_layout() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = Config {
+            directory: directory.path().to_path_buf(),
+        };
+        let run_id = Uuid::new_v4();
+        let dir = snapshot(&config, run_id, std::process::id()).await.unwrap();
+
+        // <directory>/<run_id>/<milliseconds since the epoch>/
+        assert_eq!(
+            dir.parent().unwrap(),
+            directory.path().join(run_id.to_string())
+        );
+        let timestamp = dir.file_name().unwrap().to_str().unwrap();
+        assert!(timestamp.parse::<u128>().unwrap() > 0);
+        for file in ["maps", "smaps", "status"] {
+            assert!(dir.join(file).is_file(), "{file} missing");
+        }
+        // A directory per thread, whether or not its stack could be read.
+        let threads = std::fs::read_dir(dir.join("task")).unwrap().count();
+        assert!(threads > 0);
+    }
+
+    #[tokio::test]
+    async fn unreadable_file_skipped() {
+        let directory = tempfile::tempdir().unwrap();
+        let to = directory.path().join("stack");
+        let copied = copy(Path::new("/proc/self/task/0/stack"), &to)
+            .await
+            .unwrap();
+        assert!(!copied);
+        assert!(!to.exists());
+    }
+
+    #[tokio::test]
+    async fn missing_process_is_error() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = Config {
+            directory: directory.path().to_path_buf(),
+        };
+        assert!(matches!(
+            snapshot(&config, Uuid::new_v4(), u32::MAX).await,
+            Err(Error::Io(_))
+        ));
+        // Nothing is left behind for a snapshot that failed.
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+}
@@ -6,11 +6,14 @@
 //! cannot incorporate whatever it's doing into the capture data that lading
 //! produces. This observer, on Linux, looks up the target process in procfs and
 //! writes out key details about memory and CPU consumption into the capture
-//! data. On non-Linux systems the observer, if enabled, will emit a warning.
+//! data. The observer also samples host-wide CPU, memory and load, as well as
+//! lading's own resource consumption, to help explain noisy results. On
+//! non-Linux systems the observer, if enabled, will emit a warning.
 
-use std::{io, sync::atomic::AtomicU64};
+use std::{io, num::NonZeroU64, path::PathBuf, sync::atomic::AtomicU64};
 
 use crate::target::TargetPidReceiver;
+use regex::Regex;
 use serde::Deserialize;
 
 use crate::signals::Shutdown;
@@ -32,16 +35,83 @@ pub enum Error {
     /// Wrapper for [`std::io::Error`]
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
+    /// Wrapper for [`regex::Error`]
+    #[error("invalid process filter: {0}")]
+    ProcessFilter(#[from] regex::Error),
     #[cfg(target_os = "linux")]
     /// Wrapper for [`linux::Error`]
     #[error("Linux error: {0}")]
     Linux(#[from] linux::Error),
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+fn default_sample_period_milliseconds() -> NonZeroU64 {
+    NonZeroU64::new(1_000).expect("default sample period must be non-zero")
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 /// Configuration for [`Server`]
-pub struct Config {}
+pub struct Config {
+    /// The time between samples of the target, in milliseconds.
+    #[serde(default = "default_sample_period_milliseconds")]
+    pub sample_period_milliseconds: NonZeroU64,
+    /// The groups of metrics to sample.
+    #[serde(default)]
+    pub metrics: Metrics,
+    /// A regular expression that restricts sampling to processes whose `exe`
+    /// basename or `comm` matches. Processes that do not match are still
+    /// searched for children but are not themselves sampled. If unset all
+    /// processes in the target's tree are sampled.
+    #[serde(default)]
+    pub process_filter: Option<String>,
+    /// A cgroup directory whose processes, and their descendants, are sampled
+    /// alongside the target's tree. Membership is read again at every sample.
+    /// Set from `--target-cgroup` when the target is discovered by cgroup.
+    #[serde(default)]
+    pub cgroup: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            sample_period_milliseconds: default_sample_period_milliseconds(),
+            metrics: Metrics::default(),
+            process_filter: None,
+            cgroup: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
+/// Enables or disables groups of observer metrics. All groups are enabled by
+/// default.
+pub struct Metrics {
+    /// Emit `cpu_percentage`, `kernel_cpu_percentage` and
+    /// `user_cpu_percentage` per process.
+    pub cpu: bool,
+    /// Emit `rss_bytes`, `rsslim_bytes` and `vsize_bytes` per process.
+    pub memory: bool,
+    /// Emit `num_threads` per process and `num_processes` for the whole tree.
+    pub process_count: bool,
+    /// Emit system-wide CPU, memory and load average metrics along with
+    /// lading's own CPU and RSS consumption. These are labeled with `scope`
+    /// `host` or `lading` respectively.
+    pub host: bool,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            cpu: true,
+            memory: true,
+            process_count: true,
+            host: true,
+        }
+    }
+}
 
 #[derive(Debug)]
 /// The inspector sub-process server.
@@ -52,9 +122,11 @@ pub struct Config {}
 /// that only one instance of this struct will ever exist at a time, although
 /// there are no protections for that.
 pub struct Server {
-    #[allow(dead_code)] // config is not actively used, left as a stub
+    #[allow(dead_code)] // this field is unused when target_os is not "linux"
     config: Config,
     #[allow(dead_code)] // this field is unused when target_os is not "linux"
+    process_filter: Option<Regex>,
+    #[allow(dead_code)] // this field is unused when target_os is not "linux"
     shutdown: Shutdown,
 }
 
@@ -66,10 +138,19 @@ impl Server {
     ///
     /// # Errors
     ///
-    /// Function will error if the path to the sub-process is not valid or if
-    /// the path is valid but is not to file executable by this program.
+    /// Function will error if the configured `process_filter` is not a valid
+    /// regular expression.
     pub fn new(config: Config, shutdown: Shutdown) -> Result<Self, Error> {
-        Ok(Self { config, shutdown })
+        let process_filter = config
+            .process_filter
+            .as_deref()
+            .map(Regex::new)
+            .transpose()?;
+        Ok(Self {
+            config,
+            process_filter,
+            shutdown,
+        })
     }
 
     /// Run this [`Server`] to completion
@@ -79,7 +160,8 @@ impl Server {
     /// propagate. This is less than ideal.
     ///
     /// Target server will use the `TargetPidReceiver` passed here to transmit
-    /// its PID. This PID is passed to the sub-process as the first argument.
+    /// its PID. If the target is restarted its new PID is transmitted and the
+    /// observer follows the new process.
     ///
     /// # Errors
     ///
@@ -99,24 +181,50 @@ impl Server {
     pub async fn run(mut self, mut pid_snd: TargetPidReceiver) -> Result<(), Error> {
         use std::time::Duration;
 
+        use tokio::sync::broadcast::error::RecvError;
+
         use crate::observer::linux::Sampler;
 
         let target_pid = pid_snd
             .recv()
             .await
             .expect("target failed to transmit PID, catastrophic failure");
-        drop(pid_snd);
 
         let target_pid = target_pid.expect("observer cannot be used in no-target mode");
 
-        let mut sample_delay = tokio::time::interval(Duration::from_secs(1));
-        let mut sampler = Sampler::new(target_pid)?;
-
+        let mut sample_delay = tokio::time::interval(Duration::from_millis(
+            self.config.sample_period_milliseconds.get(),
+        ));
+        let mut sampler = Sampler::new(
+            target_pid,
+            self.config.metrics,
+            self.process_filter.clone(),
+            self.config.cgroup.clone(),
+        )?;
+
+        // The target may be restarted, in which case a new PID is transmitted
+        // and we follow it. Once the target server is done transmitting we stop
+        // listening.
+        let mut pid_snd = Some(pid_snd);
         loop {
             tokio::select! {
                 _ = sample_delay.tick() => {
                     sampler.sample()?;
                 }
+                pid = async { pid_snd.as_mut().expect("guarded by select").recv().await }, if pid_snd.is_some() => {
+                    match pid {
+                        Ok(Some(target_pid)) => {
+                            tracing::info!("observer following restarted target, PID {target_pid}");
+                            if let Err(err) = sampler.follow(target_pid) {
+                                tracing::warn!("unable to follow target PID {target_pid}: {err}");
+                            }
+                        }
+                        Ok(None) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => {
+                            pid_snd = None;
+                        }
+                    }
+                }
                 _ = self.shutdown.recv() => {
                     tracing::info!("shutdown signal received");
                     return Ok(());
@@ -1,11 +1,12 @@
-use std::{collections::VecDeque, io, sync::atomic::Ordering};
+use std::{collections::VecDeque, io, path::PathBuf, sync::atomic::Ordering};
 
 use metrics::gauge;
 use nix::errno::Errno;
 use procfs::process::Process;
+use regex::Regex;
 use rustc_hash::{FxHashMap, FxHashSet};
 
-use super::RSS_BYTES;
+use super::{Metrics, RSS_BYTES};
 
 #[derive(thiserror::Error, Debug)]
 /// Errors produced by functions in this module
@@ -35,11 +36,21 @@ pub(crate) struct Sampler {
     num_cores: usize,
     ticks_per_second: u64,
     page_size: u64,
+    metrics: Metrics,
+    process_filter: Option<Regex>,
+    cgroup: Option<PathBuf>,
     previous_samples: FxHashMap<(i32, String), Sample>,
+    previous_host_cpu: HostCpuSample,
+    previous_lading_sample: Sample,
 }
 
 impl Sampler {
-    pub(crate) fn new(parent_pid: u32) -> Result<Self, Error> {
+    pub(crate) fn new(
+        parent_pid: u32,
+        metrics: Metrics,
+        process_filter: Option<Regex>,
+        cgroup: Option<PathBuf>,
+    ) -> Result<Self, Error> {
         let parent = Process::new(parent_pid.try_into().expect("PID coercion failed"))?;
 
         Ok(Self {
@@ -47,10 +58,25 @@ impl Sampler {
             num_cores: num_cpus::get(), // Cores, logical on Linux, obeying cgroup limits if present
             ticks_per_second: procfs::ticks_per_second(),
             page_size: procfs::page_size(),
+            metrics,
+            process_filter,
+            cgroup,
             previous_samples: FxHashMap::default(),
+            previous_host_cpu: HostCpuSample::default(),
+            previous_lading_sample: Sample::default(),
         })
     }
 
+    /// Follow a new parent process, as when the target is restarted. Samples
+    /// of the previous process tree are dropped but host and lading samples
+    /// are kept, so their next percentages are relative to the last sample
+    /// rather than to boot.
+    pub(crate) fn follow(&mut self, parent_pid: u32) -> Result<(), Error> {
+        self.parent = Process::new(parent_pid.try_into().expect("PID coercion failed"))?;
+        self.previous_samples.clear();
+        Ok(())
+    }
+
     #[allow(
         clippy::similar_names,
         clippy::too_many_lines,
@@ -70,18 +96,47 @@ impl Sampler {
         // Calculate the ticks since machine uptime. This will be important
         // later for calculating per-process uptime. Because we capture this one
         // we will be slightly out of date with each subsequent iteration of the
-        // loop. We do not believe this to be an issue.
+        // loop. We do not believe this to be an issue. Note that we avoid
+        // rounding to whole seconds before converting to ticks, else sample
+        // periods under a second would see no change in uptime.
         let uptime_seconds: f64 = procfs::Uptime::new()
             .expect("could not query machine uptime")
             .uptime; // seconds since boot
-        let uptime_ticks: u64 = uptime_seconds.round() as u64 * self.ticks_per_second; // CPU-ticks since boot
+        let uptime_ticks: u64 = (uptime_seconds * self.ticks_per_second as f64).round() as u64; // CPU-ticks since boot
 
         // Every sample run we collect all the child processes rooted at the
         // parent. As noted by the procfs documentation is this done by
         // dereferencing the `/proc/<pid>/root` symlink.
         let mut pids: FxHashSet<i32> = FxHashSet::default();
         let mut processes: VecDeque<Process> = VecDeque::with_capacity(16); // an arbitrary smallish number
-        processes.push_back(Process::new(self.parent.pid())?);
+
+        // The parent may have exited, perhaps to be restarted by the target
+        // server. In that case there is no process tree to sample.
+        if let Ok(parent) = Process::new(self.parent.pid()) {
+            pids.insert(parent.pid());
+            processes.push_back(parent);
+        }
+        // Processes in the cgroup need not descend from the parent, we sample
+        // each of them and their children too. Membership changes as processes
+        // come and go so is read afresh each sample.
+        if let Some(cgroup) = &self.cgroup {
+            match std::fs::read_to_string(cgroup.join("cgroup.procs")) {
+                Ok(procs) => {
+                    for process in procs
+                        .lines()
+                        .filter_map(|line| line.trim().parse::<i32>().ok())
+                        .filter_map(|pid| Process::new(pid).ok())
+                    {
+                        if pids.insert(process.pid()) {
+                            processes.push_back(process);
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::debug!("unable to read cgroup {}: {err}", cgroup.display());
+                }
+            }
+        }
         while let Some(process) = processes.pop_back() {
             // Search for child processes. This is done by querying for every
             // thread of `process` and inspecting each child of the thread. Note
@@ -149,10 +204,21 @@ impl Sampler {
             }
             let stats = stats.unwrap(); // SAFETY: is_err check above
 
+            // If the user has restricted sampling to a subset of processes we
+            // skip those that match neither by exe basename nor by comm. Their
+            // children have already been queued for scanning above.
+            if let Some(filter) = &self.process_filter {
+                if !(filter.is_match(&basename) || filter.is_match(&stats.comm)) {
+                    continue;
+                }
+            }
+
             // Calculate process uptime. We have two pieces of information from
             // the kernel: computer uptime and process starttime relative to
-            // power-on of the computer.
-            let uptime: u64 = uptime_ticks - stats.starttime; // ticks
+            // power-on of the computer. A process that started in the same
+            // tick as our uptime reading may appear to start in the future,
+            // hence the saturation.
+            let uptime: u64 = uptime_ticks.saturating_sub(stats.starttime); // ticks
 
             // The times that the process and the processes' waited for children
             // have been scheduled in kernel and user space. We exclude cstime,
@@ -194,55 +260,251 @@ impl Sampler {
 
             let labels = [("pid", format!("{pid}")), ("exe", basename)];
 
-            // Number of pages that the process has in real memory.
-            gauge!("rss_bytes", rss as f64, &labels);
-            // Soft limit on RSS bytes, see RLIMIT_RSS in getrlimit(2).
-            gauge!("rsslim_bytes", rsslim as f64, &labels);
-            // The size in bytes of the process in virtual memory.
-            gauge!("vsize_bytes", vsize as f64, &labels);
-            // Number of threads this process has active.
-            gauge!("num_threads", stats.num_threads as f64, &labels);
+            if self.metrics.memory {
+                // Number of pages that the process has in real memory.
+                gauge!("rss_bytes", rss as f64, &labels);
+                // Soft limit on RSS bytes, see RLIMIT_RSS in getrlimit(2).
+                gauge!("rsslim_bytes", rsslim as f64, &labels);
+                // The size in bytes of the process in virtual memory.
+                gauge!("vsize_bytes", vsize as f64, &labels);
+            }
+            if self.metrics.process_count {
+                // Number of threads this process has active.
+                gauge!("num_threads", stats.num_threads as f64, &labels);
+            }
 
             total_rss += rss;
             total_processes += 1;
         }
 
-        gauge!("num_processes", total_processes as f64);
+        if self.metrics.process_count {
+            gauge!("num_processes", total_processes as f64);
+        }
         RSS_BYTES.store(total_rss, Ordering::Relaxed); // stored for the purposes of throttling
 
-        // Now we loop through our just collected samples and calculate CPU
-        // utilization. This require memory and we will now reference -- and
-        // update, when done -- the previous samples.
-        for (key, sample) in &samples {
-            let prev = self.previous_samples.remove(key).unwrap_or_default();
-
-            let uptime_diff = sample.uptime - prev.uptime; // CPU-ticks
-            let stime_diff: u64 = sample.stime - prev.stime; // CPU-ticks
-            let utime_diff: u64 = sample.utime - prev.utime; // CPU-ticks
-            let time_diff: u64 = (sample.stime + sample.utime) - (prev.stime + prev.utime); // CPU-ticks
-
-            let user_percentage =
-                percentage(utime_diff as f64, uptime_diff as f64, self.num_cores as f64);
-            let kernel_percentage =
-                percentage(stime_diff as f64, uptime_diff as f64, self.num_cores as f64);
-            let cpu_percentage =
-                percentage(time_diff as f64, uptime_diff as f64, self.num_cores as f64);
-
-            let labels = [
-                ("pid", format!("{pid}", pid = key.0)),
-                ("exe", key.1.clone()),
-            ];
-
-            gauge!("cpu_percentage", cpu_percentage, &labels);
-            gauge!("kernel_cpu_percentage", kernel_percentage, &labels);
-            gauge!("user_cpu_percentage", user_percentage, &labels);
+        if self.metrics.cpu {
+            // Now we loop through our just collected samples and calculate CPU
+            // utilization. This require memory and we will now reference -- and
+            // update, when done -- the previous samples.
+            for (key, sample) in &samples {
+                let prev = self.previous_samples.remove(key).unwrap_or_default();
+
+                let uptime_diff = sample.uptime - prev.uptime; // CPU-ticks
+                let stime_diff: u64 = sample.stime - prev.stime; // CPU-ticks
+                let utime_diff: u64 = sample.utime - prev.utime; // CPU-ticks
+                let time_diff: u64 = (sample.stime + sample.utime) - (prev.stime + prev.utime); // CPU-ticks
+
+                let user_percentage =
+                    percentage(utime_diff as f64, uptime_diff as f64, self.num_cores as f64);
+                let kernel_percentage =
+                    percentage(stime_diff as f64, uptime_diff as f64, self.num_cores as f64);
+                let cpu_percentage =
+                    percentage(time_diff as f64, uptime_diff as f64, self.num_cores as f64);
+
+                let labels = [
+                    ("pid", format!("{pid}", pid = key.0)),
+                    ("exe", key.1.clone()),
+                ];
+
+                gauge!("cpu_percentage", cpu_percentage, &labels);
+                gauge!("kernel_cpu_percentage", kernel_percentage, &labels);
+                gauge!("user_cpu_percentage", user_percentage, &labels);
+            }
+            self.previous_samples = samples;
+        }
+
+        if self.metrics.host {
+            self.sample_host(uptime_ticks)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sample host-wide CPU, memory and load along with lading's own CPU and
+    /// memory consumption. These metrics do not describe the target but help
+    /// to explain noise in target metrics: a contended host or a saturated
+    /// load generator will both skew results. All metrics emitted here carry a
+    /// `scope` label of either `host` or `lading` and are never labeled with a
+    /// target `pid`.
+    #[allow(
+        clippy::similar_names,
+        clippy::too_many_lines,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn sample_host(&mut self, uptime_ticks: u64) -> Result<(), Error> {
+        let host_labels = [("scope", String::from("host"))];
+
+        // The aggregate "cpu" line of /proc/stat sums ticks across all cores,
+        // so the percentages here are of total host capacity, not of a single
+        // core as with per-process metrics.
+        let stat = procfs::KernelStats::new()?;
+        let cpu = HostCpuSample::from(&stat.total);
+        let percentages = cpu.percentages_since(&self.previous_host_cpu);
+        gauge!("host_cpu_percentage", percentages.busy, &host_labels);
+        gauge!("host_user_cpu_percentage", percentages.user, &host_labels);
+        gauge!(
+            "host_kernel_cpu_percentage",
+            percentages.system,
+            &host_labels
+        );
+        gauge!(
+            "host_iowait_cpu_percentage",
+            percentages.iowait,
+            &host_labels
+        );
+        gauge!("host_steal_cpu_percentage", percentages.steal, &host_labels);
+        self.previous_host_cpu = cpu;
+
+        let meminfo = procfs::Meminfo::new()?;
+        gauge!(
+            "host_memory_total_bytes",
+            meminfo.mem_total as f64,
+            &host_labels
+        );
+        gauge!(
+            "host_memory_free_bytes",
+            meminfo.mem_free as f64,
+            &host_labels
+        );
+        if let Some(available) = meminfo.mem_available {
+            gauge!(
+                "host_memory_available_bytes",
+                available as f64,
+                &host_labels
+            );
         }
-        self.previous_samples = samples;
+        gauge!(
+            "host_memory_cached_bytes",
+            meminfo.cached as f64,
+            &host_labels
+        );
+
+        let loadavg = procfs::LoadAverage::new()?;
+        gauge!("host_load_average_1m", f64::from(loadavg.one), &host_labels);
+        gauge!(
+            "host_load_average_5m",
+            f64::from(loadavg.five),
+            &host_labels
+        );
+        gauge!(
+            "host_load_average_15m",
+            f64::from(loadavg.fifteen),
+            &host_labels
+        );
+
+        // Lading's own consumption. If lading is CPU bound it will not be
+        // able to hold the configured load against the target and the results
+        // of the experiment are suspect.
+        let lading_labels = [("scope", String::from("lading"))];
+        let stats = Process::myself()?.stat()?;
+        let sample = Sample {
+            utime: stats.utime,
+            stime: stats.stime,
+            uptime: uptime_ticks.saturating_sub(stats.starttime),
+        };
+        let prev = &self.previous_lading_sample;
+        let uptime_diff = sample.uptime.saturating_sub(prev.uptime) as f64;
+        let utime_diff = sample.utime.saturating_sub(prev.utime) as f64;
+        let stime_diff = sample.stime.saturating_sub(prev.stime) as f64;
+        let num_cores = self.num_cores as f64;
+        gauge!(
+            "lading_cpu_percentage",
+            percentage(utime_diff + stime_diff, uptime_diff, num_cores),
+            &lading_labels
+        );
+        gauge!(
+            "lading_kernel_cpu_percentage",
+            percentage(stime_diff, uptime_diff, num_cores),
+            &lading_labels
+        );
+        gauge!(
+            "lading_user_cpu_percentage",
+            percentage(utime_diff, uptime_diff, num_cores),
+            &lading_labels
+        );
+        gauge!(
+            "lading_rss_bytes",
+            (stats.rss * self.page_size) as f64,
+            &lading_labels
+        );
+        gauge!(
+            "lading_num_threads",
+            stats.num_threads as f64,
+            &lading_labels
+        );
+        self.previous_lading_sample = sample;
 
         Ok(())
     }
 }
 
+/// A summary of the aggregate CPU line of /proc/stat, in CPU-ticks.
+#[derive(Debug, Default, Clone, Copy)]
+struct HostCpuSample {
+    busy: u64,
+    user: u64,
+    system: u64,
+    iowait: u64,
+    steal: u64,
+    total: u64,
+}
+
+impl From<&procfs::CpuTime> for HostCpuSample {
+    fn from(cpu: &procfs::CpuTime) -> Self {
+        let iowait = cpu.iowait.unwrap_or_default();
+        let steal = cpu.steal.unwrap_or_default();
+        // Guest time is already accounted for in user and nice time, so we do
+        // not add it to the total.
+        let total = cpu.user
+            + cpu.nice
+            + cpu.system
+            + cpu.idle
+            + iowait
+            + cpu.irq.unwrap_or_default()
+            + cpu.softirq.unwrap_or_default()
+            + steal;
+        Self {
+            busy: total - cpu.idle - iowait,
+            user: cpu.user + cpu.nice,
+            system: cpu.system,
+            iowait,
+            steal,
+            total,
+        }
+    }
+}
+
+impl HostCpuSample {
+    /// Host CPU utilization between `prev` and this sample, as percentages of
+    /// total host capacity. Should the counters go backwards, as when `prev`
+    /// is from before a reboot, the affected percentages are zero.
+    #[allow(clippy::cast_precision_loss)]
+    fn percentages_since(&self, prev: &Self) -> HostCpuPercentages {
+        let total_diff = self.total.saturating_sub(prev.total) as f64;
+        let host_percentage = |now: u64, then: u64| -> f64 {
+            percentage(now.saturating_sub(then) as f64, total_diff, 1.0)
+        };
+        HostCpuPercentages {
+            busy: host_percentage(self.busy, prev.busy),
+            user: host_percentage(self.user, prev.user),
+            system: host_percentage(self.system, prev.system),
+            iowait: host_percentage(self.iowait, prev.iowait),
+            steal: host_percentage(self.steal, prev.steal),
+        }
+    }
+}
+
+/// Host CPU utilization between two [`HostCpuSample`]s.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct HostCpuPercentages {
+    busy: f64,
+    user: f64,
+    system: f64,
+    iowait: f64,
+    steal: f64,
+}
+
 #[inline]
 fn percentage(delta_ticks: f64, delta_time: f64, num_cores: f64) -> f64 {
     // Takes (heavy) inspiration from Datadog Agent, see
@@ -258,3 +520,67 @@ fn percentage(delta_ticks: f64, delta_time: f64, num_cores: f64) -> f64 {
 
     overall_percentage.clamp(0.0, 100.0 * num_cores)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_cpu_percentages() {
+        let prev = HostCpuSample {
+            busy: 100,
+            user: 50,
+            system: 30,
+            iowait: 10,
+            steal: 0,
+            total: 1_000,
+        };
+        let now = HostCpuSample {
+            busy: 400,
+            user: 250,
+            system: 110,
+            iowait: 30,
+            steal: 10,
+            total: 2_000,
+        };
+
+        assert_eq!(
+            now.percentages_since(&prev),
+            HostCpuPercentages {
+                busy: 30.0,
+                user: 20.0,
+                system: 8.0,
+                iowait: 2.0,
+                steal: 1.0,
+            }
+        );
+        // No ticks elapsed, or counters that went backwards, are not usage.
+        assert_eq!(now.percentages_since(&now), HostCpuPercentages::default());
+        assert_eq!(prev.percentages_since(&now), HostCpuPercentages::default());
+    }
+
+    #[test]
+    fn follow_keeps_host_samples() {
+        let pid = std::process::id();
+        let mut sampler = Sampler::new(pid, Metrics::default(), None, None).unwrap();
+        sampler.sample().unwrap();
+        let host_cpu = sampler.previous_host_cpu;
+        let lading_uptime = sampler.previous_lading_sample.uptime;
+        assert!(host_cpu.total > 0);
+        assert!(!sampler.previous_samples.is_empty());
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let followed = sampler.follow(child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        followed.unwrap();
+        assert_eq!(sampler.parent.pid(), i32::try_from(child.id()).unwrap());
+        assert!(sampler.previous_samples.is_empty());
+        assert_eq!(sampler.previous_host_cpu.total, host_cpu.total);
+        assert_eq!(sampler.previous_lading_sample.uptime, lading_uptime);
+    }
+}
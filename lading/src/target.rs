@@ -7,34 +7,64 @@
 //!
 //! Lading supports two types of targets, binary and process ID (PID) targets.
 //! In binary target mode, lading will launch a child process and shut it down
-//! cleanly by signaling SIGTERM to it. If the target crashes this is also
-//! detected and lading does a controlled shutdown.
+//! cleanly by signaling it, SIGTERM unless configured otherwise by
+//! [`StopPolicy`]. If the target crashes this is also
+//! detected and lading does a controlled shutdown, unless a [`RestartPolicy`]
+//! is configured in which case lading restarts the target and broadcasts its
+//! new PID.
 //!
 //! In PID target mode, lading will follow along with a running process. This is
 //! intended to enable containerized targets. In this mode, the target process
 //! should run until lading has exited. Lading will exit with an error if the
 //! watched process terminates early.
+//!
+//! A PID target may instead be discovered at runtime, see [`discovery`]. A
+//! target discovered by cgroup is followed for as long as the cgroup has
+//! processes, rather than for as long as any one process lives.
+//!
+//! In either mode a [`readiness::Config`] may be set, in which case the target
+//! PID is only broadcast once the target passes its readiness check.
+//!
+//! The output of a binary target may be scanned for error storms, see
+//! [`scan`].
 
 use std::{
+    future::Future,
     io,
     num::NonZeroU32,
+    os::unix::process::ExitStatusExt,
     path::PathBuf,
     process::{ExitStatus, Stdio},
     sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
-use metrics::gauge;
+use metrics::{counter, gauge, increment_gauge};
 use nix::{
     errno::Errno,
-    sys::signal::{kill, SIGTERM},
+    sys::signal::{kill, Signal, SIGKILL, SIGTERM},
     unistd::Pid,
 };
 use rustc_hash::FxHashMap;
-use tokio::process::Command;
-use tracing::{error, info};
+use tokio::{
+    fs,
+    process::{Child, Command},
+    sync::{mpsc, oneshot},
+    time::{sleep, timeout, Instant},
+};
+use tracing::{error, info, warn};
 
 pub use crate::common::{Behavior, Output};
-use crate::{common::stdio, observer::RSS_BYTES, signals::Shutdown};
+use crate::{
+    common::{stdio, stdio_append},
+    observer::RSS_BYTES,
+    signals::Shutdown,
+};
+
+pub mod discovery;
+pub mod limits;
+pub mod readiness;
+pub mod scan;
 
 /// Expose the process' current RSS consumption, allowing abstractions to be
 /// built on top in the Target implementation.
@@ -107,7 +137,7 @@ pub enum Error {
     /// Unable to create PidFd from raw PID
     #[error("unable to create PidFd: {0}")]
     PidConversion(io::Error),
-    /// SIGTERM error
+    /// Unable to signal the target to stop
     #[error("unable to terminate target process: {0}")]
     SigTerm(Errno),
     /// The target PID does not exist or is invalid
@@ -116,6 +146,22 @@ pub enum Error {
     /// The target process exited unexpectedly
     #[error("target exited unexpectedly: {0:?}")]
     TargetExited(Option<ExitStatus>),
+    /// See [`crate::target::readiness::Error`] for details.
+    #[error(transparent)]
+    Readiness(#[from] readiness::Error),
+    /// See [`crate::target::limits::Error`] for details.
+    #[error(transparent)]
+    Limits(#[from] limits::Error),
+    /// See [`crate::target::discovery::Error`] for details.
+    #[error(transparent)]
+    Discovery(#[from] discovery::Error),
+    /// See [`crate::target::scan::Error`] for details.
+    #[error(transparent)]
+    Scan(#[from] scan::Error),
+    /// The target's output matched a pattern configured to abort the
+    /// experiment
+    #[error("target output matched abort pattern {0}")]
+    OutputAbort(String),
 }
 
 /// Configuration for PID target mode
@@ -124,6 +170,8 @@ pub enum Error {
 pub struct PidConfig {
     /// PID to watch
     pub pid: NonZeroU32,
+    /// Readiness check that must pass before load begins.
+    pub readiness: Option<readiness::Config>,
 }
 
 /// Configuration for binary launch mode
@@ -141,6 +189,57 @@ pub struct BinaryConfig {
     pub environment_variables: FxHashMap<String, String>,
     /// Manages stderr, stdout of the target sub-process.
     pub output: Output,
+    /// Scan the target sub-process' stdout and stderr, counting lines and
+    /// pattern matches.
+    pub output_scan: Option<scan::Config>,
+    /// Restart the target sub-process if it exits before lading shuts down.
+    /// If unset any early exit of the target is an error.
+    pub restart_policy: Option<RestartPolicy>,
+    /// Readiness check that must pass before load begins. The check is made
+    /// again each time the target is restarted.
+    pub readiness: Option<readiness::Config>,
+    /// How the target sub-process is stopped when lading shuts down.
+    pub stop: StopPolicy,
+    /// Resource limits applied to the target sub-process.
+    pub limits: limits::Config,
+}
+
+/// Policy for stopping a binary target when lading shuts down
+///
+/// The time from signaling the target to its exit is recorded as
+/// `target_shutdown_duration_seconds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StopPolicy {
+    /// The signal sent to the target to begin its shutdown.
+    pub signal: Signal,
+    /// The time the target has to exit after `signal` before it is sent
+    /// SIGKILL. If unset lading waits indefinitely, subject to its own
+    /// maximum shutdown delay.
+    pub grace_period: Option<Duration>,
+}
+
+impl Default for StopPolicy {
+    fn default() -> Self {
+        Self {
+            signal: SIGTERM,
+            grace_period: None,
+        }
+    }
+}
+
+/// Policy for restarting a binary target that exits early
+///
+/// The delay before each restart starts at `initial_backoff` and doubles with
+/// every restart, up to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// The maximum number of times the target will be restarted. Once
+    /// exhausted the next exit of the target is an error.
+    pub max_restarts: u32,
+    /// The delay before the first restart.
+    pub initial_backoff: Duration,
+    /// The upper bound on the delay between restarts.
+    pub max_backoff: Duration,
 }
 
 /// Configuration for [`Server`]
@@ -150,6 +249,8 @@ pub enum Config {
     Pid(PidConfig),
     /// A binary that will be launched and managed directly
     Binary(BinaryConfig),
+    /// An existing process, managed externally, that is found at runtime
+    Discover(discovery::Config),
 }
 
 #[derive(Debug)]
@@ -200,6 +301,19 @@ impl Server {
     /// Function will return an error if no process with the given PID exists
     /// or if the process terminates while being watched.
     ///
+    /// # Discovery mode
+    ///
+    /// As PID watch mode, once the target process has been found. A target
+    /// discovered by cgroup is watched as a whole: should the followed process
+    /// exit while others remain in the cgroup the oldest remaining process is
+    /// followed and its PID transmitted.
+    ///
+    /// ## Errors
+    ///
+    /// Function will additionally return an error if no target process is
+    /// found within the discovery timeout, or if a target cgroup becomes
+    /// empty.
+    ///
     /// # Panics
     ///
     /// None are known.
@@ -217,11 +331,93 @@ impl Server {
             Config::Binary(config) => {
                 Self::execute_binary(config, pid_snd, self.shutdown).await?;
             }
+            Config::Discover(config) => {
+                Self::discover(config, pid_snd, self.shutdown).await?;
+            }
         }
 
         Ok(())
     }
 
+    /// Find a process running elsewhere on the system and watch it as if its
+    /// PID had been given. A target discovered by cgroup is watched by
+    /// [`Server::watch_cgroup`] instead.
+    async fn discover(
+        config: discovery::Config,
+        pid_snd: TargetPidSender,
+        mut shutdown: Shutdown,
+    ) -> Result<(), Error> {
+        let pids = tokio::select! {
+            pids = discovery::discover(&config) => pids?,
+            _ = shutdown.recv() => {
+                info!("shutdown signal received");
+                return Ok(());
+            }
+        };
+        // Discovery only succeeds once a process is found.
+        let pid = pids[0];
+        if let discovery::Method::Cgroup(path) = config.method {
+            return Self::watch_cgroup(path, pid, config.readiness, pid_snd, shutdown).await;
+        }
+        let config = PidConfig {
+            pid: NonZeroU32::new(pid).ok_or(Error::PidNotFound(pid))?,
+            readiness: config.readiness,
+        };
+        Self::watch(config, pid_snd, shutdown).await
+    }
+
+    /// Watch the processes of a cgroup managed elsewhere on the system,
+    /// following `pid`. Processes may come and go, should the followed process
+    /// exit the oldest remaining process is followed in its place and its PID
+    /// transmitted. lading will report an error if the cgroup empties before
+    /// the test completes.
+    async fn watch_cgroup(
+        path: PathBuf,
+        mut pid: u32,
+        readiness: Option<readiness::Config>,
+        pid_snd: TargetPidSender,
+        mut shutdown: Shutdown,
+    ) -> Result<(), Error> {
+        if let Some(readiness) = &readiness {
+            tokio::select! {
+                ready = readiness::wait(readiness, None) => ready?,
+                _ = shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+        }
+
+        pid_snd
+            .send(Some(pid))
+            .expect("target server unable to transmit PID, catastrophic failure");
+
+        loop {
+            tokio::select! {
+                _ = sleep(discovery::POLL_INTERVAL) => {
+                    let members = discovery::cgroup_members(&path)?;
+                    let Some(oldest) = members.first() else {
+                        error!("target cgroup {} has no processes", path.display());
+                        return Err(Error::TargetExited(None));
+                    };
+                    if !members.contains(&pid) {
+                        info!("target PID {pid} exited, following PID {oldest}");
+                        pid = *oldest;
+                        // Receivers that only wait for the first PID will have
+                        // dropped by now, so there may be nobody left to notify.
+                        if pid_snd.send(Some(pid)).is_err() {
+                            warn!("no receivers for target PID {pid}");
+                        }
+                    }
+                },
+                _ = shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
     /// Watch a process running elsewhere on the system. lading will report an
     /// error if the process ends before the test completes.
     async fn watch(
@@ -244,6 +440,16 @@ impl Server {
             return Err(Error::PidNotFound(config.pid.get()));
         }
 
+        if let Some(readiness) = &config.readiness {
+            tokio::select! {
+                ready = readiness::wait(readiness, None) => ready?,
+                _ = shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+        }
+
         pid_snd
             .send(Some(config.pid.get()))
             .expect("target server unable to transmit PID, catastrophic failure");
@@ -294,54 +500,480 @@ impl Server {
     }
 
     /// Execute a binary target. lading will attempt to gracefully terminate the
-    /// process after the test has completed.
+    /// process after the test has completed. If the target exits early and a
+    /// [`RestartPolicy`] is set the target is restarted and its new PID is
+    /// transmitted through `pid_snd`.
+    #[allow(clippy::too_many_lines)]
     async fn execute_binary(
         config: BinaryConfig,
         pid_snd: TargetPidSender,
         mut shutdown: Shutdown,
     ) -> Result<ExitStatus, Error> {
-        let mut target_cmd = Command::new(config.command);
-        target_cmd
-            .stdin(Stdio::null())
-            .stdout(stdio(&config.output.stdout))
-            .stderr(stdio(&config.output.stderr));
+        let mut restarts: u32 = 0;
+        let mut backoff = config
+            .restart_policy
+            .map(|policy| policy.initial_backoff)
+            .unwrap_or_default();
+        // The instant the target last exited, if it is being restarted.
+        let mut down_since: Option<Instant> = None;
+        let mut pid_sent = false;
+        // Output pumps of every run of the target report aborting pattern
+        // matches here.
+        let (abort_snd, mut abort_rcv) = mpsc::channel(1);
+        // The cgroup is shared by all runs of the target and removed once we
+        // return.
+        let cgroup = config
+            .limits
+            .cgroup
+            .as_ref()
+            .map(limits::Cgroup::create)
+            .transpose()?;
+
+        loop {
+            let (mut target_child, stdout_match) =
+                Self::spawn_binary(
+                    &config,
+                    down_since.is_some(),
+                    cgroup.as_ref(),
+                    &abort_snd,
+                    &shutdown,
+                )
+                .await?;
+            let target_id = target_child.id().expect("target must have PID");
+            if let Some(down_since) = down_since.take() {
+                increment_gauge!(
+                    "target_downtime_seconds",
+                    down_since.elapsed().as_secs_f64()
+                );
+            }
+
+            let target_wait = target_child.wait();
+            tokio::pin!(target_wait);
+
+            // If the target exits before it is ready we handle that exit like
+            // any other, skipping the PID broadcast.
+            let mut exit = None;
+            if let Some(readiness) = &config.readiness {
+                tokio::select! {
+                    ready = readiness::wait(readiness, stdout_match) => ready?,
+                    res = &mut target_wait => exit = Some(res),
+                    Some(pattern) = abort_rcv.recv() => {
+                        Self::terminate(target_id, target_wait, config.stop).await?;
+                        return Err(Error::OutputAbort(pattern));
+                    }
+                    _ = shutdown.recv() => {
+                        info!("shutdown signal received");
+                        return Self::terminate(target_id, target_wait, config.stop).await;
+                    }
+                }
+            }
+
+            let exit = if let Some(exit) = exit {
+                exit
+            } else {
+                if pid_sent {
+                    // Receivers that only wait for the first PID will have
+                    // dropped by now, so there may be nobody left to notify.
+                    if pid_snd.send(Some(target_id)).is_err() {
+                        warn!("no receivers for restarted target PID {target_id}");
+                    }
+                } else {
+                    pid_snd
+                        .send(Some(target_id))
+                        .expect("target server unable to transmit PID, catastrophic failure");
+                    pid_sent = true;
+                }
+
+                tokio::select! {
+                    res = &mut target_wait => res,
+                    Some(pattern) = abort_rcv.recv() => {
+                        Self::terminate(target_id, target_wait, config.stop).await?;
+                        return Err(Error::OutputAbort(pattern));
+                    }
+                    _ = shutdown.recv() => {
+                        info!("shutdown signal received");
+                        return Self::terminate(target_id, target_wait, config.stop).await;
+                    }
+                }
+            };
+            let status = match exit {
+                Ok(status) => status,
+                Err(e) => {
+                    error!("target exited unexpectedly; exit code unavailable ({})", e);
+                    return Err(Error::TargetExited(None));
+                }
+            };
+
+            record_exit(status, "run");
+
+            let policy = match config.restart_policy {
+                Some(policy) if restarts < policy.max_restarts => policy,
+                _ => {
+                    error!("target exited unexpectedly with code {}", status);
+                    return Err(Error::TargetExited(Some(status)));
+                }
+            };
+            down_since = Some(Instant::now());
+            restarts += 1;
+            warn!(
+                "target exited with code {status}, restarting in {backoff:?} ({restarts}/{max})",
+                max = policy.max_restarts
+            );
+
+            tokio::select! {
+                _ = sleep(backoff) => {},
+                _ = shutdown.recv() => {
+                    info!("shutdown signal received while target restart pending");
+                    return Ok(status);
+                }
+            }
+            counter!("target_restarts", 1);
+            backoff = std::cmp::min(backoff.saturating_mul(2), policy.max_backoff);
+        }
+    }
+
+    /// Spawn the binary target sub-process. If `restart` is set output logs
+    /// are appended to rather than truncated.
+    ///
+    /// If the target's readiness is signaled on stdout or its output is
+    /// scanned, the output is piped through lading. The returned receiver is
+    /// signaled once the readiness pattern matches. Matches of aborting scan
+    /// patterns are sent to `abort`.
+    ///
+    /// The target is placed in `cgroup`, if any, and pinned to its configured
+    /// CPUs before it execs.
+    async fn spawn_binary(
+        config: &BinaryConfig,
+        restart: bool,
+        cgroup: Option<&limits::Cgroup>,
+        abort: &mpsc::Sender<String>,
+        shutdown: &Shutdown,
+    ) -> Result<(Child, Option<oneshot::Receiver<()>>), Error> {
+        let output = if restart { stdio_append } else { stdio };
+        let ready_pattern = match &config.readiness {
+            Some(readiness::Config {
+                check: readiness::Check::StdoutRegex(pattern),
+                ..
+            }) => Some(regex::bytes::Regex::new(pattern).map_err(readiness::Error::from)?),
+            _ => None,
+        };
+        let scanner = config
+            .output_scan
+            .as_ref()
+            .map(scan::Scanner::new)
+            .transpose()?;
+        let pipe_stdout = ready_pattern.is_some() || scanner.is_some();
+        let pipe_stderr = scanner.is_some();
+
+        let mut target_cmd = Command::new(&config.command);
+        target_cmd.stdin(Stdio::null());
+        if pipe_stdout {
+            target_cmd.stdout(Stdio::piped());
+        } else {
+            target_cmd.stdout(output(&config.output.stdout));
+        }
+        if pipe_stderr {
+            target_cmd.stderr(Stdio::piped());
+        } else {
+            target_cmd.stderr(output(&config.output.stderr));
+        }
         if !config.inherit_environment {
             target_cmd.env_clear();
         }
         target_cmd
             .kill_on_drop(true)
-            .args(config.arguments)
+            .args(&config.arguments)
             .envs(config.environment_variables.iter());
+        let pre_exec = limits::PreExec::new(cgroup, config.limits.cpu_affinity.as_ref())?;
+        if !pre_exec.is_empty() {
+            // SAFETY: `PreExec::apply` only makes async-signal-safe calls.
+            unsafe {
+                target_cmd.pre_exec(move || pre_exec.apply());
+            }
+        }
         let mut target_child = target_cmd.spawn().map_err(Error::TargetSpawn)?;
-        let target_id = target_child.id().expect("target must have PID");
-        pid_snd
-            .send(Some(target_id))
-            .expect("target server unable to transmit PID, catastrophic failure");
-        drop(pid_snd);
 
-        tokio::select! {
-            res = target_child.wait() => {
-                match res {
-                    Ok(res) => {
-                        error!("target exited unexpectedly with code {}", res);
-                        Err(Error::TargetExited(Some(res)))
-                    },
-                    Err(e) => {
-                        error!("target exited unexpectedly; exit code unavailable ({})", e);
-                        Err(Error::TargetExited(None))
-                    },
+        let mut stdout_match = None;
+        if let Some(stdout) = target_child.stdout.take() {
+            let ready = ready_pattern.map(|pattern| {
+                let (snd, rcv) = oneshot::channel();
+                stdout_match = Some(rcv);
+                (pattern, snd)
+            });
+            let pump = scan::Pump {
+                stream: scan::Stream::Stdout,
+                log: open_log(&config.output.stdout, restart).await?,
+                scanner: scanner.clone(),
+                ready,
+                abort: abort.clone(),
+                shutdown: shutdown.clone(),
+            };
+            tokio::spawn(async move {
+                if let Err(err) = pump.run(stdout).await {
+                    warn!("unable to read target stdout: {err}");
                 }
-            },
-            _ = shutdown.recv() => {
-                info!("shutdown signal received");
-                // Note that `Child::kill` sends SIGKILL which is not what we
-                // want. We instead send SIGTERM so that the child has a chance
-                // to clean up.
-                let pid: Pid = Pid::from_raw(target_id.try_into().unwrap());
-                kill(pid, SIGTERM).map_err(Error::SigTerm)?;
-                let res = target_child.wait().await.map_err(Error::TargetWait)?;
-                Ok(res)
+            });
+        }
+        if let Some(stderr) = target_child.stderr.take() {
+            let pump = scan::Pump {
+                stream: scan::Stream::Stderr,
+                log: open_log(&config.output.stderr, restart).await?,
+                scanner,
+                ready: None,
+                abort: abort.clone(),
+                shutdown: shutdown.clone(),
+            };
+            tokio::spawn(async move {
+                if let Err(err) = pump.run(stderr).await {
+                    warn!("unable to read target stderr: {err}");
+                }
+            });
+        }
+        Ok((target_child, stdout_match))
+    }
+
+    /// Gracefully terminate the binary target sub-process, waiting on
+    /// `target_wait` for it to exit. If the target does not exit within the
+    /// grace period of `stop` it is sent SIGKILL.
+    async fn terminate<F>(
+        target_id: u32,
+        mut target_wait: F,
+        stop: StopPolicy,
+    ) -> Result<ExitStatus, Error>
+    where
+        F: Future<Output = io::Result<ExitStatus>> + Unpin,
+    {
+        // Note that `Child::kill` sends SIGKILL which is not what we want by
+        // default. We instead send a configurable signal, SIGTERM unless told
+        // otherwise, so that the child has a chance to clean up.
+        let pid: Pid = Pid::from_raw(target_id.try_into().unwrap());
+        let start = Instant::now();
+        info!("sending {} to target", stop.signal);
+        kill(pid, stop.signal).map_err(Error::SigTerm)?;
+
+        let res = if let Some(grace_period) = stop.grace_period {
+            if let Ok(res) = timeout(grace_period, &mut target_wait).await {
+                res
+            } else {
+                warn!("target did not exit within {grace_period:?}, sending SIGKILL");
+                counter!("target_killed", 1);
+                kill(pid, SIGKILL).map_err(Error::SigTerm)?;
+                target_wait.await
             }
+        } else {
+            target_wait.await
+        };
+        let res = res.map_err(Error::TargetWait)?;
+
+        gauge!(
+            "target_shutdown_duration_seconds",
+            start.elapsed().as_secs_f64()
+        );
+        record_exit(res, "shutdown");
+        info!("target exited with {res}");
+        Ok(res)
+    }
+}
+
+/// Open the log for target output piped through lading, appending if
+/// `restart` is set.
+async fn open_log(behavior: &Behavior, restart: bool) -> Result<Option<fs::File>, Error> {
+    match behavior {
+        Behavior::Quiet => Ok(None),
+        Behavior::Log(path) => fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(restart)
+            .truncate(!restart)
+            .open(path)
+            .await
+            .map(Some)
+            .map_err(|err| Error::Scan(err.into())),
+    }
+}
+
+/// Record the exit of the target sub-process, labeled by exit code and the
+/// `phase` of the run the exit happened in: `run` for early exits, `shutdown`
+/// for exits lading requested.
+fn record_exit(status: ExitStatus, phase: &'static str) {
+    let exit_code = status.code().map_or_else(
+        || format!("signal_{}", status.signal().unwrap_or_default()),
+        |code| code.to_string(),
+    );
+    counter!("target_exits", 1, "exit_code" => exit_code, "phase" => phase);
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::common::recorded::{self, install};
+
+    fn binary(command: &str, arguments: &[&str]) -> BinaryConfig {
+        BinaryConfig {
+            command: PathBuf::from(command),
+            arguments: arguments.iter().map(|a| (*a).to_owned()).collect(),
+            inherit_environment: false,
+            environment_variables: FxHashMap::default(),
+            output: Output {
+                stderr: Behavior::Quiet,
+                stdout: Behavior::Quiet,
+            },
+            output_scan: None,
+            restart_policy: None,
+            readiness: None,
+            stop: StopPolicy::default(),
+            limits: limits::Config::default(),
         }
     }
+
+    /// The PIDs transmitted so far.
+    fn transmitted(pid_rcv: &mut broadcast::Receiver<Option<u32>>) -> Vec<u32> {
+        std::iter::from_fn(|| pid_rcv.try_recv().ok())
+            .flatten()
+            .collect()
+    }
+
+    // The clock is paused so that backoff is exact, advancing only while
+    // lading sleeps between restarts.
+    #[tokio::test(start_paused = true)]
+    async fn restarts_with_backoff() {
+        install();
+        let mut config = binary("/bin/false", &[]);
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(30),
+        });
+        let (pid_snd, mut pid_rcv) = broadcast::channel(8);
+
+        let res = Server::execute_binary(config, pid_snd, Shutdown::new()).await;
+        assert!(
+            matches!(res, Err(Error::TargetExited(Some(status))) if status.code() == Some(1)),
+            "{res:?}"
+        );
+
+        // Every run of the target has its PID transmitted.
+        let pids = transmitted(&mut pid_rcv);
+        assert_eq!(pids.len(), 4, "{pids:?}");
+        let distinct: std::collections::HashSet<_> = pids.iter().collect();
+        assert_eq!(distinct.len(), 4, "{pids:?}");
+
+        assert_eq!(recorded::counter("target_restarts", &[]), Some(3));
+        assert_eq!(
+            recorded::counter("target_exits", &[("exit_code", "1"), ("phase", "run")]),
+            Some(4)
+        );
+        // Backoff doubles from 20ms, capped at 30ms: 20ms, 30ms, 30ms.
+        let downtime = recorded::gauge("target_downtime_seconds", &[]).unwrap();
+        assert!((downtime - 0.08).abs() < 1e-3, "{downtime}");
+    }
+
+    #[tokio::test]
+    async fn exit_without_restart_policy_is_error() {
+        install();
+        let (pid_snd, mut pid_rcv) = broadcast::channel(8);
+
+        let res = Server::execute_binary(binary("/bin/false", &[]), pid_snd, Shutdown::new()).await;
+        assert!(matches!(res, Err(Error::TargetExited(Some(_)))), "{res:?}");
+        assert_eq!(transmitted(&mut pid_rcv).len(), 1);
+        assert_eq!(recorded::counter("target_restarts", &[]), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_while_restart_pending() {
+        install();
+        let mut config = binary("/bin/false", &[]);
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            initial_backoff: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(60),
+        });
+        let (pid_snd, mut pid_rcv) = broadcast::channel(8);
+        let shutdown = Shutdown::new();
+
+        let run = tokio::spawn(Server::execute_binary(config, pid_snd, shutdown.clone()));
+        // Wait for the target to exit, the restart is then pending. Spinning
+        // rather than sleeping keeps the paused clock from advancing.
+        pid_rcv.recv().await.unwrap();
+        let early_exit = [("exit_code", "1"), ("phase", "run")];
+        while recorded::counter("target_exits", &early_exit).is_none() {
+            tokio::task::yield_now().await;
+        }
+        shutdown.signal().unwrap();
+
+        let status = run.await.unwrap().unwrap();
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(recorded::counter("target_restarts", &[]), None);
+    }
+
+    /// Spawn `script` in a shell, returning once it prints a line.
+    async fn spawn_ready(script: &str) -> Child {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(script)
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).await.unwrap();
+        child
+    }
+
+    #[tokio::test]
+    async fn stop_escalates_to_sigkill() {
+        install();
+        // SIGTERM stays ignored across the exec.
+        let mut child = spawn_ready("trap '' TERM; echo ready; exec sleep 30").await;
+        let stop = StopPolicy {
+            signal: SIGTERM,
+            grace_period: Some(Duration::from_millis(100)),
+        };
+
+        let target_id = child.id().unwrap();
+        let status = Server::terminate(target_id, Box::pin(child.wait()), stop)
+            .await
+            .unwrap();
+        assert_eq!(status.signal(), Some(SIGKILL as i32));
+        assert_eq!(recorded::counter("target_killed", &[]), Some(1));
+        assert_eq!(
+            recorded::counter(
+                "target_exits",
+                &[("exit_code", "signal_9"), ("phase", "shutdown")]
+            ),
+            Some(1)
+        );
+        let duration = recorded::gauge("target_shutdown_duration_seconds", &[]).unwrap();
+        assert!(duration >= 0.1, "{duration}");
+    }
+
+    #[tokio::test]
+    async fn stop_sends_configured_signal() {
+        install();
+        // Only SIGINT gets a clean exit, SIGTERM would kill the shell.
+        let mut child =
+            spawn_ready("trap 'exit 0' INT; echo ready; while :; do sleep 0.01; done").await;
+        let stop = StopPolicy {
+            signal: Signal::SIGINT,
+            grace_period: Some(Duration::from_secs(10)),
+        };
+
+        let target_id = child.id().unwrap();
+        let status = Server::terminate(target_id, Box::pin(child.wait()), stop)
+            .await
+            .unwrap();
+        assert_eq!(status.code(), Some(0));
+        assert_eq!(recorded::counter("target_killed", &[]), None);
+        assert_eq!(
+            recorded::counter("target_exits", &[("exit_code", "0"), ("phase", "shutdown")]),
+            Some(1)
+        );
+        let duration = recorded::gauge("target_shutdown_duration_seconds", &[]).unwrap();
+        assert!(duration < 10.0, "{duration}");
+    }
 }
@@ -0,0 +1,390 @@
+//! Target discovery
+//!
+//! In orchestrated setups the target's PID is rarely known in advance. This
+//! module finds a running target by one of a handful of methods -- a pidfile,
+//! a process name or command line pattern, or membership of a cgroup -- waiting
+//! up to a timeout for the target to appear. Several processes may match, in
+//! which case the oldest is taken as the target. The observer samples the
+//! target and all its descendants, so in the common case of a container the
+//! whole process tree is followed. Matches outside that tree are not observed
+//! and are warned about; discover by cgroup to follow them. A cgroup's
+//! membership is followed for the whole run: the observer samples every member
+//! and the target is only considered to have exited once the cgroup is empty.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use metrics::gauge;
+use nix::{sys::signal::kill, unistd::Pid};
+use regex::Regex;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, warn};
+
+use super::readiness;
+
+/// The interval between attempts to discover the target, and between checks
+/// of a cgroup's membership.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors produced by target discovery
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The target did not appear in the configured time
+    #[error("target not found after {0:?}")]
+    Timeout(Duration),
+    /// The process pattern is not a valid regular expression
+    #[error("invalid process pattern: {0}")]
+    Regex(#[from] regex::Error),
+    /// Discovery method is not supported on this operating system
+    #[error("target discovery by {0} is only supported on Linux")]
+    Unsupported(&'static str),
+}
+
+/// The method by which the target is discovered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    /// Read the target PID from this file.
+    Pidfile(PathBuf),
+    /// Match processes whose name, `comm`, matches this regular expression.
+    ProcessName(String),
+    /// Match processes whose command line, arguments joined by spaces,
+    /// matches this regular expression.
+    Cmdline(String),
+    /// Match all processes in this cgroup directory.
+    Cgroup(PathBuf),
+}
+
+/// Configuration for discovery target mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// How the target is found.
+    pub method: Method,
+    /// The maximum time to wait for the target to appear. If exceeded the run
+    /// fails.
+    pub timeout: Duration,
+    /// Readiness check that must pass, once the target is found, before load
+    /// begins.
+    pub readiness: Option<readiness::Config>,
+}
+
+/// A candidate target process
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    pid: i32,
+    /// Start time of the process in ticks since boot, zero if unknown.
+    starttime: u64,
+}
+
+/// Wait for the target to appear, returning the PIDs of all matching
+/// processes, oldest first
+///
+/// # Errors
+///
+/// Function will return an error if the method's pattern is invalid, if the
+/// method is unsupported on this system or if no target appears within the
+/// configured timeout.
+pub(crate) async fn discover(config: &Config) -> Result<Vec<u32>, Error> {
+    let pattern = match &config.method {
+        Method::ProcessName(pattern) | Method::Cmdline(pattern) => Some(Regex::new(pattern)?),
+        Method::Pidfile(_) | Method::Cgroup(_) => None,
+    };
+
+    let search = async {
+        loop {
+            let pids = oldest_first(candidates(&config.method, pattern.as_ref())?);
+            if let Some(target) = pids.first() {
+                gauge!("target_discovered_processes", pids.len() as f64);
+                info!(
+                    "discovered {count} candidate target process(es), following PID {target}",
+                    count = pids.len(),
+                );
+                if !matches!(config.method, Method::Cgroup(_)) {
+                    let unrelated: Vec<u32> = pids[1..]
+                        .iter()
+                        .copied()
+                        .filter(|pid| !descends_from(*pid, *target))
+                        .collect();
+                    if !unrelated.is_empty() {
+                        warn!(
+                            "processes {unrelated:?} match but do not descend from PID {target} \
+                             and will not be observed, discover by cgroup to follow them"
+                        );
+                    }
+                }
+                return Ok::<_, Error>(pids);
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    };
+
+    timeout(config.timeout, search)
+        .await
+        .map_err(|_| Error::Timeout(config.timeout))?
+}
+
+/// The PIDs of the processes in the cgroup at `path`, oldest first. The list
+/// is empty if the cgroup has no members or no longer exists.
+///
+/// # Errors
+///
+/// Function will return an error if cgroup discovery is unsupported on this
+/// system.
+pub(crate) fn cgroup_members(path: &Path) -> Result<Vec<u32>, Error> {
+    let members = oldest_first(candidates(&Method::Cgroup(path.to_path_buf()), None)?);
+    gauge!("target_discovered_processes", members.len() as f64);
+    Ok(members)
+}
+
+/// Order candidates oldest first. The oldest is most likely the ancestor of
+/// the others.
+fn oldest_first(mut candidates: Vec<Candidate>) -> Vec<u32> {
+    candidates.sort_by_key(|c| (c.starttime, c.pid));
+    candidates
+        .into_iter()
+        .map(|c| u32::try_from(c.pid).expect("PIDs are positive"))
+        .collect()
+}
+
+/// Whether `pid` is `ancestor` or one of its descendants. A process whose
+/// ancestry cannot be read is taken not to descend from `ancestor`.
+fn descends_from(mut pid: u32, ancestor: u32) -> bool {
+    while pid != ancestor {
+        match parent(pid) {
+            Some(parent) if parent > 0 => pid = parent,
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(target_os = "linux")]
+fn parent(pid: u32) -> Option<u32> {
+    let pid = i32::try_from(pid).ok()?;
+    let stat = procfs::process::Process::new(pid)
+        .and_then(|p| p.stat())
+        .ok()?;
+    u32::try_from(stat.ppid).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn parent(_pid: u32) -> Option<u32> {
+    None
+}
+
+/// Search once for processes matching `method`. Processes that vanish or
+/// cannot be read mid-search are skipped.
+fn candidates(method: &Method, pattern: Option<&Regex>) -> Result<Vec<Candidate>, Error> {
+    match method {
+        Method::Pidfile(path) => Ok(read_pidfile(path)
+            .filter(|pid| kill(Pid::from_raw(*pid), None).is_ok())
+            .map(|pid| Candidate { pid, starttime: 0 })
+            .into_iter()
+            .collect()),
+        Method::ProcessName(_) | Method::Cmdline(_) | Method::Cgroup(_) => {
+            search_procfs(method, pattern)
+        }
+    }
+}
+
+fn read_pidfile(path: &Path) -> Option<i32> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => contents.trim().parse().ok().filter(|pid| *pid > 0),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            debug!("unable to read pidfile {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+// The signature is shared with the non-Linux stub, which always errors.
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_wraps)]
+fn search_procfs(method: &Method, pattern: Option<&Regex>) -> Result<Vec<Candidate>, Error> {
+    use procfs::process::{all_processes, Process};
+    use rustc_hash::FxHashSet;
+
+    let starttime = |pid: i32| -> Option<u64> {
+        Process::new(pid)
+            .and_then(|p| p.stat())
+            .map(|s| s.starttime)
+            .ok()
+    };
+
+    if let Method::Cgroup(path) = method {
+        let procs = match std::fs::read_to_string(path.join("cgroup.procs")) {
+            Ok(procs) => procs,
+            Err(err) => {
+                debug!("unable to read cgroup {}: {err}", path.display());
+                return Ok(Vec::new());
+            }
+        };
+        return Ok(procs
+            .lines()
+            .filter_map(|line| line.trim().parse::<i32>().ok())
+            .filter_map(|pid| starttime(pid).map(|starttime| Candidate { pid, starttime }))
+            .collect());
+    }
+
+    // Lading's command line may well contain the pattern being searched for,
+    // as may that of whatever launched lading. We exclude lading and all its
+    // ancestors from the search.
+    let mut excluded: FxHashSet<i32> = FxHashSet::default();
+    let mut pid = std::process::id();
+    while pid > 0 && excluded.insert(i32::try_from(pid).expect("PIDs are positive")) {
+        pid = parent(pid).unwrap_or(0);
+    }
+
+    let pattern = pattern.expect("process name and cmdline discovery require a pattern");
+    let processes = match all_processes() {
+        Ok(processes) => processes,
+        Err(err) => {
+            debug!("unable to list processes: {err}");
+            return Ok(Vec::new());
+        }
+    };
+    let mut candidates = Vec::new();
+    for process in processes.filter_map(Result::ok) {
+        if excluded.contains(&process.pid()) {
+            continue;
+        }
+        let Ok(stat) = process.stat() else {
+            continue;
+        };
+        let is_match = match method {
+            Method::ProcessName(_) => pattern.is_match(&stat.comm),
+            Method::Cmdline(_) => process
+                .cmdline()
+                .map_or(false, |cmdline| pattern.is_match(&cmdline.join(" "))),
+            Method::Pidfile(_) | Method::Cgroup(_) => unreachable!("handled above"),
+        };
+        if is_match {
+            candidates.push(Candidate {
+                pid: stat.pid,
+                starttime: stat.starttime,
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn search_procfs(method: &Method, _pattern: Option<&Regex>) -> Result<Vec<Candidate>, Error> {
+    Err(Error::Unsupported(match method {
+        Method::ProcessName(_) => "process name",
+        Method::Cmdline(_) => "command line",
+        Method::Cgroup(_) | Method::Pidfile(_) => "cgroup",
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process::Command};
+
+    use super::*;
+
+    #[test]
+    fn pidfile_parsing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("target.pid");
+        assert_eq!(read_pidfile(&path), None, "missing pidfile");
+
+        for (contents, expected) in [
+            ("1234", Some(1234)),
+            ("  1234\n", Some(1234)),
+            ("", None),
+            ("0", None),
+            ("-1", None),
+            ("12 34", None),
+            ("target", None),
+        ] {
+            fs::write(&path, contents).unwrap();
+            assert_eq!(read_pidfile(&path), expected, "pidfile {contents:?}");
+        }
+    }
+
+    #[test]
+    fn pidfile_of_live_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("target.pid");
+        let pid = std::process::id();
+        fs::write(&path, format!("{pid}\n")).unwrap();
+
+        let candidates = candidates(&Method::Pidfile(path), None).unwrap();
+        assert_eq!(oldest_first(candidates), vec![pid]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cgroup_members_oldest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let procs = dir.path().join("cgroup.procs");
+        assert!(
+            cgroup_members(dir.path()).unwrap().is_empty(),
+            "no cgroup.procs"
+        );
+
+        // Ensure the child starts in a later clock tick than this process.
+        std::thread::sleep(Duration::from_millis(50));
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = std::process::id();
+        // PIDs that are not running, or not PIDs at all, are skipped.
+        fs::write(
+            &procs,
+            format!("{child}\n4194304\n\n{pid}\n", child = child.id()),
+        )
+        .unwrap();
+        let members = cgroup_members(dir.path());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert_eq!(members.unwrap(), vec![pid, child.id()]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn descent() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = std::process::id();
+        let is_descendant = descends_from(child.id(), pid);
+        let is_ancestor = descends_from(pid, child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert!(descends_from(pid, pid));
+        assert!(is_descendant);
+        assert!(!is_ancestor);
+        assert!(!descends_from(4_194_304, pid), "no such process");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn empty_cgroup_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("cgroup.procs"), "").unwrap();
+        let config = Config {
+            method: Method::Cgroup(dir.path().to_path_buf()),
+            timeout: Duration::from_millis(250),
+            readiness: None,
+        };
+
+        assert!(matches!(
+            discover(&config).await,
+            Err(Error::Timeout(timeout)) if timeout == config.timeout
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_pattern_is_error() {
+        let config = Config {
+            method: Method::ProcessName("(".to_owned()),
+            timeout: Duration::from_millis(250),
+            readiness: None,
+        };
+
+        assert!(matches!(discover(&config).await, Err(Error::Regex(_))));
+    }
+}
@@ -0,0 +1,420 @@
+//! Resource limits for binary targets
+//!
+//! Experimental results vary with the resources available to the target. This
+//! module allows lading to place a binary target in a cgroup v2 of its own
+//! creation, with a CPU quota and a memory maximum, and to pin the target --
+//! and separately lading itself -- to a set of CPUs. Limits are applied to the
+//! target between fork and exec so the target never runs unconstrained. The
+//! limits actually applied are recorded as gauges.
+
+use std::{
+    fs, io,
+    num::{ParseFloatError, ParseIntError},
+    os::fd::{AsRawFd, OwnedFd},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use metrics::gauge;
+use nix::{
+    errno::Errno,
+    sched::{sched_setaffinity, CpuSet},
+    unistd::{self, Pid},
+};
+use tracing::{debug, info};
+
+/// The cgroup v2 CPU period, in microseconds, used when converting a quota in
+/// cores. This is the kernel default.
+const CPU_PERIOD_MICROS: u64 = 100_000;
+
+/// The smallest CPU quota, in microseconds, the kernel accepts in `cpu.max`.
+const MIN_CPU_QUOTA_MICROS: u64 = 1_000;
+
+/// Errors produced when applying resource limits
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Unable to create or configure the target cgroup
+    #[error("unable to configure cgroup {path}: {source}")]
+    Cgroup {
+        /// The cgroup file or directory that could not be written
+        path: PathBuf,
+        /// The underlying error
+        source: io::Error,
+    },
+    /// The cgroup parent is not part of a cgroup v2 hierarchy
+    #[error("{0} is not a cgroup v2 directory")]
+    NotCgroup2(PathBuf),
+    /// Unable to build or apply a CPU affinity mask
+    #[error("unable to set CPU affinity: {0}")]
+    Affinity(Errno),
+}
+
+/// Error produced when parsing a [`CpuList`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum CpuListParseError {
+    /// A CPU index is not a non-negative integer
+    #[error("invalid CPU index: {0}")]
+    Index(#[from] ParseIntError),
+    /// A range of CPUs ends before it starts
+    #[error("invalid CPU range {0}-{1}")]
+    Range(usize, usize),
+    /// A CPU index is beyond what an affinity mask can hold, `CPU_SETSIZE`
+    #[error("CPU {0} is out of range, CPUs must be less than {max}", max = CpuSet::count())]
+    OutOfRange(usize),
+    /// The list has no CPUs in it
+    #[error("CPU list is empty")]
+    Empty,
+}
+
+/// A list of CPUs in the format of cpuset(7), for example `0-3,6`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuList {
+    cpus: Vec<usize>,
+}
+
+impl CpuList {
+    /// The number of CPUs in this list.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.cpus.len()
+    }
+
+    /// Whether this list has no CPUs. Never true for a parsed list.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_empty()
+    }
+
+    fn cpu_set(&self) -> Result<CpuSet, Error> {
+        let mut cpu_set = CpuSet::new();
+        for cpu in &self.cpus {
+            cpu_set.set(*cpu).map_err(Error::Affinity)?;
+        }
+        Ok(cpu_set)
+    }
+}
+
+impl FromStr for CpuList {
+    type Err = CpuListParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut cpus = Vec::new();
+        for part in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if let Some((start, end)) = part.split_once('-') {
+                let start: usize = start.trim().parse()?;
+                let end: usize = end.trim().parse()?;
+                if end < start {
+                    return Err(CpuListParseError::Range(start, end));
+                }
+                if end >= CpuSet::count() {
+                    return Err(CpuListParseError::OutOfRange(end));
+                }
+                cpus.extend(start..=end);
+            } else {
+                let cpu = part.parse()?;
+                if cpu >= CpuSet::count() {
+                    return Err(CpuListParseError::OutOfRange(cpu));
+                }
+                cpus.push(cpu);
+            }
+        }
+        if cpus.is_empty() {
+            return Err(CpuListParseError::Empty);
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(Self { cpus })
+    }
+}
+
+/// Error produced when building a [`CpuQuota`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum CpuQuotaError {
+    /// The number of cores is not a number
+    #[error("invalid number of cores: {0}")]
+    Parse(#[from] ParseFloatError),
+    /// The number of cores is not finite or too small for the kernel to accept
+    #[error(
+        "CPU quota must be a finite number of cores, at least {min}, got {0}",
+        min = MIN_CPU_QUOTA_MICROS as f64 / CPU_PERIOD_MICROS as f64
+    )]
+    OutOfRange(f64),
+}
+
+/// CPU quota for a cgroup, see `cpu.max` in the cgroup v2 documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuQuota {
+    /// Microseconds of CPU time the cgroup may consume per `period_micros`.
+    pub quota_micros: u64,
+    /// The accounting period, in microseconds.
+    pub period_micros: u64,
+}
+
+impl CpuQuota {
+    /// A quota of `cores` CPUs, fractional cores allowed.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if `cores` is not finite or is below the
+    /// smallest quota the kernel accepts, one hundredth of a core.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn from_cores(cores: f64) -> Result<Self, CpuQuotaError> {
+        let quota_micros = (cores * CPU_PERIOD_MICROS as f64).round();
+        if !quota_micros.is_finite() || quota_micros < MIN_CPU_QUOTA_MICROS as f64 {
+            return Err(CpuQuotaError::OutOfRange(cores));
+        }
+        Ok(Self {
+            quota_micros: quota_micros as u64,
+            period_micros: CPU_PERIOD_MICROS,
+        })
+    }
+}
+
+impl FromStr for CpuQuota {
+    type Err = CpuQuotaError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::from_cores(input.trim().parse()?)
+    }
+}
+
+/// Configuration for the cgroup lading creates for a binary target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupConfig {
+    /// The cgroup v2 directory under which lading creates the target's cgroup.
+    /// The `cpu` and `memory` controllers must be available to it.
+    pub parent: PathBuf,
+    /// The CPU quota of the target's cgroup.
+    pub cpu_quota: Option<CpuQuota>,
+    /// The maximum memory, in bytes, of the target's cgroup.
+    pub memory_max: Option<u64>,
+}
+
+/// Resource limits for a binary target
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Place the target in a lading-created cgroup with these limits.
+    pub cgroup: Option<CgroupConfig>,
+    /// Pin the target to these CPUs. If unset the target inherits lading's own
+    /// CPU affinity.
+    pub cpu_affinity: Option<CpuList>,
+}
+
+/// A cgroup created by lading for the target, removed on drop
+#[derive(Debug)]
+pub(crate) struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create the target's cgroup beneath `config.parent` and apply the
+    /// configured limits, recording the limits the kernel reports back.
+    pub(crate) fn create(config: &CgroupConfig) -> Result<Self, Error> {
+        if !config.parent.join("cgroup.controllers").exists() {
+            return Err(Error::NotCgroup2(config.parent.clone()));
+        }
+
+        // Controllers must be enabled in the parent for limits to be set in
+        // the child. This may fail if they already are or if the parent is
+        // managed elsewhere, in which case writing the limits below will tell
+        // us whether we're in trouble.
+        let mut controllers = Vec::new();
+        if config.cpu_quota.is_some() {
+            controllers.push("+cpu");
+        }
+        if config.memory_max.is_some() {
+            controllers.push("+memory");
+        }
+        if !controllers.is_empty() {
+            let subtree_control = config.parent.join("cgroup.subtree_control");
+            if let Err(err) = fs::write(&subtree_control, controllers.join(" ")) {
+                debug!(
+                    "unable to enable controllers in {}: {err}",
+                    subtree_control.display()
+                );
+            }
+        }
+
+        let path = config
+            .parent
+            .join(format!("lading-target-{}", std::process::id()));
+        fs::create_dir_all(&path).map_err(|source| Error::Cgroup {
+            path: path.clone(),
+            source,
+        })?;
+        let cgroup = Self { path };
+
+        if let Some(quota) = config.cpu_quota {
+            cgroup.write(
+                "cpu.max",
+                &format!("{} {}", quota.quota_micros, quota.period_micros),
+            )?;
+            let applied = cgroup.read("cpu.max")?;
+            let mut fields = applied.split_whitespace();
+            if let (Some(Ok(quota)), Some(Ok(period))) = (
+                fields.next().map(str::parse::<u64>),
+                fields.next().map(str::parse::<u64>),
+            ) {
+                gauge!(
+                    "target_cgroup_cpu_quota_cores",
+                    quota as f64 / period as f64
+                );
+            }
+        }
+        if let Some(memory_max) = config.memory_max {
+            cgroup.write("memory.max", &memory_max.to_string())?;
+            if let Ok(applied) = cgroup.read("memory.max")?.parse::<u64>() {
+                gauge!("target_cgroup_memory_max_bytes", applied as f64);
+            }
+        }
+        info!("created target cgroup {}", cgroup.path.display());
+        Ok(cgroup)
+    }
+
+    /// Open the cgroup's `cgroup.procs` for writing. Writing "0" to the
+    /// returned file moves the writing process into the cgroup, which is safe
+    /// to do between fork and exec.
+    pub(crate) fn procs(&self) -> Result<OwnedFd, Error> {
+        let path = self.path.join("cgroup.procs");
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .map(OwnedFd::from)
+            .map_err(|source| Error::Cgroup { path, source })
+    }
+
+    fn write(&self, file: &str, contents: &str) -> Result<(), Error> {
+        let path = self.path.join(file);
+        fs::write(&path, contents).map_err(|source| Error::Cgroup { path, source })
+    }
+
+    fn read(&self, file: &str) -> Result<String, Error> {
+        let path = self.path.join(file);
+        fs::read_to_string(&path)
+            .map(|contents| contents.trim().to_string())
+            .map_err(|source| Error::Cgroup { path, source })
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // A cgroup can only be removed once it has no processes in it. The
+        // target may not have been reaped yet, so this is best effort.
+        if let Err(err) = fs::remove_dir(&self.path) {
+            debug!("unable to remove cgroup {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// Limits applied to the target sub-process between fork and exec
+#[derive(Debug)]
+pub(crate) struct PreExec {
+    cgroup_procs: Option<OwnedFd>,
+    cpu_set: Option<CpuSet>,
+}
+
+impl PreExec {
+    pub(crate) fn new(
+        cgroup: Option<&Cgroup>,
+        cpu_affinity: Option<&CpuList>,
+    ) -> Result<Self, Error> {
+        let cgroup_procs = cgroup.map(Cgroup::procs).transpose()?;
+        let cpu_set = cpu_affinity.map(CpuList::cpu_set).transpose()?;
+        if let Some(cpu_affinity) = cpu_affinity {
+            gauge!("target_cpu_affinity_count", cpu_affinity.len() as f64);
+        }
+        Ok(Self {
+            cgroup_procs,
+            cpu_set,
+        })
+    }
+
+    /// Whether there is anything to apply.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cgroup_procs.is_none() && self.cpu_set.is_none()
+    }
+
+    /// Apply limits to the calling process. This function is called in the
+    /// child after fork and so must only make async-signal-safe calls: it does
+    /// not allocate.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        if let Some(procs) = &self.cgroup_procs {
+            unistd::write(procs.as_raw_fd(), b"0")?;
+        }
+        if let Some(cpu_set) = &self.cpu_set {
+            sched_setaffinity(Pid::from_raw(0), cpu_set)?;
+        }
+        Ok(())
+    }
+}
+
+/// Pin the calling thread, and every thread it subsequently creates, to
+/// `cpus`. Call this before lading's runtime is built to pin lading itself.
+///
+/// # Errors
+///
+/// Function will error if the CPU list is not valid for this host.
+pub fn pin_current_thread(cpus: &CpuList) -> Result<(), Error> {
+    sched_setaffinity(Pid::from_raw(0), &cpus.cpu_set()?).map_err(Error::Affinity)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn cpu_list_parses_ranges_and_singletons() {
+        let cpus = CpuList::from_str("0-2, 6,1").unwrap();
+        assert_eq!(cpus.cpus, vec![0, 1, 2, 6]);
+    }
+
+    #[test]
+    fn cpu_list_rejects_bad_input() {
+        assert_eq!(CpuList::from_str(""), Err(CpuListParseError::Empty));
+        assert_eq!(
+            CpuList::from_str("3-1"),
+            Err(CpuListParseError::Range(3, 1))
+        );
+        assert!(matches!(
+            CpuList::from_str("a"),
+            Err(CpuListParseError::Index(_))
+        ));
+        let max = CpuSet::count();
+        assert_eq!(
+            CpuList::from_str("0-999999999"),
+            Err(CpuListParseError::OutOfRange(999_999_999))
+        );
+        assert_eq!(
+            CpuList::from_str(&max.to_string()),
+            Err(CpuListParseError::OutOfRange(max))
+        );
+        assert!(CpuList::from_str(&format!("0-{}", max - 1)).is_ok());
+    }
+
+    #[test]
+    fn cpu_quota_from_cores() {
+        assert_eq!(
+            CpuQuota::from_str("1.5"),
+            Ok(CpuQuota {
+                quota_micros: 150_000,
+                period_micros: CPU_PERIOD_MICROS,
+            })
+        );
+        assert_eq!(
+            CpuQuota::from_str("0.01").map(|quota| quota.quota_micros),
+            Ok(MIN_CPU_QUOTA_MICROS)
+        );
+        for cores in ["0", "-1", "0.001", "NaN", "inf"] {
+            assert!(
+                matches!(CpuQuota::from_str(cores), Err(CpuQuotaError::OutOfRange(_))),
+                "{cores}"
+            );
+        }
+        assert!(matches!(
+            CpuQuota::from_str("many"),
+            Err(CpuQuotaError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+//! Target readiness checks
+//!
+//! Many targets take some time after launch before they are able to accept
+//! load, binding listeners or loading configuration. Load pushed into the
+//! target before then only produces connection errors, skewing results. A
+//! readiness check is polled until it passes or a timeout elapses. The target
+//! PID is not broadcast -- and so no generator begins and the warmup clock does
+//! not start -- until the check passes.
+
+use std::{net::SocketAddr, os::unix::fs::FileTypeExt, path::PathBuf, time::Duration};
+
+use hyper::{client::Client, Uri};
+use tokio::{
+    fs,
+    net::TcpStream,
+    sync::oneshot,
+    time::{sleep, timeout},
+};
+use tracing::{debug, info};
+
+/// The interval between attempts of a polling readiness check.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors produced by readiness checks
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The target did not become ready in the configured time
+    #[error("target not ready after {0:?}")]
+    Timeout(Duration),
+    /// The stdout pattern is not a valid regular expression
+    #[error("invalid stdout readiness pattern: {0}")]
+    Regex(#[from] regex::Error),
+    /// The target's stdout is not available to lading
+    #[error("stdout readiness check is only supported for binary targets")]
+    StdoutUnavailable,
+    /// The target closed stdout before the pattern matched
+    #[error("target stdout closed before readiness pattern matched")]
+    StdoutClosed,
+}
+
+/// The condition that signals the target is ready for load
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+    /// A TCP connection to this address succeeds.
+    Tcp(SocketAddr),
+    /// An HTTP GET of this URI returns a 2xx status.
+    Http(Uri),
+    /// A Unix socket exists at this path.
+    UnixSocket(PathBuf),
+    /// A line written by the target to stdout matches this regular expression.
+    StdoutRegex(String),
+}
+
+/// Configuration for target readiness
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The check that must pass before load begins.
+    pub check: Check,
+    /// The maximum time to wait for the check to pass. If exceeded the run
+    /// fails.
+    pub timeout: Duration,
+}
+
+/// Wait for the target to become ready
+///
+/// Polling checks are attempted every [`POLL_INTERVAL`]. The stdout check
+/// instead waits on `stdout_match`, signaled by the [`super::scan::Pump`]
+/// reading the target's stdout.
+///
+/// # Errors
+///
+/// Function will return an error if the check does not pass within the
+/// configured timeout or if a stdout check is requested but no `stdout_match`
+/// is available.
+pub(crate) async fn wait(
+    config: &Config,
+    stdout_match: Option<oneshot::Receiver<()>>,
+) -> Result<(), Error> {
+    let check = async {
+        match &config.check {
+            Check::Tcp(addr) => loop {
+                if TcpStream::connect(addr).await.is_ok() {
+                    return Ok(());
+                }
+                sleep(POLL_INTERVAL).await;
+            },
+            Check::Http(uri) => {
+                let client = Client::new();
+                loop {
+                    match client.get(uri.clone()).await {
+                        Ok(response) if response.status().is_success() => return Ok(()),
+                        Ok(response) => debug!("readiness check returned {}", response.status()),
+                        Err(err) => debug!("readiness check failed: {err}"),
+                    }
+                    sleep(POLL_INTERVAL).await;
+                }
+            }
+            Check::UnixSocket(path) => loop {
+                if let Ok(metadata) = fs::metadata(path).await {
+                    if metadata.file_type().is_socket() {
+                        return Ok(());
+                    }
+                }
+                sleep(POLL_INTERVAL).await;
+            },
+            Check::StdoutRegex(_) => match stdout_match {
+                Some(stdout_match) => stdout_match.await.map_err(|_| Error::StdoutClosed),
+                None => Err(Error::StdoutUnavailable),
+            },
+        }
+    };
+
+    timeout(config.timeout, check)
+        .await
+        .map_err(|_| Error::Timeout(config.timeout))??;
+    info!("target is ready");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, Server, StatusCode,
+    };
+    use regex::bytes::Regex;
+    use tokio::{
+        net::{TcpListener, UnixListener},
+        sync::{mpsc, oneshot},
+    };
+
+    use super::{wait, Check, Config, Error};
+    use crate::{
+        signals::Shutdown,
+        target::scan::{Pump, Stream},
+    };
+
+    fn config(check: Check) -> Config {
+        Config {
+            check,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Serve every request with `status` on an ephemeral port.
+    fn serve(status: StatusCode) -> SocketAddr {
+        let service = make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |_| async move {
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = status;
+                Ok::<_, Infallible>(response)
+            }))
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        wait(&config(Check::Tcp(addr)), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn http() {
+        let addr = serve(StatusCode::OK);
+        let uri = format!("http://{addr}/ready").parse().unwrap();
+        wait(&config(Check::Http(uri)), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("target.sock");
+        let check = config(Check::UnixSocket(path.clone()));
+        // The socket appears only after polling has begun.
+        let bind = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            UnixListener::bind(&path).unwrap()
+        };
+        let (ready, _listener) = tokio::join!(wait(&check, None), bind);
+        ready.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stdout_regex() {
+        let (ready, stdout_match) = oneshot::channel();
+        let (abort, _) = mpsc::channel(1);
+        let pump = Pump {
+            stream: Stream::Stdout,
+            log: None,
+            scanner: None,
+            ready: Some((Regex::new("listening on \\d+").unwrap(), ready)),
+            abort,
+            shutdown: Shutdown::new(),
+        };
+        let output: &[u8] = b"starting\nlistening on 8080\nrunning\n";
+        let check = config(Check::StdoutRegex(String::from("listening on \\d+")));
+        let (ready, pumped) = tokio::join!(wait(&check, Some(stdout_match)), pump.run(output));
+        pumped.unwrap();
+        ready.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stdout_regex_unmatched() {
+        let (ready, stdout_match) = oneshot::channel();
+        let (abort, _) = mpsc::channel(1);
+        let pump = Pump {
+            stream: Stream::Stdout,
+            log: None,
+            scanner: None,
+            ready: Some((Regex::new("listening on \\d+").unwrap(), ready)),
+            abort,
+            shutdown: Shutdown::new(),
+        };
+        let output: &[u8] = b"starting\nshutting down\n";
+        let check = config(Check::StdoutRegex(String::from("listening on \\d+")));
+        let (ready, pumped) = tokio::join!(wait(&check, Some(stdout_match)), pump.run(output));
+        pumped.unwrap();
+        assert!(matches!(ready, Err(Error::StdoutClosed)));
+
+        assert!(matches!(
+            wait(&check, None).await,
+            Err(Error::StdoutUnavailable)
+        ));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let timeout = Duration::from_millis(300);
+        // Nothing listens on a port just released, nor answers 2xx here, nor
+        // is a socket at a path that does not exist.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let unavailable = serve(StatusCode::SERVICE_UNAVAILABLE);
+        let dir = tempfile::tempdir().unwrap();
+        for check in [
+            Check::Tcp(addr),
+            Check::Http(format!("http://{unavailable}/ready").parse().unwrap()),
+            Check::UnixSocket(dir.path().join("target.sock")),
+        ] {
+            let config = Config { check, timeout };
+            assert!(matches!(
+                wait(&config, None).await,
+                Err(Error::Timeout(elapsed)) if elapsed == timeout
+            ));
+        }
+    }
+}
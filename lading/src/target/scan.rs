@@ -0,0 +1,368 @@
+//! Target output scanning
+//!
+//! Error storms in the target are easy to miss without combing through its
+//! logs after the fact. When scanning is enabled the target's stdout and
+//! stderr are piped through lading, which counts lines per stream as
+//! `target_output_lines` and lines matching each configured pattern as
+//! `target_output_pattern_matches`. A pattern may be marked to abort the
+//! experiment on its first match. Output is still written to the configured
+//! [`crate::common::Behavior`] exactly as if it had not passed through lading.
+
+use std::{io, str::FromStr};
+
+use metrics::register_counter;
+use regex::bytes::Regex;
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, BufWriter},
+    sync::{mpsc, oneshot},
+};
+use tracing::error;
+
+use crate::signals::Shutdown;
+
+/// Errors produced while scanning target output
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// A pattern is not a valid regular expression
+    #[error("invalid output pattern: {0}")]
+    Regex(#[from] regex::Error),
+    /// Wrapper for [`std::io::Error`]
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Error produced when parsing a [`Pattern`]
+#[derive(thiserror::Error, Debug)]
+pub enum PatternParseError {
+    /// The pattern is not of the form NAME=REGEX
+    #[error("expected NAME=REGEX, got {0}")]
+    Format(String),
+    /// The pattern's regular expression is invalid
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+/// A pattern that target output lines are matched against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// The name of the pattern, used as the `pattern` label of its match
+    /// counter.
+    pub name: String,
+    /// The regular expression a line must match.
+    pub regex: String,
+    /// Abort the experiment if any line matches.
+    pub abort: bool,
+}
+
+impl FromStr for Pattern {
+    type Err = PatternParseError;
+
+    /// Parse a non-aborting pattern from NAME=REGEX.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let Some((name, regex)) = input.split_once('=') else {
+            return Err(PatternParseError::Format(input.to_string()));
+        };
+        if name.is_empty() {
+            return Err(PatternParseError::Format(input.to_string()));
+        }
+        Regex::new(regex)?;
+        Ok(Self {
+            name: name.to_string(),
+            regex: regex.to_string(),
+            abort: false,
+        })
+    }
+}
+
+/// Configuration for target output scanning
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Patterns matched against every line of target output.
+    pub patterns: Vec<Pattern>,
+}
+
+/// A stream of target output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn as_str(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// The compiled form of [`Config`]
+#[derive(Debug, Clone)]
+pub(crate) struct Scanner {
+    patterns: Vec<(Pattern, Regex)>,
+}
+
+impl Scanner {
+    pub(crate) fn new(config: &Config) -> Result<Self, Error> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| Ok((pattern.clone(), Regex::new(&pattern.regex)?)))
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { patterns })
+    }
+}
+
+/// Copies one stream of target output to its log, line by line, inspecting
+/// each line along the way
+#[derive(Debug)]
+pub(crate) struct Pump {
+    pub(crate) stream: Stream,
+    /// Where the output is written, if anywhere.
+    pub(crate) log: Option<fs::File>,
+    /// Counts lines and pattern matches, if set.
+    pub(crate) scanner: Option<Scanner>,
+    /// Signaled on the first line matching the readiness pattern.
+    pub(crate) ready: Option<(Regex, oneshot::Sender<()>)>,
+    /// Sent the name of an aborting pattern on its first match.
+    pub(crate) abort: mpsc::Sender<String>,
+    /// Once signaled the log is flushed line by line, as lading may exit
+    /// before the target closes its output.
+    pub(crate) shutdown: Shutdown,
+}
+
+impl Pump {
+    /// Read `output` until the target closes it.
+    pub(crate) async fn run<R>(mut self, output: R) -> Result<(), Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let stream = self.stream.as_str();
+        let lines = self
+            .scanner
+            .as_ref()
+            .map(|_| register_counter!("target_output_lines", "stream" => stream));
+        let matches: Vec<_> = self
+            .scanner
+            .iter()
+            .flat_map(|scanner| scanner.patterns.iter())
+            .map(|(pattern, regex)| {
+                let counter = register_counter!(
+                    "target_output_pattern_matches",
+                    "stream" => stream,
+                    "pattern" => pattern.name.clone()
+                );
+                (pattern, regex, counter)
+            })
+            .collect();
+
+        // Writing each line straight to the file would cost a round trip to
+        // the blocking pool per line, and under an error storm the pump
+        // would fall behind and stall the target on a full pipe.
+        let mut log = self.log.take().map(BufWriter::new);
+        let mut reader = BufReader::new(output);
+        let mut line = Vec::with_capacity(256);
+        let mut shutting_down = false;
+        loop {
+            // A read interrupted by shutdown keeps what it read in `line` and
+            // continues where it left off.
+            let read = tokio::select! {
+                read = reader.read_until(b'\n', &mut line) => read?,
+                () = self.shutdown.recv(), if !shutting_down => {
+                    shutting_down = true;
+                    if let Some(log) = log.as_mut() {
+                        log.flush().await?;
+                    }
+                    continue;
+                }
+            };
+            if read == 0 && line.is_empty() {
+                break;
+            }
+            if let Some(log) = log.as_mut() {
+                log.write_all(&line).await?;
+                if shutting_down {
+                    log.flush().await?;
+                }
+            }
+            if let Some((pattern, _)) = &self.ready {
+                if pattern.is_match(&line) {
+                    let (_, ready) = self.ready.take().expect("checked above");
+                    // The receiver is gone if readiness timed out, which is
+                    // reported elsewhere.
+                    let _ = ready.send(());
+                }
+            }
+            if let Some(lines) = &lines {
+                lines.increment(1);
+            }
+            for (pattern, regex, counter) in &matches {
+                if !regex.is_match(&line) {
+                    continue;
+                }
+                counter.increment(1);
+                if pattern.abort {
+                    error!(
+                        "target {stream} matched abort pattern {name}: {line}",
+                        name = pattern.name,
+                        line = String::from_utf8_lossy(&line).trim_end()
+                    );
+                    // Only the first abort matters, later ones find the
+                    // channel full.
+                    let _ = self.abort.try_send(pattern.name.clone());
+                }
+            }
+            line.clear();
+        }
+        if let Some(log) = log.as_mut() {
+            log.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::common::recorded::{self, install};
+
+    #[test]
+    fn pattern_parses_name_and_regex() {
+        let pattern = Pattern::from_str("dropped=dropped \\d+ events").unwrap();
+        assert_eq!(pattern.name, "dropped");
+        assert_eq!(pattern.regex, "dropped \\d+ events");
+        assert!(!pattern.abort);
+
+        assert!(matches!(
+            Pattern::from_str("panic"),
+            Err(PatternParseError::Format(_))
+        ));
+        assert!(matches!(
+            Pattern::from_str("=panic"),
+            Err(PatternParseError::Format(_))
+        ));
+        assert!(matches!(
+            Pattern::from_str("bad=("),
+            Err(PatternParseError::Regex(_))
+        ));
+    }
+
+    fn scanner() -> Scanner {
+        let mut panic = Pattern::from_str("panic=^panic").unwrap();
+        panic.abort = true;
+        Scanner::new(&Config {
+            patterns: vec![
+                Pattern::from_str("dropped=dropped \\d+ events").unwrap(),
+                panic,
+            ],
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn pump_counts_lines_and_matches() {
+        install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stderr.log");
+        let (abort, mut abort_rcv) = mpsc::channel(1);
+        let pump = Pump {
+            stream: Stream::Stderr,
+            log: Some(fs::File::create(&path).await.unwrap()),
+            scanner: Some(scanner()),
+            ready: None,
+            abort,
+            shutdown: Shutdown::new(),
+        };
+        // The last line is unterminated.
+        let output: &[u8] = b"starting\ndropped 3 events\nrunning\ndropped 12 events";
+        pump.run(output).await.unwrap();
+
+        // Output is logged as is.
+        assert_eq!(std::fs::read(&path).unwrap(), output);
+        assert_eq!(
+            recorded::counter("target_output_lines", &[("stream", "stderr")]),
+            Some(4)
+        );
+        assert_eq!(
+            recorded::counter(
+                "target_output_pattern_matches",
+                &[("stream", "stderr"), ("pattern", "dropped")]
+            ),
+            Some(2)
+        );
+        assert_eq!(
+            recorded::counter(
+                "target_output_pattern_matches",
+                &[("stream", "stderr"), ("pattern", "panic")]
+            ),
+            Some(0)
+        );
+        assert!(abort_rcv.try_recv().is_err(), "nothing to abort on");
+    }
+
+    #[tokio::test]
+    async fn pump_aborts_on_match() {
+        install();
+        let (abort, mut abort_rcv) = mpsc::channel(1);
+        let pump = Pump {
+            stream: Stream::Stdout,
+            log: None,
+            scanner: Some(scanner()),
+            ready: None,
+            abort,
+            shutdown: Shutdown::new(),
+        };
+        let output: &[u8] = b"running\npanic: out of memory\npanic: again\n";
+        pump.run(output).await.unwrap();
+
+        assert_eq!(
+            recorded::counter(
+                "target_output_pattern_matches",
+                &[("stream", "stdout"), ("pattern", "panic")]
+            ),
+            Some(2)
+        );
+        // Every match is counted but only the first is sent.
+        assert_eq!(abort_rcv.try_recv().ok(), Some(String::from("panic")));
+        assert!(abort_rcv.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn pump_flushes_log_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stdout.log");
+        let (abort, _abort_rcv) = mpsc::channel(1);
+        let shutdown = Shutdown::new();
+        let pump = Pump {
+            stream: Stream::Stdout,
+            log: Some(fs::File::create(&path).await.unwrap()),
+            scanner: None,
+            ready: None,
+            abort,
+            shutdown: shutdown.clone(),
+        };
+        let (mut output, input) = tokio::io::duplex(64);
+        let pump = tokio::spawn(pump.run(input));
+
+        output.write_all(b"before\n").await.unwrap();
+        shutdown.signal().unwrap();
+        // Lines written once lading is shutting down reach the log at once,
+        // with the target's output still open.
+        output.write_all(b"during\n").await.unwrap();
+        let logged = async {
+            while std::fs::read(&path).unwrap() != b"before\nduring\n" {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), logged)
+            .await
+            .expect("log not flushed");
+
+        drop(output);
+        pump.await.unwrap().unwrap();
+    }
+}
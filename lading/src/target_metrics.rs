@@ -1,26 +1,34 @@
 //! Fetch metrics from the target process
 //!
 //! This module allows lading to fetch metrics from the target process and
-//! include them in the captures file.
+//! include them in the captures file. Metrics are either scraped from the
+//! target, see [`expvar`] and [`prometheus`], or pushed by it, see
+//! [`dogstatsd`] and [`otlp`]. All are recorded with a `target/` prefix.
 //!
 
 use serde::Deserialize;
 
 use crate::signals::Shutdown;
 
+pub mod dogstatsd;
 pub mod expvar;
+pub mod otlp;
 pub mod prometheus;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 /// Errors produced by [`Server`]
 pub enum Error {
     /// See [`crate::target_metrics::expvar::Error`] for details.
     Expvar(expvar::Error),
     /// See [`crate::target_metrics::prometheus::Error`] for details.
     Prometheus(prometheus::Error),
+    /// See [`crate::target_metrics::dogstatsd::Error`] for details.
+    Dogstatsd(dogstatsd::Error),
+    /// See [`crate::target_metrics::otlp::Error`] for details.
+    Otlp(otlp::Error),
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 /// Configuration for [`Server`]
 pub enum Config {
@@ -28,6 +36,10 @@ pub enum Config {
     Expvar(expvar::Config),
     /// See [`crate::target_metrics::prometheus::Config`] for details.
     Prometheus(prometheus::Config),
+    /// See [`crate::target_metrics::dogstatsd::Config`] for details.
+    Dogstatsd(dogstatsd::Config),
+    /// See [`crate::target_metrics::otlp::Config`] for details.
+    Otlp(otlp::Config),
 }
 
 /// The `target_metrics` server.
@@ -37,6 +49,10 @@ pub enum Server {
     Expvar(expvar::Expvar),
     /// See [`crate::target_metrics::prometheus::Prometheus`] for details.
     Prometheus(prometheus::Prometheus),
+    /// See [`crate::target_metrics::dogstatsd::Dogstatsd`] for details.
+    Dogstatsd(dogstatsd::Dogstatsd),
+    /// See [`crate::target_metrics::otlp::Otlp`] for details.
+    Otlp(otlp::Otlp),
 }
 
 impl Server {
@@ -52,6 +68,8 @@ impl Server {
             Config::Prometheus(conf) => {
                 Self::Prometheus(prometheus::Prometheus::new(conf, shutdown))
             }
+            Config::Dogstatsd(conf) => Self::Dogstatsd(dogstatsd::Dogstatsd::new(conf, shutdown)),
+            Config::Otlp(conf) => Self::Otlp(otlp::Otlp::new(conf, shutdown)),
         }
     }
 
@@ -72,6 +90,74 @@ impl Server {
         match self {
             Server::Expvar(inner) => inner.run().await.map_err(Error::Expvar),
             Server::Prometheus(inner) => inner.run().await.map_err(Error::Prometheus),
+            Server::Dogstatsd(inner) => inner.run().await.map_err(Error::Dogstatsd),
+            Server::Otlp(inner) => inner.run().await.map_err(Error::Otlp),
         }
     }
 }
+
+/// Cumulative histogram buckets of upper bound and count.
+type Buckets = Vec<(f64, f64)>;
+
+/// The quantiles recorded for target histograms unless configured otherwise.
+fn default_quantiles() -> Vec<f64> {
+    vec![0.5, 0.9, 0.99]
+}
+
+/// Estimate the `q` quantile from cumulative histogram `buckets` of upper
+/// bound and count, as Prometheus' `histogram_quantile` does.
+fn bucket_quantile(q: f64, buckets: &mut [(f64, f64)]) -> Option<f64> {
+    if !(0.0..=1.0).contains(&q) {
+        return None;
+    }
+    buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let &(upper, total) = buckets.last()?;
+    if upper != f64::INFINITY || buckets.len() < 2 || total <= 0.0 {
+        return None;
+    }
+    let rank = q * total;
+    let idx = buckets.iter().position(|(_, count)| *count >= rank)?;
+    if idx == buckets.len() - 1 {
+        // The quantile falls in the +Inf bucket, the best estimate is the
+        // upper bound of the highest finite bucket.
+        return Some(buckets[idx - 1].0);
+    }
+    let (end, count) = buckets[idx];
+    let (start, prev_count) = if idx == 0 {
+        (if end > 0.0 { 0.0 } else { end }, 0.0)
+    } else {
+        buckets[idx - 1]
+    };
+    if count <= prev_count {
+        return Some(end);
+    }
+    Some(start + (end - start) * ((rank - prev_count) / (count - prev_count)))
+}
+
+/// The value at quantile `q` of sorted `values`, by nearest rank.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn nearest_rank(q: f64, values: &[f64]) -> Option<f64> {
+    if values.is_empty() || !(0.0..=1.0).contains(&q) {
+        return None;
+    }
+    let rank = (q * values.len() as f64).ceil() as usize;
+    Some(values[rank.saturating_sub(1).min(values.len() - 1)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_quantiles() {
+        let values: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert_eq!(nearest_rank(0.5, &values), Some(5.0));
+        assert_eq!(nearest_rank(0.99, &values), Some(10.0));
+        assert_eq!(nearest_rank(0.0, &values), Some(1.0));
+        assert_eq!(nearest_rank(0.5, &[]), None);
+    }
+}
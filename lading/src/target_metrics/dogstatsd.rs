@@ -0,0 +1,447 @@
+//! `DogStatsD` target metrics receiver
+//!
+//! Many targets push their internal metrics as `DogStatsD` rather than expose
+//! them for scraping. This module listens for `DogStatsD` on a UDP or Unix
+//! datagram socket and records what it receives.
+//!
+//! Counters and gauges are recorded as received, a gauge value with a leading
+//! sign adjusting the gauge rather than setting it. Histogram, distribution and
+//! timing samples are aggregated for one second at a time and recorded as a
+//! gauge per configured quantile, with a `quantile` label, alongside a
+//! `.count` counter and `.sum` gauge. Sampled counters and distributions are
+//! scaled by their sample rate. Sets are recorded as a gauge of the number of
+//! distinct values seen in the second. Tags become labels, `key:value` tags
+//! split at the first colon, and every metric is labeled `target:dogstatsd`.
+//! Events and service checks are ignored. Lines that cannot be parsed are
+//! skipped and counted as `target_metrics_dogstatsd_parse_errors`.
+
+use std::{io, net::SocketAddr, path::PathBuf, time::Duration};
+
+use metrics::{counter, decrement_gauge, gauge, increment_gauge};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::Deserialize;
+use tokio::net::{UdpSocket, UnixDatagram};
+use tracing::{info, trace};
+
+use crate::signals::Shutdown;
+
+/// The period over which distributions and sets are aggregated.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`Dogstatsd`]
+pub enum Error {
+    /// Wrapper for [`std::io::Error`]
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Configuration for receiving `DogStatsD` target metrics
+pub struct Config {
+    /// The socket to listen on.
+    listen: Listen,
+    /// Quantiles recorded for each histogram, distribution and timer, each in
+    /// [0, 1].
+    #[serde(default = "super::default_quantiles")]
+    quantiles: Vec<f64>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The socket on which `DogStatsD` is received
+pub enum Listen {
+    /// A UDP socket bound to this address
+    Udp(SocketAddr),
+    /// A Unix datagram socket bound to this path. Any existing file at the
+    /// path is removed.
+    UnixDatagram(PathBuf),
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    /// Histograms, distributions and timers
+    Distribution,
+    Set,
+}
+
+/// Errors produced by [`parse_line`]
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseError {
+    /// The line has no name or no value
+    #[error("missing metric name or value")]
+    MissingValue,
+    /// The line has no type, or one that is not known
+    #[error("missing or unknown metric type")]
+    Type,
+    /// The sample rate is not a number in (0, 1]
+    #[error("sample rate must be in (0, 1]")]
+    SampleRate,
+}
+
+/// A metric line, which may carry several values
+#[derive(Debug, PartialEq)]
+struct Sample<'a> {
+    name: &'a str,
+    values: Vec<&'a str>,
+    kind: Kind,
+    sample_rate: f64,
+    labels: Labels,
+}
+
+/// Parse one line of a `DogStatsD` datagram:
+/// `name:value[:value...]|type[|@rate][|#tag,...][|...]`. Returns `Ok(None)`
+/// for events and service checks.
+fn parse_line(line: &str) -> Result<Option<Sample<'_>>, ParseError> {
+    if line.starts_with("_e{") || line.starts_with("_sc|") {
+        return Ok(None);
+    }
+    let mut fields = line.split('|');
+    let (name, values) = fields
+        .next()
+        .and_then(|field| field.split_once(':'))
+        .ok_or(ParseError::MissingValue)?;
+    if name.is_empty() || values.is_empty() {
+        return Err(ParseError::MissingValue);
+    }
+    let kind = match fields.next() {
+        Some("c") => Kind::Counter,
+        Some("g") => Kind::Gauge,
+        Some("h" | "d" | "ms") => Kind::Distribution,
+        Some("s") => Kind::Set,
+        _ => return Err(ParseError::Type),
+    };
+    let mut sample_rate = 1.0;
+    let mut labels = Vec::new();
+    for field in fields {
+        if let Some(rate) = field.strip_prefix('@') {
+            sample_rate = rate.parse().map_err(|_| ParseError::SampleRate)?;
+            if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+                return Err(ParseError::SampleRate);
+            }
+        } else if let Some(tags) = field.strip_prefix('#') {
+            labels.extend(tags.split(',').filter(|t| !t.is_empty()).map(|tag| {
+                let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+                (key.to_owned(), value.to_owned())
+            }));
+        }
+        // Container IDs, timestamps and other extensions are ignored.
+    }
+    Ok(Some(Sample {
+        name,
+        values: values.split(':').collect(),
+        kind,
+        sample_rate,
+        labels,
+    }))
+}
+
+/// The value at quantile `q` of `values` sorted by value, by nearest rank,
+/// each value standing for `weight` samples.
+fn weighted_rank(q: f64, values: &[(f64, f64)]) -> Option<f64> {
+    if values.is_empty() || !(0.0..=1.0).contains(&q) {
+        return None;
+    }
+    let rank = q * values.iter().map(|(_, weight)| weight).sum::<f64>();
+    let mut seen = 0.0;
+    values
+        .iter()
+        .find(|(_, weight)| {
+            seen += weight;
+            seen >= rank
+        })
+        .or(values.last())
+        .map(|(value, _)| *value)
+}
+
+/// Distributions and sets received since the last flush
+#[derive(Debug, Default)]
+struct Aggregates {
+    /// Values and the number of samples each stands for, the inverse of its
+    /// sample rate
+    distributions: FxHashMap<(String, Labels), Vec<(f64, f64)>>,
+    sets: FxHashMap<(String, Labels), FxHashSet<String>>,
+}
+
+impl Aggregates {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn flush(&mut self, quantiles: &[f64]) {
+        for ((name, labels), mut values) in self.distributions.drain() {
+            values.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for q in quantiles {
+                if let Some(value) = weighted_rank(*q, &values) {
+                    let mut labels = labels.clone();
+                    labels.push(("quantile".to_owned(), q.to_string()));
+                    gauge!(format!("target/{name}"), value, &labels);
+                }
+            }
+            let count: f64 = values.iter().map(|(_, weight)| weight).sum();
+            counter!(
+                format!("target/{name}.count"),
+                count.round() as u64,
+                &labels
+            );
+            gauge!(
+                format!("target/{name}.sum"),
+                values
+                    .iter()
+                    .map(|(value, weight)| value * weight)
+                    .sum::<f64>(),
+                &labels
+            );
+        }
+        for ((name, labels), values) in self.sets.drain() {
+            gauge!(format!("target/{name}"), values.len() as f64, &labels);
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Socket {
+    Udp(UdpSocket),
+    Unix(UnixDatagram),
+}
+
+impl Socket {
+    async fn bind(listen: &Listen) -> Result<Self, Error> {
+        Ok(match listen {
+            Listen::Udp(addr) => Self::Udp(UdpSocket::bind(addr).await?),
+            Listen::UnixDatagram(path) => {
+                // Sockets cannot be rebound if they existed previously.
+                let _ = std::fs::remove_file(path);
+                Self::Unix(UnixDatagram::bind(path)?)
+            }
+        })
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Udp(socket) => socket.recv(buf).await,
+            Self::Unix(socket) => socket.recv(buf).await,
+        }
+    }
+}
+
+/// The `Dogstatsd` target metrics implementation.
+#[derive(Debug)]
+pub struct Dogstatsd {
+    config: Config,
+    shutdown: Shutdown,
+}
+
+impl Dogstatsd {
+    /// Create a new [`Dogstatsd`] instance
+    ///
+    /// This is responsible for receiving metrics pushed by the target process
+    /// in the `DogStatsD` format.
+    ///
+    pub(crate) fn new(config: Config, shutdown: Shutdown) -> Self {
+        Self { config, shutdown }
+    }
+
+    /// Run this [`Dogstatsd`] to completion
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the socket cannot be bound or
+    /// receiving from it fails.
+    ///
+    /// # Panics
+    ///
+    /// None are known.
+    pub(crate) async fn run(mut self) -> Result<(), Error> {
+        info!("DogStatsD target metrics receiver running");
+        let socket = Socket::bind(&self.config.listen).await?;
+        let mut buf = vec![0; 65536];
+        let mut aggregates = Aggregates::default();
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                res = socket.recv(&mut buf) => {
+                    let n = res?;
+                    let errors = Self::record(&buf[..n], &mut aggregates);
+                    if errors > 0 {
+                        counter!("target_metrics_dogstatsd_parse_errors", errors);
+                    }
+                }
+                _ = flush.tick() => aggregates.flush(&self.config.quantiles),
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    aggregates.flush(&self.config.quantiles);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Record the metrics of a datagram, returning the number of lines that
+    /// could not be parsed.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn record(datagram: &[u8], aggregates: &mut Aggregates) -> u64 {
+        let mut errors = 0;
+        let datagram = String::from_utf8_lossy(datagram);
+        for line in datagram.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let mut sample = match parse_line(line) {
+                Ok(Some(sample)) => sample,
+                Ok(None) => continue,
+                Err(err) => {
+                    trace!("unparseable DogStatsD line, {err}: {line}");
+                    errors += 1;
+                    continue;
+                }
+            };
+            sample
+                .labels
+                .push(("target".to_owned(), "dogstatsd".to_owned()));
+            let name = sample.name;
+
+            if sample.kind == Kind::Set {
+                let key = (name.to_owned(), sample.labels);
+                let set = aggregates.sets.entry(key).or_default();
+                set.extend(sample.values.iter().map(|v| (*v).to_owned()));
+                continue;
+            }
+            for raw in &sample.values {
+                let Ok(value) = raw.parse::<f64>() else {
+                    errors += 1;
+                    continue;
+                };
+                match sample.kind {
+                    Kind::Counter => {
+                        let value = value / sample.sample_rate;
+                        if value < 0.0 || value > u64::MAX as f64 {
+                            errors += 1;
+                            continue;
+                        }
+                        counter!(
+                            format!("target/{name}"),
+                            value.round() as u64,
+                            &sample.labels
+                        );
+                    }
+                    // A signed value is a delta, as in StatsD.
+                    Kind::Gauge if raw.starts_with('+') => {
+                        increment_gauge!(format!("target/{name}"), value, &sample.labels);
+                    }
+                    Kind::Gauge if raw.starts_with('-') => {
+                        decrement_gauge!(format!("target/{name}"), -value, &sample.labels);
+                    }
+                    Kind::Gauge => gauge!(format!("target/{name}"), value, &sample.labels),
+                    Kind::Distribution => {
+                        let key = (name.to_owned(), sample.labels.clone());
+                        aggregates
+                            .distributions
+                            .entry(key)
+                            .or_default()
+                            .push((value, 1.0 / sample.sample_rate));
+                    }
+                    Kind::Set => unreachable!("handled above"),
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::recorded::{self, install};
+
+    #[test]
+    fn parses_metric_lines() {
+        let sample = parse_line("requests:1:2|c|@0.5|#env:prod,canary|T1656581400")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            sample,
+            Sample {
+                name: "requests",
+                values: vec!["1", "2"],
+                kind: Kind::Counter,
+                sample_rate: 0.5,
+                labels: vec![
+                    ("env".to_owned(), "prod".to_owned()),
+                    ("canary".to_owned(), String::new())
+                ],
+            }
+        );
+        assert_eq!(parse_line("_sc|redis|0"), Ok(None));
+        assert_eq!(parse_line("_e{5,4}:title|text"), Ok(None));
+        assert_eq!(parse_line("no_value|c"), Err(ParseError::MissingValue));
+        assert_eq!(parse_line(":1|c"), Err(ParseError::MissingValue));
+        assert_eq!(parse_line("no_type:1"), Err(ParseError::Type));
+        assert_eq!(parse_line("bad_type:1|x"), Err(ParseError::Type));
+        assert_eq!(parse_line("bad_rate:1|c|@2"), Err(ParseError::SampleRate));
+        assert_eq!(parse_line("bad_rate:1|c|@x"), Err(ParseError::SampleRate));
+    }
+
+    #[test]
+    fn weighted_ranks() {
+        let values = [(1.0, 2.0), (10.0, 1.0)];
+        assert_eq!(weighted_rank(0.5, &values), Some(1.0));
+        assert_eq!(weighted_rank(0.9, &values), Some(10.0));
+        assert_eq!(weighted_rank(0.0, &values), Some(1.0));
+        assert_eq!(weighted_rank(0.5, &[]), None);
+    }
+
+    #[tokio::test]
+    async fn records_received_metrics() {
+        install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dogstatsd.sock");
+        let shutdown = Shutdown::new();
+        let config = Config {
+            listen: Listen::UnixDatagram(path.clone()),
+            quantiles: vec![0.5],
+        };
+        let receiver = tokio::spawn(Dogstatsd::new(config, shutdown.clone()).run());
+        while !path.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let client = UnixDatagram::unbound().unwrap();
+        let datagram = "requests:3|c|#env:prod\n\
+                        requests:2|c|@0.5|#env:prod\n\
+                        queue:10|g\nqueue:+5|g\nqueue:-3|g\n\
+                        latency:1|d|@0.5\nlatency:10|d\n\
+                        users:a|s\nusers:b|s\nusers:a|s\n\
+                        _sc|redis|0\nnot a metric\n";
+        client.send_to(datagram.as_bytes(), &path).await.unwrap();
+        // Let the receiver take the datagram before it is shut down.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.signal().unwrap();
+        receiver.await.unwrap().unwrap();
+
+        let target = ("target", "dogstatsd");
+        assert_eq!(
+            recorded::counter("target/requests", &[("env", "prod"), target]),
+            Some(7)
+        );
+        assert_eq!(recorded::gauge("target/queue", &[target]), Some(12.0));
+        assert_eq!(
+            recorded::gauge("target/latency", &[("quantile", "0.5"), target]),
+            Some(1.0)
+        );
+        assert_eq!(
+            recorded::counter("target/latency.count", &[target]),
+            Some(3)
+        );
+        assert_eq!(recorded::gauge("target/latency.sum", &[target]), Some(12.0));
+        assert_eq!(recorded::gauge("target/users", &[target]), Some(2.0));
+        assert_eq!(
+            recorded::counter("target_metrics_dogstatsd_parse_errors", &[]),
+            Some(1)
+        );
+    }
+}
@@ -2,10 +2,20 @@
 //!
 //! This module scrapes Go expvar formatted metrics from the target software.
 //! The metrics are formatted as a JSON tree that is fetched over HTTP.
+//!
+//! Values are chosen either by `vars`, plain JSON Pointers whose numbers are
+//! recorded as gauges, or by `selectors`. A selector is a JSON Pointer in
+//! which any segment may be a wildcard, written `{label}`, that matches every
+//! key of an object or index of an array. The matched key is recorded as the
+//! value of label `label`. Each selector records its values as a gauge, a
+//! counter, or -- for arrays of numbers -- as a gauge per configured quantile,
+//! with a `quantile` label, alongside a `.count` gauge of the array's length.
+//! Every element of an array counts, so arrays that are padded, as the
+//! `memstats/PauseNs` ring buffer is, will skew its quantiles.
 
-use std::time::Duration;
+use std::{fmt, time::Duration};
 
-use metrics::gauge;
+use metrics::{absolute_counter, gauge};
 use serde::Deserialize;
 use serde_json::Value;
 use tracing::{error, info, trace};
@@ -20,14 +30,228 @@ pub enum Error {
     EarlyShutdown,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 /// Configuration for collecting Go Expvar based target metrics
 pub struct Config {
     /// URI to read expvars from
     uri: String,
-    /// Variable names to scrape
+    /// Variable names to scrape, as JSON Pointers, recorded as gauges
+    #[serde(default)]
     vars: Vec<String>,
+    /// Selectors of the values to scrape
+    #[serde(default)]
+    selectors: Vec<Selector>,
+    /// Quantiles recorded for selectors of kind `quantiles`, each in [0, 1].
+    #[serde(default = "super::default_quantiles")]
+    quantiles: Vec<f64>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// A selection of expvar values to scrape
+pub struct Selector {
+    /// JSON Pointer to the values, where a `{label}` segment matches any key
+    /// or index and records it as label `label`.
+    pointer: Pointer,
+    /// The metric name, `target/` prefixed. Defaults to the pointer without
+    /// its wildcard segments.
+    #[serde(default)]
+    name: Option<String>,
+    /// How the selected values are recorded.
+    #[serde(default)]
+    kind: Kind,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// How the values of a [`Selector`] are recorded
+pub enum Kind {
+    /// Record each number as a gauge.
+    #[default]
+    Gauge,
+    /// Record each non-negative number as a counter's total.
+    Counter,
+    /// Record each array of numbers by its quantiles.
+    Quantiles,
+}
+
+/// A segment of a [`Pointer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Matches exactly this object key or array index.
+    Key(String),
+    /// Matches any key or index, recorded as this label.
+    Wildcard(String),
+}
+
+/// A JSON Pointer that may contain wildcard segments
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pointer {
+    segments: Vec<Segment>,
+}
+
+/// Error produced when parsing a [`Pointer`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum PointerError {
+    /// Pointers must be empty or begin with a slash
+    #[error("JSON Pointer must begin with '/', got {0}")]
+    Relative(String),
+    /// A wildcard has no label name
+    #[error("wildcard in {0} has an empty label name")]
+    EmptyLabel(String),
+}
+
+impl TryFrom<String> for Pointer {
+    type Error = PointerError;
+
+    fn try_from(pointer: String) -> Result<Self, Self::Error> {
+        if pointer.is_empty() {
+            return Ok(Self {
+                segments: Vec::new(),
+            });
+        }
+        let Some(rest) = pointer.strip_prefix('/') else {
+            return Err(PointerError::Relative(pointer));
+        };
+        let mut segments = Vec::new();
+        for segment in rest.split('/') {
+            if let Some(label) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                if label.is_empty() {
+                    return Err(PointerError::EmptyLabel(pointer));
+                }
+                segments.push(Segment::Wildcard(label.to_string()));
+            } else {
+                segments.push(Segment::Key(segment.replace("~1", "/").replace("~0", "~")));
+            }
+        }
+        Ok(Self { segments })
+    }
+}
+
+impl fmt::Display for Pointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            match segment {
+                Segment::Key(key) => write!(f, "/{}", key.replace('~', "~0").replace('/', "~1"))?,
+                Segment::Wildcard(label) => write!(f, "/{{{label}}}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+impl Pointer {
+    /// The values in `json` this pointer selects, each with the labels of the
+    /// keys its wildcards matched.
+    fn select<'a>(&self, json: &'a Value) -> Vec<(Labels, &'a Value)> {
+        let mut selected = vec![(Vec::new(), json)];
+        for segment in &self.segments {
+            selected = selected
+                .into_iter()
+                .flat_map(|(labels, value)| {
+                    let children: Vec<(Option<String>, &Value)> = match (segment, value) {
+                        (Segment::Key(key), Value::Object(map)) => {
+                            map.get(key).map(|v| (None, v)).into_iter().collect()
+                        }
+                        (Segment::Key(key), Value::Array(array)) => key
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|idx| array.get(idx))
+                            .map(|v| (None, v))
+                            .into_iter()
+                            .collect(),
+                        (Segment::Wildcard(_), Value::Object(map)) => {
+                            map.iter().map(|(k, v)| (Some(k.clone()), v)).collect()
+                        }
+                        (Segment::Wildcard(_), Value::Array(array)) => array
+                            .iter()
+                            .enumerate()
+                            .map(|(idx, v)| (Some(idx.to_string()), v))
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    children.into_iter().map(move |(key, child)| {
+                        let mut labels = labels.clone();
+                        if let (Some(key), Segment::Wildcard(label)) = (key, segment) {
+                            labels.push((label.clone(), key));
+                        }
+                        (labels, child)
+                    })
+                })
+                .collect();
+        }
+        selected
+    }
+
+    /// The metric name of this pointer, its keys joined by slashes.
+    fn name(&self) -> String {
+        self.segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Key(key) => Some(key.as_str()),
+                Segment::Wildcard(_) => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Record the values of `selector` found in `json`.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn record(selector: &Selector, json: &Value, quantiles: &[f64]) {
+    let name = format!(
+        "target/{}",
+        selector
+            .name
+            .clone()
+            .unwrap_or_else(|| selector.pointer.name())
+    );
+    for (mut labels, value) in selector.pointer.select(json) {
+        labels.push(("source".to_owned(), "target_metrics/expvar".to_owned()));
+        match selector.kind {
+            Kind::Gauge => {
+                if let Some(value) = value.as_f64() {
+                    trace!("expvar: {name} = {value}");
+                    gauge!(name.clone(), value, &labels);
+                }
+            }
+            Kind::Counter => {
+                let value = value.as_u64().or_else(|| {
+                    value
+                        .as_f64()
+                        .filter(|v| *v >= 0.0 && *v <= u64::MAX as f64)
+                        .map(|v| v as u64)
+                });
+                if let Some(value) = value {
+                    trace!("expvar: {name} = {value}");
+                    absolute_counter!(name.clone(), value, &labels);
+                }
+            }
+            Kind::Quantiles => {
+                let Some(array) = value.as_array() else {
+                    continue;
+                };
+                let mut values: Vec<f64> = array.iter().filter_map(Value::as_f64).collect();
+                values.sort_by(f64::total_cmp);
+                for q in quantiles {
+                    if let Some(value) = super::nearest_rank(*q, &values) {
+                        let mut labels = labels.clone();
+                        labels.push(("quantile".to_owned(), q.to_string()));
+                        gauge!(name.clone(), value, &labels);
+                    }
+                }
+                gauge!(format!("{name}.count"), values.len() as f64, &labels);
+            }
+        }
+    }
 }
 
 /// The `Expvar` target metrics implementation.
@@ -83,6 +307,9 @@ impl Expvar {
                         gauge!(format!("target/{name}", name = var_name.trim_start_matches('/')), val, "source" => "target_metrics/expvar");
                     }
                 }
+                for selector in &self.config.selectors {
+                    record(selector, &json, &self.config.quantiles);
+                }
             }
         };
 
@@ -98,3 +325,112 @@ impl Expvar {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::common::recorded::{self, install};
+
+    fn pointer(s: &str) -> Pointer {
+        Pointer::try_from(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn wildcards_expand_into_labels() {
+        let json = json!({
+            "memstats": {
+                "Alloc": 1024,
+                "BySize": [
+                    {"Size": 0, "Mallocs": 3},
+                    {"Size": 8, "Mallocs": 5}
+                ]
+            },
+            "a/b": {"x": 1, "y": 2}
+        });
+
+        let alloc = pointer("/memstats/Alloc");
+        assert_eq!(alloc.select(&json), vec![(vec![], &json!(1024))]);
+        assert_eq!(alloc.name(), "memstats/Alloc");
+
+        let by_size = pointer("/memstats/BySize/{class}/Mallocs");
+        assert_eq!(
+            by_size.select(&json),
+            vec![
+                (vec![("class".to_owned(), "0".to_owned())], &json!(3)),
+                (vec![("class".to_owned(), "1".to_owned())], &json!(5)),
+            ]
+        );
+        assert_eq!(by_size.name(), "memstats/BySize/Mallocs");
+        assert_eq!(by_size.to_string(), "/memstats/BySize/{class}/Mallocs");
+
+        let escaped = pointer("/a~1b/{axis}");
+        assert_eq!(escaped.select(&json).len(), 2);
+        assert!(pointer("/missing/{x}").select(&json).is_empty());
+
+        assert!(matches!(
+            Pointer::try_from("memstats".to_string()),
+            Err(PointerError::Relative(_))
+        ));
+        assert!(matches!(
+            Pointer::try_from("/memstats/{}".to_string()),
+            Err(PointerError::EmptyLabel(_))
+        ));
+    }
+
+    #[test]
+    fn selectors_record_by_kind() {
+        install();
+        let json = json!({
+            "requests": {"get": 7, "put": 2.0},
+            "queue": {"depth": -3.5},
+            "latencies": [4, 1, 3, 2, "n/a", 5]
+        });
+        let source = ("source", "target_metrics/expvar");
+        let selector = |pointer_str: &str, name: Option<&str>, kind| Selector {
+            pointer: pointer(pointer_str),
+            name: name.map(str::to_owned),
+            kind,
+        };
+
+        record(
+            &selector("/requests/{method}", Some("http.requests"), Kind::Counter),
+            &json,
+            &[],
+        );
+        assert_eq!(
+            recorded::counter("target/http.requests", &[("method", "get"), source]),
+            Some(7)
+        );
+        assert_eq!(
+            recorded::counter("target/http.requests", &[("method", "put"), source]),
+            Some(2)
+        );
+        // Negative numbers are no counter's total.
+        record(&selector("/queue/depth", None, Kind::Counter), &json, &[]);
+        assert_eq!(recorded::counter("target/queue/depth", &[source]), None);
+
+        record(&selector("/queue/depth", None, Kind::Gauge), &json, &[]);
+        assert_eq!(recorded::gauge("target/queue/depth", &[source]), Some(-3.5));
+
+        record(
+            &selector("/latencies", None, Kind::Quantiles),
+            &json,
+            &[0.5, 1.0],
+        );
+        assert_eq!(
+            recorded::gauge("target/latencies", &[("quantile", "0.5"), source]),
+            Some(3.0)
+        );
+        assert_eq!(
+            recorded::gauge("target/latencies", &[("quantile", "1"), source]),
+            Some(5.0)
+        );
+        // Non-numeric elements are skipped.
+        assert_eq!(
+            recorded::gauge("target/latencies.count", &[source]),
+            Some(5.0)
+        );
+    }
+}
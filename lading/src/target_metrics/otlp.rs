@@ -0,0 +1,485 @@
+//! OTLP target metrics receiver
+//!
+//! Targets instrumented with OpenTelemetry push their internal metrics over
+//! OTLP. This module accepts OTLP/HTTP metric exports, protobuf encoded, on
+//! `/v1/metrics` and records what it receives.
+//!
+//! Gauges are recorded as gauges. Monotonic sums are recorded as counters,
+//! others as gauges, honoring their aggregation temporality. Histograms are
+//! recorded as a gauge per configured quantile, estimated from their buckets
+//! and carrying a `quantile` label, alongside a `.count` counter and `.sum`
+//! gauge. Summary quantiles are recorded as reported. Exponential histograms
+//! are recorded by their count and sum only. Data point attributes become
+//! labels, and every metric is labeled `target:otlp`. Requests that cannot be
+//! decoded are rejected and counted as `target_metrics_otlp_decode_errors`.
+
+use std::net::SocketAddr;
+
+use hyper::{
+    body, header,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use metrics::{absolute_counter, counter, decrement_gauge, gauge, increment_gauge};
+use opentelemetry_proto::tonic::{
+    collector::metrics::v1::{ExportMetricsServiceRequest, ExportMetricsServiceResponse},
+    common::v1::{any_value, KeyValue},
+    metrics::v1::{metric::Data, number_data_point, AggregationTemporality, NumberDataPoint},
+};
+use prost::Message;
+use serde::Deserialize;
+use tracing::{error, info, trace};
+
+use crate::signals::Shutdown;
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`Otlp`]
+pub enum Error {
+    /// Wrapper for [`hyper::Error`]
+    #[error("HTTP server error: {0}")]
+    Hyper(#[from] hyper::Error),
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Configuration for receiving OTLP target metrics
+pub struct Config {
+    /// Address -- IP plus port -- to bind to
+    binding_addr: SocketAddr,
+    /// Quantiles estimated from each histogram, each in [0, 1].
+    #[serde(default = "super::default_quantiles")]
+    quantiles: Vec<f64>,
+}
+
+type Labels = Vec<(String, String)>;
+
+fn labels(attributes: &[KeyValue]) -> Labels {
+    let mut labels: Labels = attributes
+        .iter()
+        .filter_map(|kv| {
+            let value = match kv.value.as_ref()?.value.as_ref()? {
+                any_value::Value::StringValue(v) => v.clone(),
+                any_value::Value::BoolValue(v) => v.to_string(),
+                any_value::Value::IntValue(v) => v.to_string(),
+                any_value::Value::DoubleValue(v) => v.to_string(),
+                // Arrays, maps and bytes do not make useful labels.
+                _ => return None,
+            };
+            Some((kv.key.clone(), value))
+        })
+        .collect();
+    labels.push(("target".to_owned(), "otlp".to_owned()));
+    labels
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn number(point: &NumberDataPoint) -> Option<f64> {
+    match point.value.as_ref()? {
+        number_data_point::Value::AsDouble(v) => Some(*v),
+        number_data_point::Value::AsInt(v) => Some(*v as f64),
+    }
+}
+
+/// Record a count, `cumulative` counts as the current total and others as an
+/// increment.
+fn record_count(name: String, count: u64, cumulative: bool, labels: &Labels) {
+    if cumulative {
+        absolute_counter!(name, count, labels);
+    } else {
+        counter!(name, count, labels);
+    }
+}
+
+/// Record the metrics of an export request.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn record(request: &ExportMetricsServiceRequest, quantiles: &[f64]) {
+    let metrics = request
+        .resource_metrics
+        .iter()
+        .flat_map(|r| &r.instrumentation_library_metrics)
+        .flat_map(|s| &s.metrics);
+    for metric in metrics {
+        let name = format!("target/{}", metric.name);
+        trace!("otlp: {name}");
+        match &metric.data {
+            Some(Data::Gauge(g)) => {
+                for point in &g.data_points {
+                    if let Some(value) = number(point) {
+                        gauge!(name.clone(), value, &labels(&point.attributes));
+                    }
+                }
+            }
+            Some(Data::Sum(s)) => {
+                let cumulative =
+                    s.aggregation_temporality == AggregationTemporality::Cumulative as i32;
+                for point in &s.data_points {
+                    let Some(value) = number(point) else {
+                        continue;
+                    };
+                    let labels = labels(&point.attributes);
+                    match (s.is_monotonic, cumulative) {
+                        (true, _) if value >= 0.0 && value <= u64::MAX as f64 => {
+                            record_count(name.clone(), value as u64, cumulative, &labels);
+                        }
+                        (true, _) => {}
+                        (false, true) => gauge!(name.clone(), value, &labels),
+                        (false, false) if value >= 0.0 => {
+                            increment_gauge!(name.clone(), value, &labels);
+                        }
+                        (false, false) => decrement_gauge!(name.clone(), -value, &labels),
+                    }
+                }
+            }
+            Some(Data::Histogram(h)) => {
+                let cumulative =
+                    h.aggregation_temporality == AggregationTemporality::Cumulative as i32;
+                for point in &h.data_points {
+                    let labels = labels(&point.attributes);
+                    // OTLP buckets are not cumulative, and their upper bounds
+                    // are implicitly followed by +Inf.
+                    let mut total = 0.0;
+                    let mut buckets: super::Buckets = point
+                        .bucket_counts
+                        .iter()
+                        .zip(
+                            point
+                                .explicit_bounds
+                                .iter()
+                                .copied()
+                                .chain(std::iter::once(f64::INFINITY)),
+                        )
+                        .map(|(count, bound)| {
+                            total += *count as f64;
+                            (bound, total)
+                        })
+                        .collect();
+                    for q in quantiles {
+                        if let Some(value) = super::bucket_quantile(*q, &mut buckets) {
+                            let mut labels = labels.clone();
+                            labels.push(("quantile".to_owned(), q.to_string()));
+                            gauge!(name.clone(), value, &labels);
+                        }
+                    }
+                    record_count(format!("{name}.count"), point.count, cumulative, &labels);
+                    gauge!(format!("{name}.sum"), point.sum, &labels);
+                }
+            }
+            Some(Data::ExponentialHistogram(h)) => {
+                let cumulative =
+                    h.aggregation_temporality == AggregationTemporality::Cumulative as i32;
+                for point in &h.data_points {
+                    let labels = labels(&point.attributes);
+                    record_count(format!("{name}.count"), point.count, cumulative, &labels);
+                    gauge!(format!("{name}.sum"), point.sum, &labels);
+                }
+            }
+            Some(Data::Summary(s)) => {
+                for point in &s.data_points {
+                    let labels = labels(&point.attributes);
+                    for quantile in &point.quantile_values {
+                        let mut labels = labels.clone();
+                        labels.push(("quantile".to_owned(), quantile.quantile.to_string()));
+                        gauge!(name.clone(), quantile.value, &labels);
+                    }
+                    // Summaries are always cumulative.
+                    record_count(format!("{name}.count"), point.count, true, &labels);
+                    gauge!(format!("{name}.sum"), point.sum, &labels);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn response(status: StatusCode, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+}
+
+async fn srv(req: Request<Body>, quantiles: Vec<f64>) -> Result<Response<Body>, hyper::Error> {
+    if req.method() != Method::POST || req.uri().path() != "/v1/metrics" {
+        return Ok(response(StatusCode::NOT_FOUND, Body::empty()));
+    }
+    let is_protobuf = req.headers().get(header::CONTENT_TYPE).map_or(false, |ct| {
+        ct.as_bytes().starts_with(b"application/x-protobuf")
+    });
+    if !is_protobuf {
+        return Ok(response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Body::from("only application/x-protobuf is supported"),
+        ));
+    }
+
+    let (parts, body) = req.into_parts();
+    let bytes = body::to_bytes(body).await?;
+    let bytes = match crate::codec::decode(parts.headers.get(header::CONTENT_ENCODING), bytes) {
+        Ok(bytes) => bytes,
+        Err(response) => {
+            counter!("target_metrics_otlp_decode_errors", 1);
+            return Ok(response);
+        }
+    };
+    let Ok(request) = ExportMetricsServiceRequest::decode(bytes) else {
+        counter!("target_metrics_otlp_decode_errors", 1);
+        return Ok(response(StatusCode::BAD_REQUEST, Body::empty()));
+    };
+    record(&request, &quantiles);
+
+    let mut ok = response(
+        StatusCode::OK,
+        Body::from(ExportMetricsServiceResponse::default().encode_to_vec()),
+    );
+    ok.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/x-protobuf"),
+    );
+    Ok(ok)
+}
+
+/// The `Otlp` target metrics implementation.
+#[derive(Debug)]
+pub struct Otlp {
+    config: Config,
+    shutdown: Shutdown,
+}
+
+impl Otlp {
+    /// Create a new [`Otlp`] instance
+    ///
+    /// This is responsible for receiving metrics pushed by the target process
+    /// over OTLP/HTTP.
+    ///
+    pub(crate) fn new(config: Config, shutdown: Shutdown) -> Self {
+        Self { config, shutdown }
+    }
+
+    /// Run this [`Otlp`] to completion
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the HTTP server cannot bind or fails.
+    ///
+    /// # Panics
+    ///
+    /// None are known.
+    pub(crate) async fn run(mut self) -> Result<(), Error> {
+        info!("OTLP target metrics receiver running");
+        let quantiles = self.config.quantiles.clone();
+        let service = make_service_fn(move |_: &AddrStream| {
+            let quantiles = quantiles.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request| srv(request, quantiles.clone())))
+            }
+        });
+        let server = Server::try_bind(&self.config.binding_addr)?.serve(service);
+
+        tokio::select! {
+            res = server => {
+                error!("server shutdown unexpectedly");
+                res.map_err(Error::Hyper)
+            }
+            _ = self.shutdown.recv() => {
+                info!("shutdown signal received");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header, Body, Method, Request, StatusCode};
+    use opentelemetry_proto::tonic::{
+        collector::metrics::v1::ExportMetricsServiceRequest,
+        common::v1::{any_value, AnyValue, KeyValue},
+        metrics::v1::{
+            metric::Data, number_data_point, summary_data_point::ValueAtQuantile,
+            AggregationTemporality, Histogram, HistogramDataPoint, InstrumentationLibraryMetrics,
+            Metric, NumberDataPoint, ResourceMetrics, Sum, Summary, SummaryDataPoint,
+        },
+    };
+    use prost::Message;
+
+    use super::{record, srv};
+    use crate::common::recorded::{self, install};
+
+    /// The gauge recorded for a target metric, `labels` besides `target`.
+    fn gauge(name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        recorded::gauge(name, &[labels, &[("target", "otlp")]].concat())
+    }
+
+    /// The counter recorded for a target metric, `labels` besides `target`.
+    fn counter(name: &str, labels: &[(&str, &str)]) -> Option<u64> {
+        recorded::counter(name, &[labels, &[("target", "otlp")]].concat())
+    }
+
+    fn decode_errors() -> Option<u64> {
+        recorded::counter("target_metrics_otlp_decode_errors", &[])
+    }
+
+    fn request(metrics: Vec<Metric>) -> ExportMetricsServiceRequest {
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                instrumentation_library_metrics: vec![InstrumentationLibraryMetrics {
+                    metrics,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn metric(name: &str, data: Data) -> Metric {
+        Metric {
+            name: name.to_owned(),
+            data: Some(data),
+            ..Default::default()
+        }
+    }
+
+    fn sum(value: number_data_point::Value, monotonic: bool, cumulative: bool) -> Data {
+        let temporality = if cumulative {
+            AggregationTemporality::Cumulative
+        } else {
+            AggregationTemporality::Delta
+        };
+        Data::Sum(Sum {
+            data_points: vec![NumberDataPoint {
+                attributes: vec![KeyValue {
+                    key: "method".to_owned(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue("GET".to_owned())),
+                    }),
+                }],
+                value: Some(value),
+                ..Default::default()
+            }],
+            aggregation_temporality: temporality as i32,
+            is_monotonic: monotonic,
+        })
+    }
+
+    #[test]
+    fn records_sums() {
+        install();
+        let labels = [("method", "GET")];
+        for _ in 0..2 {
+            record(
+                &request(vec![
+                    metric(
+                        "requests",
+                        sum(number_data_point::Value::AsInt(42), true, true),
+                    ),
+                    metric(
+                        "errors",
+                        sum(number_data_point::Value::AsInt(5), true, false),
+                    ),
+                    metric(
+                        "inflight",
+                        sum(number_data_point::Value::AsDouble(3.0), false, true),
+                    ),
+                    metric(
+                        "queued",
+                        sum(number_data_point::Value::AsDouble(-2.0), false, false),
+                    ),
+                ]),
+                &[],
+            );
+        }
+        // Cumulative sums are totals, delta sums accumulate.
+        assert_eq!(counter("target/requests", &labels), Some(42));
+        assert_eq!(counter("target/errors", &labels), Some(10));
+        assert_eq!(gauge("target/inflight", &labels), Some(3.0));
+        assert_eq!(gauge("target/queued", &labels), Some(-4.0));
+    }
+
+    #[test]
+    fn records_histograms() {
+        install();
+        let histogram = Data::Histogram(Histogram {
+            data_points: vec![HistogramDataPoint {
+                count: 4,
+                sum: 6.0,
+                bucket_counts: vec![1, 2, 1],
+                explicit_bounds: vec![1.0, 2.0],
+                ..Default::default()
+            }],
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+        });
+        record(&request(vec![metric("latency", histogram)]), &[0.5]);
+
+        assert_eq!(gauge("target/latency", &[("quantile", "0.5")]), Some(1.5));
+        assert_eq!(counter("target/latency.count", &[]), Some(4));
+        assert_eq!(gauge("target/latency.sum", &[]), Some(6.0));
+    }
+
+    #[test]
+    fn records_summaries() {
+        install();
+        let summary = Data::Summary(Summary {
+            data_points: vec![SummaryDataPoint {
+                count: 7,
+                sum: 30.0,
+                quantile_values: vec![ValueAtQuantile {
+                    quantile: 0.99,
+                    value: 12.5,
+                }],
+                ..Default::default()
+            }],
+        });
+        record(&request(vec![metric("rpc", summary)]), &[0.5]);
+
+        assert_eq!(gauge("target/rpc", &[("quantile", "0.99")]), Some(12.5));
+        assert_eq!(counter("target/rpc.count", &[]), Some(7));
+        assert_eq!(gauge("target/rpc.sum", &[]), Some(30.0));
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_requests() {
+        install();
+        let post = |path: &str, content_type: &str, body: Vec<u8>| {
+            Request::builder()
+                .method(Method::POST)
+                .uri(path)
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let valid = request(vec![]).encode_to_vec();
+
+        let response = srv(
+            post("/v1/metrics", "application/x-protobuf", valid.clone()),
+            vec![],
+        );
+        assert_eq!(response.await.unwrap().status(), StatusCode::OK);
+        let response = srv(
+            post("/v1/traces", "application/x-protobuf", valid.clone()),
+            vec![],
+        );
+        assert_eq!(response.await.unwrap().status(), StatusCode::NOT_FOUND);
+        let response = srv(post("/v1/metrics", "application/json", valid), vec![]);
+        assert_eq!(
+            response.await.unwrap().status(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(decode_errors(), None, "no decode errors yet");
+
+        // A body that is not protobuf, and one that is not gzip as claimed.
+        let response = srv(
+            post("/v1/metrics", "application/x-protobuf", vec![0xff; 8]),
+            vec![],
+        );
+        assert_eq!(response.await.unwrap().status(), StatusCode::BAD_REQUEST);
+        let mut gzip = post("/v1/metrics", "application/x-protobuf", vec![0xff; 8]);
+        gzip.headers_mut()
+            .insert(header::CONTENT_ENCODING, "gzip".parse().unwrap());
+        assert!(!srv(gzip, vec![]).await.unwrap().status().is_success());
+        assert_eq!(decode_errors(), Some(2));
+    }
+}
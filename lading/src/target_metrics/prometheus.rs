@@ -3,10 +3,17 @@
 //! This module scrapes Prometheus/OpenMetrics formatted metrics from the target
 //! software.
 //!
+//! Counters and gauges are recorded as-is. Histograms are reconstructed from
+//! their `_bucket` series and recorded as a gauge per configured quantile,
+//! estimated as Prometheus' `histogram_quantile` does, alongside their `_sum`
+//! and `_count`. Summary quantiles are recorded as reported by the target.
+//! Quantile gauges carry a `quantile` label. Lines that cannot be parsed are
+//! skipped and counted as `target_metrics_prometheus_parse_errors`.
 
-use std::{str::FromStr, time::Duration};
+use std::{num::NonZeroU64, str::FromStr, time::Duration};
 
-use metrics::{counter, gauge};
+use metrics::{absolute_counter, counter, gauge};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use rustc_hash::FxHashMap;
 use serde::Deserialize;
 use tracing::{error, info, trace, warn};
@@ -19,16 +26,35 @@ pub enum Error {
     /// Prometheus scraper shut down unexpectedly
     #[error("Unexpected shutdown")]
     EarlyShutdown,
+    /// A configured header is not a valid HTTP header
+    #[error("Invalid header")]
+    InvalidHeader,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+fn default_scrape_interval() -> NonZeroU64 {
+    NonZeroU64::new(1_000).expect("non-zero")
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 /// Configuration for collecting Prometheus based target metrics
 pub struct Config {
     /// URI to scrape
     uri: String,
-    /// Metric names to scrape. Leave unset to scrape all metrics.
+    /// Metric names to scrape. Leave unset to scrape all metrics. Histograms
+    /// and summaries are selected by their family name.
     metrics: Option<Vec<String>>,
+    /// Quantiles estimated from each histogram, each in [0, 1].
+    #[serde(default = "super::default_quantiles")]
+    quantiles: Vec<f64>,
+    /// The interval between scrapes, in milliseconds. Each scrape times out
+    /// after the same period.
+    #[serde(default = "default_scrape_interval")]
+    scrape_interval_milliseconds: NonZeroU64,
+    /// Additional HTTP headers sent with each scrape, for example
+    /// `Authorization`.
+    #[serde(default)]
+    headers: FxHashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -37,6 +63,9 @@ enum MetricType {
     Counter,
     Histogram,
     Summary,
+    /// Types lading does not interpret, recorded so that their samples are
+    /// skipped rather than counted as errors.
+    Other,
 }
 
 #[derive(Debug)]
@@ -50,14 +79,256 @@ impl FromStr for MetricType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "counter" => Ok(Self::Counter),
-            "gauge" => Ok(Self::Gauge),
+            // Untyped metrics are most often gauges in practice.
+            "gauge" | "untyped" | "unknown" => Ok(Self::Gauge),
             "histogram" => Ok(Self::Histogram),
             "summary" => Ok(Self::Summary),
+            "gaugehistogram" | "info" | "stateset" => Ok(Self::Other),
             _ => Err(MetricTypeParseError::UnknownType),
         }
     }
 }
 
+type Labels = Vec<(String, String)>;
+
+/// A single sample line of the exposition
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    name: String,
+    labels: Labels,
+    value: f64,
+}
+
+/// A value to record, derived from one or more samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Point {
+    Counter(f64),
+    Gauge(f64),
+}
+
+/// A metric to record, named as in the exposition
+#[derive(Debug, Clone, PartialEq)]
+struct Metric {
+    name: String,
+    labels: Labels,
+    point: Point,
+}
+
+/// The metrics of a single scrape
+#[derive(Debug, Default, PartialEq)]
+struct Exposition {
+    metrics: Vec<Metric>,
+    /// The number of lines that could not be parsed.
+    errors: u64,
+}
+
+/// Parse the label set of a sample, `input` beginning just past the opening
+/// brace. Returns the labels and the remainder of the line after the closing
+/// brace.
+fn parse_labels(input: &str) -> Option<(Labels, &str)> {
+    let mut labels = Vec::new();
+    let mut rest = input.trim_start();
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            return Some((labels, after));
+        }
+        let (name, after) = rest.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut chars = after.trim_start().strip_prefix('"')?.char_indices();
+        let mut value = String::new();
+        let end = loop {
+            match chars.next()? {
+                (idx, '"') => break idx,
+                (_, '\\') => match chars.next()? {
+                    (_, 'n') => value.push('\n'),
+                    (_, c) => value.push(c),
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        labels.push((name.to_owned(), value));
+        rest = after.trim_start()[1 + end + 1..].trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.starts_with('}') {
+            return None;
+        }
+    }
+}
+
+fn parse_value(input: &str) -> Option<f64> {
+    match input {
+        "+Inf" | "Inf" => Some(f64::INFINITY),
+        "-Inf" => Some(f64::NEG_INFINITY),
+        _ => input.parse().ok(),
+    }
+}
+
+/// Parse a sample line: `name{labels} value [timestamp] [# exemplar]`.
+fn parse_sample(line: &str) -> Option<Sample> {
+    let end = line.find(|c: char| c == '{' || c.is_ascii_whitespace())?;
+    let name = &line[..end];
+    if name.is_empty() {
+        return None;
+    }
+    let (labels, rest) = match line[end..].strip_prefix('{') {
+        Some(rest) => parse_labels(rest)?,
+        None => (Vec::new(), &line[end..]),
+    };
+    // OpenMetrics exemplars follow the value and optional timestamp.
+    let rest = rest
+        .split_once(" # ")
+        .map_or(rest, |(rest, _exemplar)| rest);
+    let mut parts = rest.split_ascii_whitespace();
+    let value = parse_value(parts.next()?)?;
+    // An optional timestamp, which lading ignores.
+    let _ = parts.next();
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Sample {
+        name: name.to_owned(),
+        labels,
+        value,
+    })
+}
+
+/// The type of the metric family `name` belongs to, and whether it is a
+/// histogram bucket, sum or count series.
+fn lookup<'a>(
+    typemap: &FxHashMap<String, MetricType>,
+    name: &'a str,
+) -> Option<(&'a str, MetricType, Option<&'static str>)> {
+    if let Some(metric_type) = typemap.get(name) {
+        return Some((name, *metric_type, None));
+    }
+    for suffix in ["_bucket", "_sum", "_count", "_total", "_created"] {
+        if let Some(base) = name.strip_suffix(suffix) {
+            if let Some(metric_type) = typemap.get(base) {
+                return Some((base, *metric_type, Some(suffix)));
+            }
+        }
+    }
+    None
+}
+
+/// Parse a Prometheus or `OpenMetrics` text exposition.
+fn parse(text: &str, quantiles: &[f64]) -> Exposition {
+    let mut exposition = Exposition::default();
+    // remember the type for each metric across lines
+    let mut typemap: FxHashMap<String, MetricType> = FxHashMap::default();
+    // histogram buckets, keyed by family name and labels other than `le`,
+    // in order of first appearance
+    let mut histograms: Vec<(String, Labels, super::Buckets)> = Vec::new();
+    let mut histogram_index: FxHashMap<(String, Labels), usize> = FxHashMap::default();
+
+    // Format doc: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "# EOF" {
+            break;
+        }
+        if let Some(type_line) = line.strip_prefix("# TYPE ") {
+            let mut parts = type_line.split_ascii_whitespace();
+            if let (Some(name), Some(Ok(metric_type))) =
+                (parts.next(), parts.next().map(str::parse::<MetricType>))
+            {
+                typemap.insert(name.to_owned(), metric_type);
+            } else {
+                trace!("unparseable TYPE line: {line}");
+                exposition.errors += 1;
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            // HELP, UNIT and comments
+            continue;
+        }
+
+        let Some(sample) = parse_sample(line) else {
+            trace!("unparseable sample line: {line}");
+            exposition.errors += 1;
+            continue;
+        };
+        let Some((base, metric_type, suffix)) = lookup(&typemap, &sample.name) else {
+            trace!("sample of untyped metric: {line}");
+            continue;
+        };
+
+        let point = match (metric_type, suffix) {
+            (_, Some("_created")) | (MetricType::Other, _) => continue,
+            (MetricType::Counter, _) => Point::Counter(sample.value),
+            // Summary quantile series are named for the family.
+            (MetricType::Gauge, _) | (MetricType::Summary, None) => Point::Gauge(sample.value),
+            (MetricType::Histogram | MetricType::Summary, Some("_count")) => {
+                Point::Counter(sample.value)
+            }
+            (MetricType::Histogram | MetricType::Summary, Some("_sum")) => {
+                Point::Gauge(sample.value)
+            }
+            (MetricType::Histogram, Some("_bucket")) => {
+                let mut labels = sample.labels;
+                let Some(pos) = labels.iter().position(|(name, _)| name == "le") else {
+                    exposition.errors += 1;
+                    continue;
+                };
+                let (_, le) = labels.remove(pos);
+                let Some(le) = parse_value(&le) else {
+                    exposition.errors += 1;
+                    continue;
+                };
+                let key = (base.to_owned(), labels);
+                let idx = *histogram_index.entry(key.clone()).or_insert_with(|| {
+                    histograms.push((key.0, key.1, Vec::new()));
+                    histograms.len() - 1
+                });
+                histograms[idx].2.push((le, sample.value));
+                continue;
+            }
+            (MetricType::Histogram | MetricType::Summary, _) => {
+                exposition.errors += 1;
+                continue;
+            }
+        };
+        exposition.metrics.push(Metric {
+            name: sample.name,
+            labels: sample.labels,
+            point,
+        });
+    }
+
+    for (name, labels, mut buckets) in histograms {
+        for q in quantiles {
+            let Some(value) = super::bucket_quantile(*q, &mut buckets) else {
+                continue;
+            };
+            let mut labels = labels.clone();
+            labels.push(("quantile".to_owned(), q.to_string()));
+            exposition.metrics.push(Metric {
+                name: name.clone(),
+                labels,
+                point: Point::Gauge(value),
+            });
+        }
+    }
+
+    exposition
+}
+
+/// The name of a family, for filtering, given one of its series.
+fn family_name(name: &str) -> &str {
+    ["_bucket", "_sum", "_count"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name)
+}
+
 /// The `Prometheus` target metrics implementation.
 #[derive(Debug)]
 pub struct Prometheus {
@@ -77,27 +348,41 @@ impl Prometheus {
 
     /// Run this [`Server`] to completion
     ///
-    /// Scrape metrics from the target at 1Hz.
+    /// Scrape metrics from the target at the configured interval, 1Hz by
+    /// default.
     ///
     /// # Errors
     ///
-    /// None are known.
+    /// Function will return an error if a configured header is invalid.
     ///
     /// # Panics
     ///
     /// None are known.
-    #[allow(clippy::cast_sign_loss)]
-    #[allow(clippy::cast_possible_truncation)]
-    #[allow(clippy::too_many_lines)]
-    pub(crate) async fn run(mut self) -> Result<(), Error> {
+    pub(crate) async fn run(self) -> Result<(), Error> {
         info!("Prometheus target metrics scraper running");
+        let Self {
+            config,
+            mut shutdown,
+        } = self;
         let client = reqwest::Client::new();
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) else {
+                error!("invalid Prometheus scrape header: {name}");
+                return Err(Error::InvalidHeader);
+            };
+            headers.insert(name, value);
+        }
+        let period = Duration::from_millis(config.scrape_interval_milliseconds.get());
 
         let server = async move {
             loop {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                tokio::time::sleep(period).await;
 
-                let Ok(resp) = client.get(&self.config.uri).timeout(Duration::from_secs(1)).send().await else {
+                let Ok(resp) = client.get(&config.uri).headers(headers.clone()).timeout(period).send().await else {
                     info!("failed to get Prometheus uri");
                     continue;
                 };
@@ -107,98 +392,7 @@ impl Prometheus {
                     continue;
                 };
 
-                // remember the type for each metric across lines
-                let mut typemap = FxHashMap::default();
-
-                // this deserves a real parser, but this will do for now.
-                // Format doc: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md
-                for line in text.lines() {
-                    if line.starts_with("# HELP") {
-                        continue;
-                    }
-
-                    if line.starts_with("# TYPE") {
-                        let mut parts = line.split_ascii_whitespace().skip(2);
-                        let name = parts.next().unwrap();
-                        let metric_type = parts.next().unwrap();
-                        let metric_type: MetricType = metric_type.parse().unwrap();
-                        // summary and histogram metrics additionally report names suffixed with _sum, _count, _bucket
-                        if matches!(metric_type, MetricType::Histogram | MetricType::Summary) {
-                            typemap.insert(format!("{name}_sum"), metric_type);
-                            typemap.insert(format!("{name}_count"), metric_type);
-                            typemap.insert(format!("{name}_bucket"), metric_type);
-                        }
-                        typemap.insert(name.to_owned(), metric_type);
-                        continue;
-                    }
-
-                    let mut parts = line.split_ascii_whitespace();
-                    let name_and_labels = parts.next().unwrap();
-                    let value = parts.next().unwrap();
-
-                    let (name, labels) = {
-                        if let Some((name, labels)) = name_and_labels.split_once('{') {
-                            let labels = labels.trim_end_matches('}');
-                            let labels = labels.split(',').map(|label| {
-                                let (label_name, label_value) = label.split_once('=').unwrap();
-                                let label_value = label_value.trim_matches('\"');
-                                (label_name.to_owned(), label_value.to_owned())
-                            });
-                            let labels = labels.collect::<Vec<_>>();
-                            (name, Some(labels))
-                        } else {
-                            (name_and_labels, None)
-                        }
-                    };
-
-                    let metric_type = typemap.get(name);
-                    let name = name.replace("__", ".");
-
-                    if let Some(metrics) = &self.config.metrics {
-                        if !metrics.contains(&name) {
-                            continue;
-                        }
-                    }
-
-                    match metric_type {
-                        Some(MetricType::Gauge) => {
-                            let Ok(value): Result<f64, _> = value.parse() else {
-                                let e = value.parse::<f64>().unwrap_err();
-                                warn!("{e}: {name} = {value}");
-                                continue;
-                            };
-
-                            trace!("gauge: {name} = {value}");
-                            gauge!(format!("target/{name}"), value, &labels.unwrap_or_default());
-                        }
-                        Some(MetricType::Counter) => {
-                            let Ok(value): Result<f64, _> = value.parse() else {
-                                let e = value.parse::<f64>().unwrap_err();
-                                warn!("{e}: {name} = {value}");
-                                continue;
-                            };
-
-                            let value = if value < 0.0 {
-                                warn!("Negative counter value unhandled");
-                                continue;
-                            } else {
-                                // clippy shows "error: casting `f64` to `u64` may lose the sign of the value". This is
-                                // guarded by the sign check above.
-                                if value > u64::MAX as f64 {
-                                    warn!("Counter value above maximum limit");
-                                    continue;
-                                }
-                                value as u64
-                            };
-
-                            trace!("counter: {name} = {value}");
-                            counter!(format!("target/{name}"), value, &labels.unwrap_or_default());
-                        }
-                        Some(_) | None => {
-                            trace!("unsupported metric type: {name} = {value}");
-                        }
-                    }
-                }
+                record(&config, parse(&text, &config.quantiles));
             }
         };
 
@@ -207,10 +401,138 @@ impl Prometheus {
                 error!("server shutdown unexpectedly");
                  Err(Error::EarlyShutdown)
             }
-            _ = self.shutdown.recv() => {
+            _ = shutdown.recv() => {
                 info!("shutdown signal received");
                  Ok(())
             }
         }
     }
 }
+
+/// Record the metrics of a scrape.
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_possible_truncation)]
+fn record(config: &Config, exposition: Exposition) {
+    if exposition.errors > 0 {
+        counter!("target_metrics_prometheus_parse_errors", exposition.errors);
+    }
+
+    for Metric {
+        name,
+        labels,
+        point,
+    } in exposition.metrics
+    {
+        let name = name.replace("__", ".");
+        if let Some(metrics) = &config.metrics {
+            if !metrics
+                .iter()
+                .any(|m| *m == name || m == family_name(&name))
+            {
+                continue;
+            }
+        }
+
+        match point {
+            Point::Gauge(value) => {
+                trace!("gauge: {name} = {value}");
+                gauge!(format!("target/{name}"), value, &labels);
+            }
+            Point::Counter(value) => {
+                if value < 0.0 {
+                    warn!("Negative counter value unhandled");
+                    continue;
+                }
+                // clippy shows "error: casting `f64` to `u64` may lose the sign of the value". This is
+                // guarded by the sign check above.
+                if value > u64::MAX as f64 {
+                    warn!("Counter value above maximum limit");
+                    continue;
+                }
+
+                trace!("counter: {name} = {value}");
+                absolute_counter!(format!("target/{name}"), value as u64, &labels);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn sample_with_escapes_timestamp_and_exemplar() {
+        let sample = parse_sample(
+            r#"http_requests_total{path="/a,b",msg="say \"hi\""} 12 1700000000 # {trace_id="abc"} 1.0"#,
+        )
+        .unwrap();
+        assert_eq!(sample.name, "http_requests_total");
+        assert_eq!(
+            sample.labels,
+            labels(&[("path", "/a,b"), ("msg", "say \"hi\"")])
+        );
+        assert!((sample.value - 12.0).abs() < f64::EPSILON);
+
+        assert!(parse_sample("no_value").is_none());
+        assert!(parse_sample(r#"unterminated{a="b} 1"#).is_none());
+    }
+
+    #[test]
+    fn histogram_quantiles_from_buckets() {
+        let text = r#"# TYPE latency histogram
+latency_bucket{route="x",le="0.1"} 50
+latency_bucket{route="x",le="0.5"} 90
+latency_bucket{route="x",le="+Inf"} 100
+latency_sum{route="x"} 17.5
+latency_count{route="x"} 100
+# EOF
+latency_bucket{route="x",le="1"} 100
+"#;
+        let exposition = parse(text, &[0.5, 0.9, 0.99]);
+        assert_eq!(exposition.errors, 0);
+        let quantile = |q: &str| {
+            exposition
+                .metrics
+                .iter()
+                .find(|m| m.labels == labels(&[("route", "x"), ("quantile", q)]))
+                .map(|m| m.point)
+        };
+        assert_eq!(quantile("0.5"), Some(Point::Gauge(0.1)));
+        assert_eq!(quantile("0.9"), Some(Point::Gauge(0.5)));
+        // Falls in the +Inf bucket.
+        assert_eq!(quantile("0.99"), Some(Point::Gauge(0.5)));
+        assert!(exposition.metrics.contains(&Metric {
+            name: "latency_count".to_owned(),
+            labels: labels(&[("route", "x")]),
+            point: Point::Counter(100.0),
+        }));
+        assert!(exposition.metrics.contains(&Metric {
+            name: "latency_sum".to_owned(),
+            labels: labels(&[("route", "x")]),
+            point: Point::Gauge(17.5),
+        }));
+    }
+
+    #[test]
+    fn malformed_lines_are_counted() {
+        let text = "# TYPE up gauge\n# TYPE weird nonsense\nup 1\nup{broken 2\nup NaNx\n";
+        let exposition = parse(text, &[]);
+        assert_eq!(exposition.errors, 3);
+        assert_eq!(
+            exposition.metrics,
+            vec![Metric {
+                name: "up".to_owned(),
+                labels: Vec::new(),
+                point: Point::Gauge(1.0),
+            }]
+        );
+    }
+}
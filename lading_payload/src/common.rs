@@ -1 +1,6 @@
 pub(crate) mod strings;
+
+/// Payloads timestamp their data within an hour of this instant, in seconds
+/// since the epoch. Blocks are built ahead of time and replayed for the length
+/// of a run, so there is no sense in using the wall clock.
+pub(crate) const TIMESTAMP_BASE_SECONDS: i32 = 1_690_000_000;
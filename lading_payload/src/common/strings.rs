@@ -1,6 +1,9 @@
 //! Code for the quick creation of randomize strings
 
-use std::ops::Range;
+use std::{
+    fmt::Write,
+    ops::{Range, RangeInclusive},
+};
 
 use rand::{distributions::uniform::SampleUniform, seq::SliceRandom};
 
@@ -87,6 +90,40 @@ impl Pool {
         let bytes: usize = rng.gen_range(bytes_range).into();
         self.of_size(rng, bytes)
     }
+
+    /// Return an owned `String` from the interior storage with size selected
+    /// from `bytes_range`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if the selected size is not smaller than the pool.
+    pub(crate) fn random_str<R, T>(&self, rng: &mut R, bytes_range: RangeInclusive<T>) -> String
+    where
+        R: rand::Rng + ?Sized,
+        T: Into<usize> + Copy + PartialOrd + SampleUniform,
+    {
+        let bytes: usize = rng.gen_range(bytes_range).into();
+        String::from(
+            self.of_size(rng, bytes)
+                .expect("pool is larger than any string drawn from it"),
+        )
+    }
+}
+
+/// Append a random version 4 UUID to `buf`, in its hyphenated form.
+pub(crate) fn uuid_v4<R>(rng: &mut R, buf: &mut String)
+where
+    R: rand::Rng + ?Sized,
+{
+    let mut bytes: [u8; 16] = rng.gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+    for (idx, byte) in bytes.iter().enumerate() {
+        if matches!(idx, 4 | 6 | 8 | 10) {
+            buf.push('-');
+        }
+        write!(buf, "{byte:02x}").expect("writing to a String cannot fail");
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,339 @@
+//! Kubernetes container log payloads, as written to `/var/log/pods`.
+//!
+//! [CRI log format](https://github.com/kubernetes/design-proposals-archive/blob/main/node/kubelet-cri-logging.md),
+//! [docker `json-file`](https://docs.docker.com/config/containers/logging/json-file/)
+//!
+//! Both formats record the stream, stdout or stderr, and a timestamp for each
+//! line a container writes. Lines longer than the runtime's buffer are split
+//! into partial lines: CRI tags all but the last with `P`, the last with `F`,
+//! while `json-file` omits the trailing newline from all but the last `log`.
+
+use std::{io::Write, ops::RangeInclusive, time::SystemTime};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize as _};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{common::strings, Error, Serialize};
+
+/// Characters of messages. Spaces, so there are words, but no newlines.
+const MESSAGE_ALPHABET: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789     .,:;-_/";
+
+fn message_length_minimum() -> u32 {
+    16
+}
+
+fn message_length_maximum() -> u32 {
+    512
+}
+
+fn partial_line_bytes() -> u32 {
+    // The kubelet and docker both split lines at 16KiB.
+    16 * 1024
+}
+
+fn stderr_probability() -> f32 {
+    0.1
+}
+
+/// Configure the container log payloads.
+///
+/// All ranges are inclusive. A minimum must not exceed its maximum.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// Minimum length of a message written by the container
+    #[serde(default = "message_length_minimum")]
+    pub message_length_minimum: u32,
+
+    /// Maximum length of a message written by the container
+    #[serde(default = "message_length_maximum")]
+    pub message_length_maximum: u32,
+
+    /// Messages longer than this are split into partial lines
+    #[serde(default = "partial_line_bytes")]
+    pub partial_line_bytes: u32,
+
+    /// The probability that a message is written to stderr rather than stdout
+    #[serde(default = "stderr_probability")]
+    pub stderr_probability: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            message_length_minimum: message_length_minimum(),
+            message_length_maximum: message_length_maximum(),
+            partial_line_bytes: partial_line_bytes(),
+            stderr_probability: stderr_probability(),
+        }
+    }
+}
+
+/// A message and how it is to be split, shared by both formats.
+#[derive(Debug, Clone)]
+struct Messages {
+    pool: strings::Pool,
+    message_length: RangeInclusive<usize>,
+    partial_line_bytes: usize,
+    stderr_probability: f32,
+}
+
+impl Messages {
+    fn new<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let message_length =
+            config.message_length_minimum as usize..=config.message_length_maximum as usize;
+        // The pool must be larger than any message.
+        let pool_bytes = (*message_length.end() + 1).max(1_000_000);
+        Self {
+            pool: strings::Pool::with_size_and_alphabet(rng, pool_bytes, MESSAGE_ALPHABET),
+            message_length,
+            partial_line_bytes: (config.partial_line_bytes as usize).max(1),
+            stderr_probability: config.stderr_probability,
+        }
+    }
+
+    /// Generate a message, returning its stream and its parts, all but the
+    /// last partial.
+    fn generate<R>(&self, rng: &mut R) -> (&'static str, Vec<&str>)
+    where
+        R: Rng + ?Sized,
+    {
+        let stream = if rng.gen::<f32>() < self.stderr_probability {
+            "stderr"
+        } else {
+            "stdout"
+        };
+        let length = rng.gen_range(self.message_length.clone());
+        let message = self
+            .pool
+            .of_size(rng, length)
+            .expect("pool is larger than any message");
+        // The alphabet is ASCII so any byte offset is a char boundary.
+        let parts = if message.is_empty() {
+            vec![message]
+        } else {
+            message
+                .as_bytes()
+                .chunks(self.partial_line_bytes)
+                .map(|chunk| std::str::from_utf8(chunk).unwrap())
+                .collect()
+        };
+        (stream, parts)
+    }
+}
+
+fn now() -> String {
+    OffsetDateTime::from(SystemTime::now())
+        .format(&Rfc3339)
+        .unwrap()
+}
+
+/// Write each message's lines from `lines` into `writer` until `max_bytes`
+/// would be exceeded. A message is written whole or not at all, so partial
+/// lines always end in a final one.
+fn write_messages<W, F>(max_bytes: usize, writer: &mut W, mut lines: F) -> Result<(), Error>
+where
+    W: Write,
+    F: FnMut() -> Result<Vec<u8>, Error>,
+{
+    let mut bytes_remaining = max_bytes;
+    loop {
+        let encoding = lines()?;
+        match bytes_remaining.checked_sub(encoding.len()) {
+            Some(remainder) => {
+                writer.write_all(&encoding)?;
+                bytes_remaining = remainder;
+            }
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+/// A generator for CRI container log lines
+pub struct Cri {
+    messages: Messages,
+}
+
+impl Cri {
+    /// Create a new instance of `Cri`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if the message length minimum exceeds its maximum.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self {
+            messages: Messages::new(config, rng),
+        }
+    }
+}
+
+impl Serialize for Cri {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        write_messages(max_bytes, writer, || {
+            let (stream, parts) = self.messages.generate(&mut rng);
+            let timestamp = now();
+            let mut buf = Vec::new();
+            let last = parts.len() - 1;
+            for (idx, part) in parts.into_iter().enumerate() {
+                let tag = if idx == last { "F" } else { "P" };
+                writeln!(buf, "{timestamp} {stream} {tag} {part}")?;
+            }
+            Ok(buf)
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+struct JsonFileLine<'a> {
+    log: &'a str,
+    stream: &'static str,
+    time: &'a str,
+}
+
+#[derive(Debug, Clone)]
+/// A generator for docker `json-file` container log lines
+pub struct DockerJsonFile {
+    messages: Messages,
+}
+
+impl DockerJsonFile {
+    /// Create a new instance of `DockerJsonFile`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if the message length minimum exceeds its maximum.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self {
+            messages: Messages::new(config, rng),
+        }
+    }
+}
+
+impl Serialize for DockerJsonFile {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        write_messages(max_bytes, writer, || {
+            let (stream, parts) = self.messages.generate(&mut rng);
+            let time = now();
+            let mut buf = Vec::new();
+            let last = parts.len() - 1;
+            for (idx, part) in parts.into_iter().enumerate() {
+                let log = if idx == last {
+                    format!("{part}\n")
+                } else {
+                    String::from(part)
+                };
+                let line = JsonFileLine {
+                    log: &log,
+                    stream,
+                    time: &time,
+                };
+                line.serialize(&mut serde_json::Serializer::new(&mut buf))?;
+                buf.push(b'\n');
+            }
+            Ok(buf)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::{container_log::Config, Cri, DockerJsonFile, Serialize};
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let cri = Cri::new(&Config::default(), &mut rng);
+            let docker = DockerJsonFile::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            cri.to_bytes(&mut rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            docker.to_bytes(&mut rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes);
+        }
+    }
+
+    // We want to be sure that long messages are split into partial lines, the
+    // block ending on a final one.
+    proptest! {
+        #[test]
+        fn cri_partial_lines(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                message_length_minimum: 100,
+                message_length_maximum: 300,
+                partial_line_bytes: 64,
+                ..Config::default()
+            };
+            let cri = Cri::new(&config, &mut rng);
+
+            let mut bytes = Vec::new();
+            cri.to_bytes(rng, 4096, &mut bytes).unwrap();
+            let text = std::str::from_utf8(&bytes).unwrap();
+            let mut tag = "F";
+            for line in text.lines() {
+                let fields: Vec<&str> = line.splitn(4, ' ').collect();
+                prop_assert!(fields[1] == "stdout" || fields[1] == "stderr");
+                prop_assert!(fields[3].len() <= 64);
+                tag = fields[2];
+                prop_assert!(tag == "P" || tag == "F");
+            }
+            prop_assert_eq!(tag, "F");
+        }
+    }
+
+    // We want to be sure that only the last part of a json-file message ends
+    // in a newline.
+    proptest! {
+        #[test]
+        fn json_file_partial_lines(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                message_length_minimum: 100,
+                message_length_maximum: 300,
+                partial_line_bytes: 64,
+                ..Config::default()
+            };
+            let docker = DockerJsonFile::new(&config, &mut rng);
+
+            let mut bytes = Vec::new();
+            docker.to_bytes(rng, 4096, &mut bytes).unwrap();
+            let mut complete = true;
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let doc: serde_json::Value = serde_json::from_str(line).unwrap();
+                complete = doc["log"].as_str().unwrap().ends_with('\n');
+                prop_assert!(doc["time"].is_string());
+            }
+            prop_assert!(complete);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Graylog Extended Log Format payload.
+//!
+//! [Specification](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html)
+//!
+//! Every message is a GELF 1.1 JSON document with the mandatory `version`,
+//! `host` and `short_message`, an optional `full_message`, a `timestamp`, a
+//! syslog `level` and a random selection of `_`-prefixed additional fields.
+
+use std::{io::Write, ops::RangeInclusive};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    common::{strings, TIMESTAMP_BASE_SECONDS},
+    Error, Serialize,
+};
+
+/// The number of messages generated in search of one that fits a datagram.
+const DATAGRAM_ATTEMPTS: usize = 8;
+
+fn hosts() -> u16 {
+    16
+}
+
+fn additional_field_keys() -> u16 {
+    64
+}
+
+fn additional_fields_minimum() -> u16 {
+    0
+}
+
+fn additional_fields_maximum() -> u16 {
+    16
+}
+
+fn short_message_length_minimum() -> u16 {
+    16
+}
+
+fn short_message_length_maximum() -> u16 {
+    256
+}
+
+fn full_message_length_minimum() -> u32 {
+    256
+}
+
+fn full_message_length_maximum() -> u32 {
+    16_384
+}
+
+fn full_message_probability() -> f32 {
+    0.2
+}
+
+/// How messages are separated from one another in a block
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// Every message is followed by a null byte, as GELF over TCP expects
+    NullDelimited,
+    /// Every message is followed by a newline
+    NewlineDelimited,
+    /// A block holds a single message and nothing else, as GELF over UDP
+    /// expects of a datagram
+    Datagram,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self::NullDelimited
+    }
+}
+
+/// Configure the GELF payload.
+///
+/// All ranges are inclusive. A minimum must not exceed its maximum.
+#[derive(Debug, Deserialize, Clone, PartialEq, Copy)]
+pub struct Config {
+    /// How messages are separated from one another in a block
+    #[serde(default)]
+    pub framing: Framing,
+
+    /// Number of distinct hosts messages come from
+    #[serde(default = "hosts")]
+    pub hosts: u16,
+
+    /// Number of distinct additional field names to draw from
+    #[serde(default = "additional_field_keys")]
+    pub additional_field_keys: u16,
+
+    /// Minimum number of additional fields per message
+    #[serde(default = "additional_fields_minimum")]
+    pub additional_fields_minimum: u16,
+
+    /// Maximum number of additional fields per message
+    #[serde(default = "additional_fields_maximum")]
+    pub additional_fields_maximum: u16,
+
+    /// Minimum length of `short_message`
+    #[serde(default = "short_message_length_minimum")]
+    pub short_message_length_minimum: u16,
+
+    /// Maximum length of `short_message`
+    #[serde(default = "short_message_length_maximum")]
+    pub short_message_length_maximum: u16,
+
+    /// Minimum length of `full_message`, when present
+    #[serde(default = "full_message_length_minimum")]
+    pub full_message_length_minimum: u32,
+
+    /// Maximum length of `full_message`, when present
+    #[serde(default = "full_message_length_maximum")]
+    pub full_message_length_maximum: u32,
+
+    /// The probability that a message has a `full_message`
+    #[serde(default = "full_message_probability")]
+    pub full_message_probability: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            framing: Framing::default(),
+            hosts: hosts(),
+            additional_field_keys: additional_field_keys(),
+            additional_fields_minimum: additional_fields_minimum(),
+            additional_fields_maximum: additional_fields_maximum(),
+            short_message_length_minimum: short_message_length_minimum(),
+            short_message_length_maximum: short_message_length_maximum(),
+            full_message_length_minimum: full_message_length_minimum(),
+            full_message_length_maximum: full_message_length_maximum(),
+            full_message_probability: full_message_probability(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A generator for GELF payloads
+pub struct Gelf {
+    framing: Framing,
+    hosts: Vec<String>,
+    field_keys: Vec<String>,
+    additional_fields: RangeInclusive<u16>,
+    short_message_length: RangeInclusive<u16>,
+    full_message_length: RangeInclusive<u32>,
+    full_message_probability: f32,
+    pool: strings::Pool,
+}
+
+impl Gelf {
+    /// Create a new instance of `Gelf`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if any minimum in `config` exceeds its maximum or if
+    /// there are no hosts or additional field names.
+    pub fn new<R>(config: &Config, mut rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let pool = strings::Pool::with_size(&mut rng, 1_000_000);
+        let hosts: Vec<String> = (0..config.hosts)
+            .map(|_| {
+                let length = rng.gen_range(4..=24);
+                String::from(pool.of_size(&mut rng, length).unwrap())
+            })
+            .collect();
+        // Field names are `_`-prefixed and `_id` is reserved.
+        let field_keys: Vec<String> = (0..config.additional_field_keys)
+            .map(|_| {
+                let length = rng.gen_range(3..=24);
+                format!("_{}", pool.of_size(&mut rng, length).unwrap())
+            })
+            .collect();
+        assert!(!hosts.is_empty(), "there must be hosts");
+        assert!(
+            !field_keys.is_empty(),
+            "there must be additional field names"
+        );
+
+        Self {
+            framing: config.framing,
+            hosts,
+            field_keys,
+            additional_fields: config.additional_fields_minimum..=config.additional_fields_maximum,
+            short_message_length: config.short_message_length_minimum
+                ..=config.short_message_length_maximum,
+            full_message_length: config.full_message_length_minimum
+                ..=config.full_message_length_maximum,
+            full_message_probability: config.full_message_probability,
+            pool,
+        }
+    }
+
+    fn message<R>(&self, rng: &mut R) -> Value
+    where
+        R: Rng + ?Sized,
+    {
+        let mut doc = Map::new();
+        doc.insert("version".into(), "1.1".into());
+        doc.insert(
+            "host".into(),
+            self.hosts.choose(rng).unwrap().as_str().into(),
+        );
+        let length = rng.gen_range(self.short_message_length.clone());
+        doc.insert(
+            "short_message".into(),
+            self.pool.of_size(rng, usize::from(length)).unwrap().into(),
+        );
+        if rng.gen::<f32>() < self.full_message_probability {
+            let length = rng.gen_range(self.full_message_length.clone());
+            // The pool is too small for the very largest messages, settle for
+            // what fits.
+            let length = (length as usize).min(999_999);
+            doc.insert(
+                "full_message".into(),
+                self.pool.of_size(rng, length).unwrap().into(),
+            );
+        }
+        let timestamp = f64::from(TIMESTAMP_BASE_SECONDS) + rng.gen_range(0.0..3_600.0);
+        doc.insert(
+            "timestamp".into(),
+            ((timestamp * 1_000.0).round() / 1_000.0).into(),
+        );
+        doc.insert("level".into(), rng.gen_range(0..=7).into());
+
+        let total = rng.gen_range(self.additional_fields.clone());
+        for key in self.field_keys.choose_multiple(rng, usize::from(total)) {
+            let value: Value = if rng.gen() {
+                rng.gen_range(-1_000_000..1_000_000).into()
+            } else {
+                let length = rng.gen_range(1..=64);
+                self.pool.of_size(rng, length).unwrap().into()
+            };
+            doc.insert(key.clone(), value);
+        }
+        Value::Object(doc)
+    }
+}
+
+impl Serialize for Gelf {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let delimiter: &[u8] = match self.framing {
+            Framing::NullDelimited => b"\0",
+            Framing::NewlineDelimited => b"\n",
+            Framing::Datagram => {
+                // A datagram holds exactly one message. Messages vary wildly in
+                // size so give a few of them the chance to fit.
+                for _ in 0..DATAGRAM_ATTEMPTS {
+                    let encoding = serde_json::to_vec(&self.message(&mut rng))?;
+                    if encoding.len() <= max_bytes {
+                        writer.write_all(&encoding)?;
+                        break;
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let encoding = serde_json::to_vec(&self.message(&mut rng))?;
+            let length = encoding.len() + delimiter.len();
+            match bytes_remaining.checked_sub(length) {
+                Some(remainder) => {
+                    writer.write_all(&encoding)?;
+                    writer.write_all(delimiter)?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::{
+        gelf::{Config, Framing},
+        Gelf, Serialize,
+    };
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let gelf = Gelf::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            gelf.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes, "max len: {max_bytes}, actual: {}", bytes.len());
+        }
+    }
+
+    // We want to be sure that a datagram block is a single GELF document.
+    proptest! {
+        #[test]
+        fn datagram_is_one_document(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                framing: Framing::Datagram,
+                ..Config::default()
+            };
+            let gelf = Gelf::new(&config, &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            gelf.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            if !bytes.is_empty() {
+                let doc: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                assert_eq!(doc["version"], "1.1");
+                assert!(doc.get("short_message").is_some());
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Graphite carbon payloads.
+//!
+//! [Specification](https://graphite.readthedocs.io/en/latest/feeding-carbon.html)
+//!
+//! A fixed set of metric paths is generated up-front. The plaintext encoding
+//! writes one `path value timestamp` line per datapoint. The pickle encoding
+//! writes batches, each a four byte big-endian length followed by a protocol 2
+//! pickle of a list of `(path, (timestamp, value))` tuples.
+
+use std::{io::Write, ops::RangeInclusive};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::{
+    common::{strings, TIMESTAMP_BASE_SECONDS},
+    Error, Serialize,
+};
+
+/// The bytes of a pickled batch that are not datapoints: the length prefix,
+/// `PROTO 2`, `EMPTY_LIST`, `MARK`, `APPENDS` and `STOP`.
+const PICKLE_BATCH_OVERHEAD: usize = 4 + 2 + 1 + 1 + 1 + 1;
+
+/// The bytes of a pickled datapoint that are not its path: `BINUNICODE` and
+/// its length, `BININT` and its value, `BINFLOAT` and its value and two
+/// `TUPLE2`.
+const PICKLE_DATAPOINT_OVERHEAD: usize = 1 + 4 + 1 + 4 + 1 + 8 + 1 + 1;
+
+mod opcode {
+    pub(super) const PROTO: u8 = 0x80;
+    pub(super) const EMPTY_LIST: u8 = b']';
+    pub(super) const MARK: u8 = b'(';
+    pub(super) const BINUNICODE: u8 = b'X';
+    pub(super) const BININT: u8 = b'J';
+    pub(super) const BINFLOAT: u8 = b'G';
+    pub(super) const TUPLE2: u8 = 0x86;
+    pub(super) const APPENDS: u8 = b'e';
+    pub(super) const STOP: u8 = b'.';
+}
+
+fn paths_minimum() -> u32 {
+    100
+}
+
+fn paths_maximum() -> u32 {
+    10_000
+}
+
+fn path_depth_minimum() -> u8 {
+    2
+}
+
+fn path_depth_maximum() -> u8 {
+    8
+}
+
+fn segment_length_minimum() -> u16 {
+    2
+}
+
+fn segment_length_maximum() -> u16 {
+    16
+}
+
+fn pickle_batch_size() -> u16 {
+    500
+}
+
+/// The carbon protocol to encode datapoints with
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// Newline delimited `path value timestamp` lines
+    Plaintext,
+    /// Length-prefixed pickled batches
+    Pickle,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Plaintext
+    }
+}
+
+/// Configure the Graphite payload.
+///
+/// All ranges are inclusive. A minimum must not exceed its maximum.
+#[derive(Debug, Deserialize, Clone, PartialEq, Copy)]
+pub struct Config {
+    /// The carbon protocol to encode datapoints with
+    #[serde(default)]
+    pub encoding: Encoding,
+
+    /// Minimum number of distinct metric paths to generate
+    #[serde(default = "paths_minimum")]
+    pub paths_minimum: u32,
+
+    /// Maximum number of distinct metric paths to generate
+    #[serde(default = "paths_maximum")]
+    pub paths_maximum: u32,
+
+    /// Minimum number of dot separated segments in a path
+    #[serde(default = "path_depth_minimum")]
+    pub path_depth_minimum: u8,
+
+    /// Maximum number of dot separated segments in a path
+    #[serde(default = "path_depth_maximum")]
+    pub path_depth_maximum: u8,
+
+    /// Minimum length of a path segment
+    #[serde(default = "segment_length_minimum")]
+    pub segment_length_minimum: u16,
+
+    /// Maximum length of a path segment
+    #[serde(default = "segment_length_maximum")]
+    pub segment_length_maximum: u16,
+
+    /// Maximum number of datapoints in a pickled batch
+    #[serde(default = "pickle_batch_size")]
+    pub pickle_batch_size: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::default(),
+            paths_minimum: paths_minimum(),
+            paths_maximum: paths_maximum(),
+            path_depth_minimum: path_depth_minimum(),
+            path_depth_maximum: path_depth_maximum(),
+            segment_length_minimum: segment_length_minimum(),
+            segment_length_maximum: segment_length_maximum(),
+            pickle_batch_size: pickle_batch_size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A generator for Graphite carbon payloads
+pub struct Graphite {
+    encoding: Encoding,
+    paths: Vec<String>,
+    pickle_batch_size: usize,
+}
+
+impl Graphite {
+    /// Create a new instance of `Graphite`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if any minimum in `config` exceeds its maximum or if
+    /// there are no paths.
+    pub fn new<R>(config: &Config, mut rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let segments = strings::Pool::with_size(&mut rng, 1_000_000);
+        let segment_length: RangeInclusive<u16> =
+            config.segment_length_minimum..=config.segment_length_maximum;
+
+        let total = rng.gen_range(config.paths_minimum..=config.paths_maximum);
+        let mut paths: Vec<String> = (0..total)
+            .map(|_| {
+                let depth = rng.gen_range(config.path_depth_minimum..=config.path_depth_maximum);
+                (0..depth)
+                    .map(|_| {
+                        let length = rng.gen_range(segment_length.clone());
+                        segments
+                            .of_size(&mut rng, usize::from(length))
+                            .expect("pool is larger than any segment")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+            .filter(|path| !path.is_empty())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        assert!(!paths.is_empty(), "there must be paths");
+
+        Self {
+            encoding: config.encoding,
+            paths,
+            pickle_batch_size: usize::from(config.pickle_batch_size.max(1)),
+        }
+    }
+
+    fn datapoint<R>(&self, rng: &mut R) -> (&str, f64, i32)
+    where
+        R: Rng + ?Sized,
+    {
+        let path = self.paths.choose(rng).unwrap();
+        let value = rng.gen_range(-1_000_000.0..1_000_000.0);
+        let timestamp = TIMESTAMP_BASE_SECONDS + rng.gen_range(0..3_600);
+        (path, value, timestamp)
+    }
+
+    fn plaintext<W, R>(&self, rng: &mut R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + ?Sized,
+        W: Write,
+    {
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let (path, value, timestamp) = self.datapoint(rng);
+            let encoding = format!("{path} {value} {timestamp}");
+            let line_length = encoding.len() + 1; // add one for the newline
+            match bytes_remaining.checked_sub(line_length) {
+                Some(remainder) => {
+                    writeln!(writer, "{encoding}")?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn pickle<W, R>(&self, rng: &mut R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + ?Sized,
+        W: Write,
+    {
+        let mut bytes_remaining = max_bytes;
+        let mut batch: Vec<u8> = Vec::new();
+        while let Some(mut batch_remaining) = bytes_remaining.checked_sub(PICKLE_BATCH_OVERHEAD) {
+            batch.clear();
+            batch.extend_from_slice(&[opcode::PROTO, 2, opcode::EMPTY_LIST, opcode::MARK]);
+            let mut datapoints = 0;
+            while datapoints < self.pickle_batch_size {
+                let (path, value, timestamp) = self.datapoint(rng);
+                match batch_remaining.checked_sub(PICKLE_DATAPOINT_OVERHEAD + path.len()) {
+                    Some(remainder) => batch_remaining = remainder,
+                    None => break,
+                }
+                let path_length = u32::try_from(path.len()).expect("path length exceeds u32");
+                batch.push(opcode::BINUNICODE);
+                batch.extend_from_slice(&path_length.to_le_bytes());
+                batch.extend_from_slice(path.as_bytes());
+                batch.push(opcode::BININT);
+                batch.extend_from_slice(&timestamp.to_le_bytes());
+                batch.push(opcode::BINFLOAT);
+                batch.extend_from_slice(&value.to_be_bytes());
+                batch.push(opcode::TUPLE2);
+                batch.push(opcode::TUPLE2);
+                datapoints += 1;
+            }
+            if datapoints == 0 {
+                break;
+            }
+            batch.extend_from_slice(&[opcode::APPENDS, opcode::STOP]);
+
+            let batch_length = u32::try_from(batch.len()).expect("batch length exceeds u32");
+            writer.write_all(&batch_length.to_be_bytes())?;
+            writer.write_all(&batch)?;
+            bytes_remaining -= batch.len() + 4;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Graphite {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        match self.encoding {
+            Encoding::Plaintext => self.plaintext(&mut rng, max_bytes, writer),
+            Encoding::Pickle => self.pickle(&mut rng, max_bytes, writer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::{
+        graphite::{Config, Encoding},
+        Graphite, Serialize,
+    };
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16, pickle: bool) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                encoding: if pickle { Encoding::Pickle } else { Encoding::Plaintext },
+                ..Config::default()
+            };
+            let graphite = Graphite::new(&config, &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            graphite.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes, "max len: {max_bytes}, actual: {}", bytes.len());
+        }
+    }
+
+    // We want to be sure that every pickled batch is exactly as long as its
+    // length prefix claims and is a complete pickle.
+    proptest! {
+        #[test]
+        fn pickle_batches_are_framed(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                encoding: Encoding::Pickle,
+                pickle_batch_size: 10,
+                ..Config::default()
+            };
+            let graphite = Graphite::new(&config, &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            graphite.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            let mut rest = bytes.as_slice();
+            while !rest.is_empty() {
+                let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+                let batch = &rest[4..4 + length];
+                assert_eq!(&batch[..2], &[0x80, 2]);
+                assert_eq!(batch.last(), Some(&b'.'));
+                rest = &rest[4 + length..];
+            }
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! InfluxDB line protocol payload.
+//!
+//! [Specification](https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/)
+//!
+//! A fixed set of measurements is generated up-front, each with its tag keys,
+//! tag sets and typed fields. Every line is a point of a random measurement
+//! and tag set with freshly generated field values and a nanosecond timestamp.
+//! Names and values are alphanumeric so nothing needs escaping.
+
+use std::{fmt, io::Write, ops::RangeInclusive};
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::{
+    common::{strings, TIMESTAMP_BASE_SECONDS},
+    Error, Serialize,
+};
+
+fn measurements_minimum() -> u16 {
+    10
+}
+
+fn measurements_maximum() -> u16 {
+    100
+}
+
+fn tags_per_measurement_minimum() -> u16 {
+    0
+}
+
+fn tags_per_measurement_maximum() -> u16 {
+    8
+}
+
+fn tag_sets_per_measurement_minimum() -> u16 {
+    1
+}
+
+fn tag_sets_per_measurement_maximum() -> u16 {
+    50
+}
+
+fn fields_per_measurement_minimum() -> u16 {
+    1
+}
+
+fn fields_per_measurement_maximum() -> u16 {
+    10
+}
+
+fn name_length_minimum() -> u16 {
+    4
+}
+
+fn name_length_maximum() -> u16 {
+    32
+}
+
+fn key_length_minimum() -> u16 {
+    2
+}
+
+fn key_length_maximum() -> u16 {
+    20
+}
+
+fn value_length_minimum() -> u16 {
+    1
+}
+
+fn value_length_maximum() -> u16 {
+    32
+}
+
+/// Weights for field value types: float, integer, etc
+///
+/// Defines the relative probability of each type of field.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct FieldWeights {
+    float: u8,
+    integer: u8,
+    string: u8,
+    boolean: u8,
+}
+
+impl Default for FieldWeights {
+    fn default() -> Self {
+        FieldWeights {
+            float: 50,   // 50%
+            integer: 30, // 30%
+            string: 10,  // 10%
+            boolean: 10, // 10%
+        }
+    }
+}
+
+/// Configure the InfluxDB line protocol payload.
+///
+/// All ranges are inclusive. A minimum must not exceed its maximum.
+#[derive(Debug, Deserialize, Clone, PartialEq, Copy)]
+pub struct Config {
+    /// Minimum number of measurements to generate
+    #[serde(default = "measurements_minimum")]
+    pub measurements_minimum: u16,
+
+    /// Maximum number of measurements to generate
+    #[serde(default = "measurements_maximum")]
+    pub measurements_maximum: u16,
+
+    /// Minimum number of tag keys per measurement
+    #[serde(default = "tags_per_measurement_minimum")]
+    pub tags_per_measurement_minimum: u16,
+
+    /// Maximum number of tag keys per measurement
+    #[serde(default = "tags_per_measurement_maximum")]
+    pub tags_per_measurement_maximum: u16,
+
+    /// Minimum number of distinct tag sets, series, per measurement
+    #[serde(default = "tag_sets_per_measurement_minimum")]
+    pub tag_sets_per_measurement_minimum: u16,
+
+    /// Maximum number of distinct tag sets, series, per measurement
+    #[serde(default = "tag_sets_per_measurement_maximum")]
+    pub tag_sets_per_measurement_maximum: u16,
+
+    /// Minimum number of fields per measurement
+    #[serde(default = "fields_per_measurement_minimum")]
+    pub fields_per_measurement_minimum: u16,
+
+    /// Maximum number of fields per measurement
+    #[serde(default = "fields_per_measurement_maximum")]
+    pub fields_per_measurement_maximum: u16,
+
+    /// Minimum length of a measurement name
+    #[serde(default = "name_length_minimum")]
+    pub name_length_minimum: u16,
+
+    /// Maximum length of a measurement name
+    #[serde(default = "name_length_maximum")]
+    pub name_length_maximum: u16,
+
+    /// Minimum length of a tag or field key
+    #[serde(default = "key_length_minimum")]
+    pub key_length_minimum: u16,
+
+    /// Maximum length of a tag or field key
+    #[serde(default = "key_length_maximum")]
+    pub key_length_maximum: u16,
+
+    /// Minimum length of a tag value or string field value
+    #[serde(default = "value_length_minimum")]
+    pub value_length_minimum: u16,
+
+    /// Maximum length of a tag value or string field value
+    #[serde(default = "value_length_maximum")]
+    pub value_length_maximum: u16,
+
+    /// Defines the relative probability of each type of field.
+    #[serde(default)]
+    pub field_weights: FieldWeights,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            measurements_minimum: measurements_minimum(),
+            measurements_maximum: measurements_maximum(),
+            tags_per_measurement_minimum: tags_per_measurement_minimum(),
+            tags_per_measurement_maximum: tags_per_measurement_maximum(),
+            tag_sets_per_measurement_minimum: tag_sets_per_measurement_minimum(),
+            tag_sets_per_measurement_maximum: tag_sets_per_measurement_maximum(),
+            fields_per_measurement_minimum: fields_per_measurement_minimum(),
+            fields_per_measurement_maximum: fields_per_measurement_maximum(),
+            name_length_minimum: name_length_minimum(),
+            name_length_maximum: name_length_maximum(),
+            key_length_minimum: key_length_minimum(),
+            key_length_maximum: key_length_maximum(),
+            value_length_minimum: value_length_minimum(),
+            value_length_maximum: value_length_maximum(),
+            field_weights: FieldWeights::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Float,
+    Integer,
+    String,
+    Boolean,
+}
+
+#[derive(Debug, Clone)]
+struct Measurement {
+    name: String,
+    /// Each tag set rendered with its leading comma, possibly empty.
+    tag_sets: Vec<String>,
+    fields: Vec<(String, FieldKind)>,
+}
+
+#[derive(Debug)]
+enum FieldValue<'a> {
+    Float(f64),
+    Integer(i64),
+    String(&'a str),
+    Boolean(bool),
+}
+
+impl fmt::Display for FieldValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Float(v) => write!(f, "{v:?}"),
+            FieldValue::Integer(v) => write!(f, "{v}i"),
+            FieldValue::String(v) => write!(f, "\"{v}\""),
+            FieldValue::Boolean(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A generator for InfluxDB line protocol payloads
+pub struct InfluxLineProtocol {
+    measurements: Vec<Measurement>,
+    values: strings::Pool,
+    value_length: RangeInclusive<u16>,
+}
+
+impl InfluxLineProtocol {
+    /// Create a new instance of `InfluxLineProtocol`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if any minimum in `config` exceeds its maximum, if
+    /// there are no measurements, or if all field weights are zero.
+    pub fn new<R>(config: &Config, mut rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let names = strings::Pool::with_size(&mut rng, 1_000_000);
+        let values = strings::Pool::with_size(&mut rng, 1_000_000);
+        let kinds = [
+            FieldKind::Float,
+            FieldKind::Integer,
+            FieldKind::String,
+            FieldKind::Boolean,
+        ];
+        let field_weights = WeightedIndex::new([
+            config.field_weights.float,
+            config.field_weights.integer,
+            config.field_weights.string,
+            config.field_weights.boolean,
+        ])
+        .expect("field weights must not all be zero");
+
+        let name_length = config.name_length_minimum..=config.name_length_maximum;
+        let key_length = config.key_length_minimum..=config.key_length_maximum;
+        let value_length = config.value_length_minimum..=config.value_length_maximum;
+
+        let total = rng.gen_range(config.measurements_minimum..=config.measurements_maximum);
+        let measurements: Vec<Measurement> = (0..total)
+            .map(|_| {
+                let name = names.random_str(&mut rng, name_length.clone());
+
+                // Tag keys are sorted, as Influx recommends, and unique.
+                let mut tag_keys: Vec<String> = (0..rng.gen_range(
+                    config.tags_per_measurement_minimum..=config.tags_per_measurement_maximum,
+                ))
+                    .map(|_| names.random_str(&mut rng, key_length.clone()))
+                    .collect();
+                tag_keys.sort();
+                tag_keys.dedup();
+
+                // Without tags a measurement has a single series.
+                let total_tag_sets = if tag_keys.is_empty() {
+                    1
+                } else {
+                    rng.gen_range(
+                        config.tag_sets_per_measurement_minimum
+                            ..=config.tag_sets_per_measurement_maximum,
+                    )
+                };
+                let mut tag_sets: Vec<String> = (0..total_tag_sets)
+                    .map(|_| {
+                        tag_keys
+                            .iter()
+                            .map(|key| {
+                                let value = values.random_str(&mut rng, value_length.clone());
+                                format!(",{key}={value}")
+                            })
+                            .collect()
+                    })
+                    .collect();
+                tag_sets.sort();
+                tag_sets.dedup();
+
+                let mut fields: Vec<(String, FieldKind)> = (0..rng.gen_range(
+                    config.fields_per_measurement_minimum..=config.fields_per_measurement_maximum,
+                ))
+                    .map(|_| {
+                        let key = names.random_str(&mut rng, key_length.clone());
+                        (key, kinds[field_weights.sample(&mut rng)])
+                    })
+                    .collect();
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                fields.dedup_by(|a, b| a.0 == b.0);
+
+                Measurement {
+                    name,
+                    tag_sets,
+                    fields,
+                }
+            })
+            // A point must have at least one field.
+            .filter(|m| !m.fields.is_empty())
+            .collect();
+        assert!(!measurements.is_empty(), "there must be measurements");
+
+        Self {
+            measurements,
+            values,
+            value_length,
+        }
+    }
+
+    fn line<R>(&self, rng: &mut R) -> String
+    where
+        R: Rng + ?Sized,
+    {
+        let measurement = self.measurements.choose(rng).unwrap();
+        let tag_set = measurement.tag_sets.choose(rng).unwrap();
+        let fields: Vec<String> = measurement
+            .fields
+            .iter()
+            .map(|(key, kind)| {
+                let value = match kind {
+                    FieldKind::Float => FieldValue::Float(rng.gen_range(-1_000_000.0..1_000_000.0)),
+                    FieldKind::Integer => FieldValue::Integer(rng.gen()),
+                    FieldKind::String => {
+                        let length = rng.gen_range(self.value_length.clone());
+                        FieldValue::String(
+                            self.values
+                                .of_size(rng, usize::from(length))
+                                .expect("pool is larger than any value"),
+                        )
+                    }
+                    FieldKind::Boolean => FieldValue::Boolean(rng.gen()),
+                };
+                format!("{key}={value}")
+            })
+            .collect();
+        // Timestamps are in nanoseconds.
+        let timestamp =
+            i64::from(TIMESTAMP_BASE_SECONDS) * 1_000_000_000 + rng.gen_range(0..3_600_000_000_000);
+        format!(
+            "{name}{tag_set} {fields} {timestamp}",
+            name = measurement.name,
+            fields = fields.join(","),
+        )
+    }
+}
+
+impl Serialize for InfluxLineProtocol {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let encoding = self.line(&mut rng);
+            let line_length = encoding.len() + 1; // add one for the newline
+            match bytes_remaining.checked_sub(line_length) {
+                Some(remainder) => {
+                    writeln!(writer, "{encoding}")?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::{influx_line_protocol::Config, InfluxLineProtocol, Serialize};
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let influx = InfluxLineProtocol::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            influx.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes, "max len: {max_bytes}, actual: {}", bytes.len());
+        }
+    }
+
+    // We want to be sure that every line has a measurement, a field set and a
+    // timestamp.
+    proptest! {
+        #[test]
+        fn lines_are_well_formed(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let influx = InfluxLineProtocol::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::new();
+            influx.to_bytes(rng, 4096, &mut bytes).unwrap();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let parts: Vec<&str> = line.split(' ').collect();
+                assert_eq!(parts.len(), 3, "{line}");
+                assert!(parts[1].split(',').all(|field| field.contains('=')), "{line}");
+                assert!(parts[2].parse::<i64>().is_ok(), "{line}");
+            }
+        }
+    }
+}
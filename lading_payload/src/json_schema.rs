@@ -0,0 +1,1062 @@
+//! JSON Schema driven payload.
+//!
+//! [Specification](https://json-schema.org/specification)
+//!
+//! The schema is read once and compiled into a generator of conforming
+//! newline delimited JSON documents. The following keywords are honored:
+//!
+//! * `type`, a single type or a list of them, inferred from the other keywords
+//!   when absent
+//! * `enum` and `const`
+//! * `minimum`, `maximum`, `exclusiveMinimum` and `exclusiveMaximum`
+//! * `minLength`, `maxLength`, `pattern` and the `date-time`, `uuid`, `ipv4`
+//!   and `ipv6` formats
+//! * `items`, `minItems` and `maxItems`
+//! * `properties` and `required`
+//! * `anyOf`, `oneOf` and `allOf`, the latter by shallow merge
+//! * `$ref` to a location within the same document, recursively if need be
+//!
+//! Other keywords are ignored. Properties not in `required` are present with
+//! probability `optional_property_probability`. Objects and arrays nested more
+//! than `maximum_depth` deep are generated empty, whatever their constraints,
+//! so that recursive schemas terminate. A `$ref` that leads back to itself
+//! without passing through an object or array is rejected.
+//!
+//! Some constraints are honored on a best effort basis only:
+//!
+//! * a string with a `pattern` is generated a few times in search of one within
+//!   `minLength` and `maxLength`, the last attempt is kept whatever its length
+//! * a string with neither `pattern` nor `format` is at most 999,999 bytes long,
+//!   whatever its `minLength`
+
+use std::{
+    fs,
+    io::Write,
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use rand::{seq::SliceRandom, Rng};
+use regex_syntax::hir::{self, Hir, HirKind};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    common::{strings, TIMESTAMP_BASE_SECONDS},
+    Serialize,
+};
+
+/// Range of numbers generated when a schema leaves one or both bounds open.
+const NUMBER_SPAN: i64 = 1_000_000;
+
+/// Length of strings and arrays when a schema sets no maximum.
+const DEFAULT_MAXIMUM_LENGTH: usize = 32;
+
+/// Extra repetitions allowed of an unbounded pattern repetition, `*` or `+`.
+const UNBOUNDED_REPETITION: u32 = 8;
+
+/// The number of strings generated from a pattern in search of one that
+/// satisfies the length constraints.
+const PATTERN_ATTEMPTS: usize = 8;
+
+fn optional_property_probability() -> f32 {
+    0.5
+}
+
+fn maximum_depth() -> u8 {
+    8
+}
+
+/// Errors produced when compiling a [`JsonSchema`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The schema could not be read
+    #[error("schema could not be read: {0}")]
+    Io(#[from] std::io::Error),
+    /// The schema is not JSON
+    #[error("schema is not JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A `pattern` is not a valid regular expression
+    #[error("invalid pattern: {0}")]
+    Pattern(#[from] Box<regex_syntax::Error>),
+    /// A `$ref` does not point within the schema
+    #[error("unresolvable reference {0}")]
+    Reference(String),
+    /// A `type` is not a JSON Schema type
+    #[error("unsupported type {0}")]
+    Type(String),
+    /// A lower bound exceeds its upper bound
+    #[error("schema {0} has a minimum greater than its maximum")]
+    EmptyRange(Value),
+    /// The bounds of a number are too far apart to sample between
+    #[error("schema {0} has a range too wide to sample")]
+    WideRange(Value),
+    /// A schema admits no values, it is `false` or has an empty `enum`
+    #[error("schema {0} is unsatisfiable")]
+    Unsatisfiable(Value),
+}
+
+/// Configure the JSON Schema payload.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Config {
+    /// Path to the JSON Schema document
+    pub schema_path: PathBuf,
+
+    /// The probability that a property absent from `required` is generated
+    #[serde(default = "optional_property_probability")]
+    pub optional_property_probability: f32,
+
+    /// Objects and arrays nested deeper than this are generated empty
+    #[serde(default = "maximum_depth")]
+    pub maximum_depth: u8,
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Pool,
+    Pattern(Hir),
+    DateTime,
+    Uuid,
+    Ipv4,
+    Ipv6,
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    node: usize,
+    required: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    /// A `$ref` whose target is being compiled
+    Reference,
+    Any,
+    Null,
+    Boolean,
+    Integer(RangeInclusive<i64>),
+    Number {
+        range: RangeInclusive<f64>,
+        exclusive_minimum: bool,
+        exclusive_maximum: bool,
+    },
+    String {
+        length: RangeInclusive<usize>,
+        content: Content,
+    },
+    Enum(Vec<Value>),
+    Array {
+        items: usize,
+        length: RangeInclusive<usize>,
+    },
+    Object(Vec<Property>),
+    OneOf(Vec<usize>),
+}
+
+/// Compiles a schema into an arena of nodes, children referenced by index so
+/// that recursive `$ref` cycles are possible.
+struct Compiler<'a> {
+    root: &'a Value,
+    nodes: Vec<Node>,
+    references: FxHashMap<String, usize>,
+    /// The `$ref`s of the `allOf` schemas being merged
+    merging: Vec<String>,
+}
+
+impl<'a> Compiler<'a> {
+    fn push(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn compile(&mut self, schema: &Value) -> Result<usize, Error> {
+        let map = match schema {
+            Value::Bool(true) => return Ok(self.push(Node::Any)),
+            Value::Object(map) => map,
+            _ => return Err(Error::Unsatisfiable(schema.clone())),
+        };
+
+        if let Some(reference) = map.get("$ref").and_then(Value::as_str) {
+            return self.reference(reference);
+        }
+        if let Some(value) = map.get("const") {
+            return Ok(self.push(Node::Enum(vec![value.clone()])));
+        }
+        if let Some(values) = map.get("enum").and_then(Value::as_array) {
+            if values.is_empty() {
+                return Err(Error::Unsatisfiable(schema.clone()));
+            }
+            return Ok(self.push(Node::Enum(values.clone())));
+        }
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(schemas) = map.get(keyword).and_then(Value::as_array) {
+                let choices = schemas
+                    .iter()
+                    .map(|schema| self.compile(schema))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if choices.is_empty() {
+                    return Err(Error::Unsatisfiable(schema.clone()));
+                }
+                return Ok(self.push(Node::OneOf(choices)));
+            }
+        }
+        if let Some(schemas) = map.get("allOf").and_then(Value::as_array) {
+            return self.all_of(map, schemas);
+        }
+
+        match map.get("type") {
+            Some(Value::String(kind)) => self.typed(kind, map),
+            Some(Value::Array(kinds)) => {
+                let choices = kinds
+                    .iter()
+                    .map(|kind| match kind {
+                        Value::String(kind) => self.typed(kind, map),
+                        kind => Err(Error::Type(kind.to_string())),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if choices.is_empty() {
+                    return Err(Error::Unsatisfiable(schema.clone()));
+                }
+                Ok(self.push(Node::OneOf(choices)))
+            }
+            Some(kind) => Err(Error::Type(kind.to_string())),
+            None => match infer_type(map) {
+                Some(kind) => self.typed(kind, map),
+                None => Ok(self.push(Node::Any)),
+            },
+        }
+    }
+
+    fn all_of(&mut self, map: &Map<String, Value>, schemas: &[Value]) -> Result<usize, Error> {
+        let mut merged = map.clone();
+        merged.remove("allOf");
+        let depth = self.merging.len();
+        for schema in schemas {
+            let reference = schema.get("$ref").and_then(Value::as_str);
+            if let Some(reference) = reference {
+                // A schema already being merged adds nothing if merged again,
+                // and would be merged without end.
+                if self.merging.iter().any(|merging| merging == reference) {
+                    continue;
+                }
+                self.merging.push(reference.to_string());
+            }
+            if let Value::Object(schema) = self.resolve(schema)? {
+                merge(&mut merged, &schema);
+            }
+        }
+        let compiled = self.compile(&Value::Object(merged));
+        self.merging.truncate(depth);
+        compiled
+    }
+
+    /// Reject `$ref`s that only ever lead back to themselves through `anyOf`,
+    /// `oneOf` or other `$ref`s, generating them would never finish. Cycles
+    /// through objects and arrays end at `maximum_depth`.
+    fn check_cycles(&self) -> Result<(), Error> {
+        let mut productive: Vec<bool> = self
+            .nodes
+            .iter()
+            .map(|node| !matches!(node, Node::OneOf(_)))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (idx, node) in self.nodes.iter().enumerate() {
+                if let Node::OneOf(choices) = node {
+                    if !productive[idx] && choices.iter().any(|choice| productive[*choice]) {
+                        productive[idx] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        // Every such cycle passes through a `$ref`.
+        match self.references.iter().find(|(_, idx)| !productive[**idx]) {
+            Some((reference, _)) => Err(Error::Unsatisfiable(
+                serde_json::json!({ "$ref": reference }),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Follow `schema` if it is a `$ref`, else return it.
+    fn resolve(&self, schema: &Value) -> Result<Value, Error> {
+        match schema.get("$ref").and_then(Value::as_str) {
+            Some(reference) => self.target(reference).cloned(),
+            None => Ok(schema.clone()),
+        }
+    }
+
+    fn target(&self, reference: &str) -> Result<&'a Value, Error> {
+        reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .ok_or_else(|| Error::Reference(reference.to_string()))
+    }
+
+    fn reference(&mut self, reference: &str) -> Result<usize, Error> {
+        if let Some(idx) = self.references.get(reference) {
+            return Ok(*idx);
+        }
+        let target = self.target(reference)?;
+        // Reserve a node for the reference before compiling its target so that
+        // the target may refer back to it.
+        let idx = self.push(Node::Reference);
+        self.references.insert(reference.to_string(), idx);
+        let compiled = self.compile(target)?;
+        self.nodes[idx] = Node::OneOf(vec![compiled]);
+        Ok(idx)
+    }
+
+    fn typed(&mut self, kind: &str, map: &Map<String, Value>) -> Result<usize, Error> {
+        let node = match kind {
+            "null" => Node::Null,
+            "boolean" => Node::Boolean,
+            "integer" => {
+                let minimum = integer_bound(map, "minimum", "exclusiveMinimum", 1);
+                let maximum = integer_bound(map, "maximum", "exclusiveMaximum", -1);
+                let (minimum, maximum) = match (minimum, maximum) {
+                    (Some(minimum), Some(maximum)) => (minimum, maximum),
+                    (Some(minimum), None) => (minimum, minimum.saturating_add(NUMBER_SPAN)),
+                    (None, Some(maximum)) => (maximum.saturating_sub(NUMBER_SPAN), maximum),
+                    (None, None) => (-NUMBER_SPAN, NUMBER_SPAN),
+                };
+                if minimum > maximum {
+                    return Err(Error::EmptyRange(Value::Object(map.clone())));
+                }
+                Node::Integer(minimum..=maximum)
+            }
+            "number" => number(map)?,
+            "string" => {
+                let length = length_range(map, "minLength", "maxLength")?;
+                let content = if let Some(pattern) = map.get("pattern").and_then(Value::as_str) {
+                    Content::Pattern(
+                        regex_syntax::Parser::new()
+                            .parse(pattern)
+                            .map_err(Box::new)?,
+                    )
+                } else {
+                    match map.get("format").and_then(Value::as_str) {
+                        Some("date-time") => Content::DateTime,
+                        Some("uuid") => Content::Uuid,
+                        Some("ipv4") => Content::Ipv4,
+                        Some("ipv6") => Content::Ipv6,
+                        _ => Content::Pool,
+                    }
+                };
+                Node::String { length, content }
+            }
+            "array" => {
+                let length = length_range(map, "minItems", "maxItems")?;
+                let items = match map.get("items") {
+                    Some(items) => self.compile(items)?,
+                    None => self.push(Node::Any),
+                };
+                Node::Array { items, length }
+            }
+            "object" => {
+                let required: Vec<&str> = map
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .map(|names| names.iter().filter_map(Value::as_str).collect())
+                    .unwrap_or_default();
+                let mut properties = Vec::new();
+                if let Some(Value::Object(schemas)) = map.get("properties") {
+                    for (name, schema) in schemas {
+                        let required = required.contains(&name.as_str());
+                        // A property whose schema is `false` must not appear.
+                        if schema == &Value::Bool(false) && !required {
+                            continue;
+                        }
+                        properties.push(Property {
+                            name: name.clone(),
+                            node: self.compile(schema)?,
+                            required,
+                        });
+                    }
+                }
+                for name in required {
+                    if !properties.iter().any(|property| property.name == name) {
+                        properties.push(Property {
+                            name: name.to_string(),
+                            node: self.push(Node::Any),
+                            required: true,
+                        });
+                    }
+                }
+                Node::Object(properties)
+            }
+            kind => return Err(Error::Type(kind.to_string())),
+        };
+        Ok(self.push(node))
+    }
+}
+
+/// Merge the keywords of `other` into `schema`. Properties and required
+/// properties are combined, any other keyword already present is kept.
+fn merge(schema: &mut Map<String, Value>, other: &Map<String, Value>) {
+    for (keyword, value) in other {
+        match (keyword.as_str(), schema.get_mut(keyword), value) {
+            ("properties", Some(Value::Object(properties)), Value::Object(others)) => {
+                for (name, property) in others {
+                    properties
+                        .entry(name.clone())
+                        .or_insert_with(|| property.clone());
+                }
+            }
+            ("required", Some(Value::Array(required)), Value::Array(others)) => {
+                required.extend(others.iter().cloned());
+            }
+            (_, Some(_), _) => {}
+            (_, None, _) => {
+                schema.insert(keyword.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn infer_type(map: &Map<String, Value>) -> Option<&'static str> {
+    let has = |keywords: &[&str]| keywords.iter().any(|keyword| map.contains_key(*keyword));
+    if has(&["properties", "required"]) {
+        Some("object")
+    } else if has(&["items", "minItems", "maxItems"]) {
+        Some("array")
+    } else if has(&["pattern", "minLength", "maxLength", "format"]) {
+        Some("string")
+    } else if has(&["minimum", "maximum", "exclusiveMinimum", "exclusiveMaximum"]) {
+        Some("number")
+    } else {
+        None
+    }
+}
+
+/// The inclusive integer bound of `map`. Fractional bounds are rounded
+/// inwards, which already excludes them, and integral exclusive bounds are
+/// moved one `step` inwards. Where both bounds are present the tighter is
+/// taken.
+#[allow(clippy::cast_possible_truncation, clippy::float_cmp)]
+fn integer_bound(
+    map: &Map<String, Value>,
+    inclusive: &str,
+    exclusive: &str,
+    step: i64,
+) -> Option<i64> {
+    let round = |bound: f64| {
+        if step > 0 {
+            bound.ceil() as i64
+        } else {
+            bound.floor() as i64
+        }
+    };
+    let inclusive = map.get(inclusive).and_then(Value::as_f64).map(round);
+    let exclusive = map.get(exclusive).and_then(Value::as_f64).map(|bound| {
+        if bound.fract() == 0.0 {
+            round(bound).saturating_add(step)
+        } else {
+            round(bound)
+        }
+    });
+    match (inclusive, exclusive) {
+        (Some(inclusive), Some(exclusive)) if step > 0 => Some(inclusive.max(exclusive)),
+        (Some(inclusive), Some(exclusive)) => Some(inclusive.min(exclusive)),
+        (inclusive, exclusive) => inclusive.or(exclusive),
+    }
+}
+
+/// The number range of `map`, bounds open on one or both sides spanning
+/// `NUMBER_SPAN`.
+fn number(map: &Map<String, Value>) -> Result<Node, Error> {
+    let span = NUMBER_SPAN as f64;
+    let minimum = number_bound(map, "minimum", "exclusiveMinimum", true);
+    let maximum = number_bound(map, "maximum", "exclusiveMaximum", false);
+    let ((minimum, exclusive_minimum), (maximum, exclusive_maximum)) = match (minimum, maximum) {
+        (Some(minimum), Some(maximum)) => (minimum, maximum),
+        (Some(minimum), None) => (minimum, (minimum.0 + span, false)),
+        (None, Some(maximum)) => ((maximum.0 - span, false), maximum),
+        (None, None) => ((-span, false), (span, false)),
+    };
+    // The midpoint stands in for samples that land on an exclusive bound, if
+    // it is not strictly within them nothing is.
+    let midpoint = minimum / 2.0 + maximum / 2.0;
+    if minimum > maximum
+        || (exclusive_minimum && midpoint <= minimum)
+        || (exclusive_maximum && midpoint >= maximum)
+    {
+        return Err(Error::EmptyRange(Value::Object(map.clone())));
+    }
+    if !(maximum - minimum).is_finite() {
+        return Err(Error::WideRange(Value::Object(map.clone())));
+    }
+    Ok(Node::Number {
+        range: minimum..=maximum,
+        exclusive_minimum,
+        exclusive_maximum,
+    })
+}
+
+/// The number bound of `map` and whether it is exclusive, the lower bound if
+/// `lower` else the upper. Where both bounds are present the tighter is taken,
+/// the exclusive one if they are equal.
+fn number_bound(
+    map: &Map<String, Value>,
+    inclusive: &str,
+    exclusive: &str,
+    lower: bool,
+) -> Option<(f64, bool)> {
+    let inclusive = map.get(inclusive).and_then(Value::as_f64);
+    let exclusive = map.get(exclusive).and_then(Value::as_f64);
+    match (inclusive, exclusive) {
+        (Some(inclusive), Some(exclusive)) => {
+            let tighter = if lower {
+                exclusive >= inclusive
+            } else {
+                exclusive <= inclusive
+            };
+            if tighter {
+                Some((exclusive, true))
+            } else {
+                Some((inclusive, false))
+            }
+        }
+        (Some(inclusive), None) => Some((inclusive, false)),
+        (None, Some(exclusive)) => Some((exclusive, true)),
+        (None, None) => None,
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn length_range(
+    map: &Map<String, Value>,
+    minimum: &str,
+    maximum: &str,
+) -> Result<RangeInclusive<usize>, Error> {
+    let minimum = map.get(minimum).and_then(Value::as_u64).unwrap_or(0) as usize;
+    let maximum = map
+        .get(maximum)
+        .and_then(Value::as_u64)
+        .map_or(minimum.max(DEFAULT_MAXIMUM_LENGTH), |maximum| {
+            maximum as usize
+        });
+    if minimum > maximum {
+        return Err(Error::EmptyRange(Value::Object(map.clone())));
+    }
+    Ok(minimum..=maximum)
+}
+
+#[derive(Debug, Clone)]
+/// A generator for documents conforming to a JSON Schema
+pub struct JsonSchema {
+    nodes: Vec<Node>,
+    root: usize,
+    optional_property_probability: f32,
+    maximum_depth: u8,
+    pool: strings::Pool,
+}
+
+impl JsonSchema {
+    /// Create a new instance of `JsonSchema`, reading and compiling the schema
+    /// at `config.schema_path`.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the schema cannot be read, is not JSON or uses a
+    /// keyword in a way that cannot be generated, see [`Error`].
+    pub fn new<R>(config: &Config, rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let schema = read(&config.schema_path)?;
+        Self::with_schema(&schema, config, rng)
+    }
+
+    /// Create a new instance of `JsonSchema` from an already parsed `schema`,
+    /// ignoring `config.schema_path`.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the schema uses a keyword in a way that cannot
+    /// be generated, see [`Error`].
+    pub fn with_schema<R>(schema: &Value, config: &Config, rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let mut compiler = Compiler {
+            root: schema,
+            nodes: Vec::new(),
+            references: FxHashMap::default(),
+            merging: Vec::new(),
+        };
+        let root = compiler.compile(schema)?;
+        compiler.check_cycles()?;
+        Ok(Self {
+            nodes: compiler.nodes,
+            root,
+            optional_property_probability: config.optional_property_probability,
+            maximum_depth: config.maximum_depth,
+            pool: strings::Pool::with_size(rng, 1_000_000),
+        })
+    }
+
+    fn generate<R>(&self, rng: &mut R, idx: usize, depth: u8) -> Value
+    where
+        R: Rng + ?Sized,
+    {
+        match &self.nodes[idx] {
+            Node::Reference => unreachable!("references are resolved at compile time"),
+            Node::Any => match rng.gen_range(0..4) {
+                0 => Value::Null,
+                1 => rng.gen::<bool>().into(),
+                2 => rng.gen_range(-NUMBER_SPAN..=NUMBER_SPAN).into(),
+                _ => {
+                    let length = rng.gen_range(1..=DEFAULT_MAXIMUM_LENGTH);
+                    self.pool.of_size(rng, length).unwrap().into()
+                }
+            },
+            Node::Null => Value::Null,
+            Node::Boolean => rng.gen::<bool>().into(),
+            Node::Integer(range) => rng.gen_range(range.clone()).into(),
+            Node::Number {
+                range,
+                exclusive_minimum,
+                exclusive_maximum,
+            } => {
+                let number = rng.gen_range(range.clone());
+                if (*exclusive_minimum && number <= *range.start())
+                    || (*exclusive_maximum && number >= *range.end())
+                {
+                    (range.start() / 2.0 + range.end() / 2.0).into()
+                } else {
+                    number.into()
+                }
+            }
+            Node::String { length, content } => self.string(rng, length, content).into(),
+            Node::Enum(values) => values.choose(rng).unwrap().clone(),
+            Node::Array { items, length } => {
+                if depth >= self.maximum_depth {
+                    return Value::Array(Vec::new());
+                }
+                let total = rng.gen_range(length.clone());
+                (0..total)
+                    .map(|_| self.generate(rng, *items, depth + 1))
+                    .collect()
+            }
+            Node::Object(properties) => {
+                let mut object = Map::new();
+                if depth >= self.maximum_depth {
+                    return Value::Object(object);
+                }
+                for property in properties {
+                    if property.required || rng.gen::<f32>() < self.optional_property_probability {
+                        let value = self.generate(rng, property.node, depth + 1);
+                        object.insert(property.name.clone(), value);
+                    }
+                }
+                Value::Object(object)
+            }
+            Node::OneOf(choices) => {
+                let choice = *choices.choose(rng).unwrap();
+                self.generate(rng, choice, depth)
+            }
+        }
+    }
+
+    fn string<R>(&self, rng: &mut R, length: &RangeInclusive<usize>, content: &Content) -> String
+    where
+        R: Rng + ?Sized,
+    {
+        match content {
+            Content::Pool => {
+                // The pool is too small for the very largest strings, settle
+                // for what fits.
+                let total = rng.gen_range(length.clone()).min(999_999);
+                String::from(self.pool.of_size(rng, total).unwrap())
+            }
+            Content::Pattern(hir) => {
+                let mut buf = String::new();
+                for _ in 0..PATTERN_ATTEMPTS {
+                    buf.clear();
+                    pattern(rng, hir, &mut buf);
+                    if length.contains(&buf.chars().count()) {
+                        break;
+                    }
+                }
+                buf
+            }
+            Content::DateTime => {
+                let seconds = i64::from(TIMESTAMP_BASE_SECONDS) + rng.gen_range(0..3_600);
+                OffsetDateTime::from_unix_timestamp(seconds)
+                    .expect("timestamp is in range")
+                    .format(&Rfc3339)
+                    .unwrap()
+            }
+            Content::Uuid => {
+                let mut buf = String::with_capacity(36);
+                strings::uuid_v4(rng, &mut buf);
+                buf
+            }
+            Content::Ipv4 => Ipv4Addr::from(rng.gen::<u32>()).to_string(),
+            Content::Ipv6 => Ipv6Addr::from(rng.gen::<u128>()).to_string(),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Value, Error> {
+    let contents = fs::read(path)?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+/// Write a string matching `hir` into `buf`. Classes are narrowed to printable
+/// ASCII where they allow it.
+fn pattern<R>(rng: &mut R, hir: &Hir, buf: &mut String)
+where
+    R: Rng + ?Sized,
+{
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => {}
+        HirKind::Literal(hir::Literal(bytes)) => buf.push_str(&String::from_utf8_lossy(bytes)),
+        HirKind::Class(hir::Class::Unicode(class)) => {
+            let mut printable = class.clone();
+            printable.intersect(&hir::ClassUnicode::new([hir::ClassUnicodeRange::new(
+                ' ', '~',
+            )]));
+            let class = if printable.ranges().is_empty() {
+                class
+            } else {
+                &printable
+            };
+            let ranges: Vec<(u32, u32)> = class
+                .ranges()
+                .iter()
+                .map(|range| (u32::from(range.start()), u32::from(range.end())))
+                .collect();
+            if let Some(c) = choose_in_ranges(rng, &ranges).and_then(char::from_u32) {
+                buf.push(c);
+            }
+        }
+        HirKind::Class(hir::Class::Bytes(class)) => {
+            let ranges: Vec<(u32, u32)> = class
+                .ranges()
+                .iter()
+                .filter(|range| range.start().is_ascii())
+                .map(|range| (u32::from(range.start()), u32::from(range.end().min(0x7f))))
+                .collect();
+            if let Some(c) = choose_in_ranges(rng, &ranges).and_then(char::from_u32) {
+                buf.push(c);
+            }
+        }
+        HirKind::Repetition(repetition) => {
+            let maximum = repetition
+                .max
+                .unwrap_or(repetition.min + UNBOUNDED_REPETITION);
+            for _ in 0..rng.gen_range(repetition.min..=maximum) {
+                pattern(rng, &repetition.sub, buf);
+            }
+        }
+        HirKind::Capture(capture) => pattern(rng, &capture.sub, buf),
+        HirKind::Concat(hirs) => {
+            for hir in hirs {
+                pattern(rng, hir, buf);
+            }
+        }
+        HirKind::Alternation(hirs) => {
+            let hir = hirs.choose(rng).unwrap();
+            pattern(rng, hir, buf);
+        }
+    }
+}
+
+/// Choose a value uniformly from the union of the inclusive `ranges`.
+fn choose_in_ranges<R>(rng: &mut R, ranges: &[(u32, u32)]) -> Option<u32>
+where
+    R: Rng + ?Sized,
+{
+    let total: u64 = ranges
+        .iter()
+        .map(|(start, end)| u64::from(end - start) + 1)
+        .sum();
+    if total == 0 {
+        return None;
+    }
+    let mut offset = rng.gen_range(0..total);
+    for (start, end) in ranges {
+        let size = u64::from(end - start) + 1;
+        if offset < size {
+            return Some(start + u32::try_from(offset).unwrap());
+        }
+        offset -= size;
+    }
+    None
+}
+
+impl Serialize for JsonSchema {
+    fn to_bytes<W, R>(
+        &self,
+        mut rng: R,
+        max_bytes: usize,
+        writer: &mut W,
+    ) -> Result<(), crate::Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let document = self.generate(&mut rng, self.root, 0);
+            let encoding = serde_json::to_string(&document)?;
+            let line_length = encoding.len() + 1; // add one for the newline
+
+            match bytes_remaining.checked_sub(line_length) {
+                Some(remainder) => {
+                    writeln!(writer, "{encoding}")?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use serde_json::{json, Value};
+
+    use crate::{
+        json_schema::{integer_bound, Config, Error},
+        JsonSchema, Serialize,
+    };
+
+    fn config() -> Config {
+        Config {
+            schema_path: PathBuf::new(),
+            optional_property_probability: 0.5,
+            maximum_depth: 4,
+        }
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["timestamp", "level", "message", "tags"],
+            "properties": {
+                "timestamp": { "type": "string", "format": "date-time" },
+                "level": { "enum": ["debug", "info", "warn", "error"] },
+                "message": { "type": "string", "minLength": 4, "maxLength": 64 },
+                "request_id": { "type": "string", "pattern": "^req-[0-9a-f]{8}$" },
+                "status": { "type": "integer", "minimum": 100, "exclusiveMaximum": 600 },
+                "latency": { "type": ["number", "null"], "minimum": 0 },
+                "tags": {
+                    "type": "array",
+                    "minItems": 1,
+                    "maxItems": 4,
+                    "items": { "type": "string", "maxLength": 8 }
+                },
+                "span": { "$ref": "#/$defs/span" }
+            },
+            "$defs": {
+                "span": {
+                    "type": "object",
+                    "required": ["id"],
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "parent": { "$ref": "#/$defs/span" }
+                    }
+                }
+            }
+        })
+    }
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let json_schema = JsonSchema::with_schema(&schema(), &config(), &mut rng).unwrap();
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            json_schema.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes, "max len: {max_bytes}, actual: {}", bytes.len());
+        }
+    }
+
+    // We want to be sure that generated documents honor the schema.
+    proptest! {
+        #[test]
+        fn documents_conform(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let json_schema = JsonSchema::with_schema(&schema(), &config(), &mut rng).unwrap();
+
+            let mut bytes = Vec::new();
+            json_schema.to_bytes(rng, 8192, &mut bytes).unwrap();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let doc: Value = serde_json::from_str(line).unwrap();
+                prop_assert!(doc["timestamp"].is_string());
+                prop_assert!(["debug", "info", "warn", "error"].contains(&doc["level"].as_str().unwrap()));
+                let message = doc["message"].as_str().unwrap();
+                prop_assert!((4..=64).contains(&message.len()));
+                if let Some(request_id) = doc.get("request_id") {
+                    let request_id = request_id.as_str().unwrap();
+                    prop_assert_eq!(request_id.len(), 12);
+                    prop_assert!(request_id.starts_with("req-"));
+                    prop_assert!(request_id[4..].chars().all(|c| c.is_ascii_hexdigit()));
+                }
+                if let Some(status) = doc.get("status") {
+                    prop_assert!((100..600).contains(&status.as_i64().unwrap()));
+                }
+                if let Some(latency) = doc.get("latency") {
+                    prop_assert!(latency.is_null() || latency.as_f64().unwrap() >= 0.0);
+                }
+                let tags = doc["tags"].as_array().unwrap();
+                prop_assert!((1..=4).contains(&tags.len()));
+                for tag in tags {
+                    prop_assert!(tag.as_str().unwrap().len() <= 8);
+                }
+                let mut span = doc.get("span");
+                let mut depth = 1;
+                while let Some(inner) = span {
+                    prop_assert!(depth <= 4);
+                    if depth < 4 {
+                        prop_assert_eq!(inner["id"].as_str().unwrap().len(), 36);
+                    }
+                    span = inner.get("parent");
+                    depth += 1;
+                }
+            }
+        }
+    }
+    // We want to be sure that exclusive bounds are never generated and that
+    // the tighter of an inclusive and an exclusive bound is honored.
+    proptest! {
+        #[test]
+        fn exclusive_bounds_excluded(seed: u64) {
+            // Only three numbers lie in the closed range of `narrow`, samples
+            // land on its exclusive minimum often.
+            let narrow_maximum = 1.0 + 2.0 * f64::EPSILON;
+            let schema = json!({
+                "type": "object",
+                "required": ["ratio", "narrow", "count"],
+                "properties": {
+                    "ratio": {
+                        "type": "number",
+                        "minimum": -1,
+                        "exclusiveMinimum": 0,
+                        "exclusiveMaximum": 1
+                    },
+                    "narrow": { "type": "number", "exclusiveMinimum": 1, "maximum": narrow_maximum },
+                    "count": { "type": "integer", "minimum": 0, "exclusiveMinimum": 5, "maximum": 7 }
+                }
+            });
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let json_schema = JsonSchema::with_schema(&schema, &config(), &mut rng).unwrap();
+
+            let mut bytes = Vec::new();
+            json_schema.to_bytes(rng, 8192, &mut bytes).unwrap();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let doc: Value = serde_json::from_str(line).unwrap();
+                let ratio = doc["ratio"].as_f64().unwrap();
+                prop_assert!(ratio > 0.0 && ratio < 1.0, "ratio: {ratio}");
+                let narrow = doc["narrow"].as_f64().unwrap();
+                prop_assert!(narrow > 1.0 && narrow <= narrow_maximum, "narrow: {narrow}");
+                prop_assert!((6..=7).contains(&doc["count"].as_i64().unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_exclusive_range_is_error() {
+        let schema = json!({ "type": "number", "exclusiveMinimum": 1, "maximum": 1 });
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(matches!(
+            JsonSchema::with_schema(&schema, &config(), &mut rng),
+            Err(Error::EmptyRange(_))
+        ));
+    }
+
+    #[test]
+    fn fractional_exclusive_integer_bounds() {
+        for (schema, minimum, maximum) in [
+            (json!({ "exclusiveMinimum": 1 }), Some(2), None),
+            (json!({ "exclusiveMinimum": 1.5 }), Some(2), None),
+            (json!({ "minimum": 1.5 }), Some(2), None),
+            (json!({ "exclusiveMaximum": 3 }), None, Some(2)),
+            (json!({ "exclusiveMaximum": 2.5 }), None, Some(2)),
+            (json!({ "maximum": 2.5 }), None, Some(2)),
+        ] {
+            let map = schema.as_object().unwrap();
+            assert_eq!(
+                integer_bound(map, "minimum", "exclusiveMinimum", 1),
+                minimum,
+                "{schema}"
+            );
+            assert_eq!(
+                integer_bound(map, "maximum", "exclusiveMaximum", -1),
+                maximum,
+                "{schema}"
+            );
+        }
+
+        let schema = json!({ "type": "integer", "exclusiveMinimum": 1.5, "maximum": 2 });
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(JsonSchema::with_schema(&schema, &config(), &mut rng).is_ok());
+    }
+
+    #[test]
+    fn wide_number_range_is_error() {
+        let schema = json!({ "type": "number", "minimum": -1e308, "maximum": 1e308 });
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(matches!(
+            JsonSchema::with_schema(&schema, &config(), &mut rng),
+            Err(Error::WideRange(_))
+        ));
+    }
+
+    #[test]
+    fn reference_cycle_is_error() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for schema in [
+            json!({ "$ref": "#" }),
+            json!({ "anyOf": [{ "$ref": "#" }] }),
+            json!({
+                "$ref": "#/$defs/a",
+                "$defs": { "a": { "$ref": "#/$defs/b" }, "b": { "$ref": "#/$defs/a" } }
+            }),
+        ] {
+            assert!(
+                matches!(
+                    JsonSchema::with_schema(&schema, &config(), &mut rng),
+                    Err(Error::Unsatisfiable(_))
+                ),
+                "{schema}"
+            );
+        }
+    }
+
+    // A cycle with a way out, or one through `allOf` that adds nothing, is
+    // fine.
+    #[test]
+    fn reference_cycle_with_exit() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for schema in [
+            json!({ "anyOf": [{ "$ref": "#" }, { "type": "null" }] }),
+            json!({ "type": "null", "allOf": [{ "$ref": "#" }] }),
+        ] {
+            let json_schema = JsonSchema::with_schema(&schema, &config(), &mut rng).unwrap();
+            let mut bytes = Vec::new();
+            json_schema.to_bytes(&mut rng, 512, &mut bytes).unwrap();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                assert_eq!(line, "null");
+            }
+        }
+    }
+}
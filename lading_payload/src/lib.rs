@@ -20,40 +20,66 @@
 #![allow(clippy::multiple_crate_versions)]
 
 use std::{
+    fmt,
     io::{self, Write},
     path::PathBuf,
 };
 
 use rand::Rng;
-use serde::Deserialize;
+use serde::{
+    de::{
+        self,
+        value::{EnumAccessDeserializer, MapAccessDeserializer},
+        EnumAccess, IntoDeserializer, MapAccess, Visitor,
+    },
+    Deserialize,
+};
 
 pub use apache_common::ApacheCommon;
 pub use ascii::Ascii;
+pub use container_log::{Cri, DockerJsonFile};
 pub use datadog_logs::DatadogLog;
 pub use dogstatsd::DogStatsD;
 pub use fluent::Fluent;
+pub use gelf::Gelf;
+pub use graphite::Graphite;
+pub use influx_line_protocol::InfluxLineProtocol;
 pub use json::Json;
+pub use json_schema::JsonSchema;
 pub use opentelemetry_log::OpentelemetryLogs;
 pub use opentelemetry_metric::OpentelemetryMetrics;
 pub use opentelemetry_trace::OpentelemetryTraces;
+pub use prometheus::Prometheus;
+pub use prometheus_remote_write::PrometheusRemoteWrite;
+pub use security_event::{Cef, Leef};
 pub use splunk_hec::SplunkHec;
 pub use statik::Static;
-pub use syslog::Syslog5424;
+pub use syslog::{Syslog3164, Syslog5424};
+pub use template::Template;
 pub use trace_agent::TraceAgent;
 
 pub mod apache_common;
 pub mod ascii;
 pub(crate) mod common;
+pub mod container_log;
 pub mod datadog_logs;
 pub mod dogstatsd;
 pub mod fluent;
+pub mod gelf;
+pub mod graphite;
+pub mod influx_line_protocol;
 pub mod json;
+pub mod json_schema;
 pub mod opentelemetry_log;
 pub mod opentelemetry_metric;
 pub mod opentelemetry_trace;
+pub mod prometheus;
+pub mod prometheus_remote_write;
+pub mod security_event;
 pub mod splunk_hec;
 pub mod statik;
 pub mod syslog;
+pub mod template;
 pub mod trace_agent;
 
 /// Errors related to serialization
@@ -68,6 +94,9 @@ pub enum Error {
     /// IO operation failed
     #[error("IO operation failed: {0}")]
     Io(#[from] io::Error),
+    /// Snappy compression failed
+    #[error("Snappy compression failed: {0}")]
+    Snappy(#[from] snap::Error),
 }
 
 /// To serialize into bytes
@@ -97,12 +126,15 @@ pub enum Encoding {
 
 /// Configuration for `Payload`
 #[derive(Debug, Deserialize, Clone, PartialEq)]
-#[serde(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case", remote = "Self")]
 pub enum Config {
     /// Generates Fluent messages
     Fluent,
-    /// Generates syslog5424 messages
-    Syslog5424,
+    /// Generates syslog5424 messages with the configured framing. A bare
+    /// `syslog5424` takes the default framing.
+    Syslog5424(crate::syslog::Config),
+    /// Generates BSD syslog3164 messages with the configured framing
+    Syslog3164(crate::syslog::Config),
     /// Generates Splunk HEC messages
     SplunkHec {
         /// Defines the encoding to use for the Splunk HEC messages.
@@ -120,6 +152,8 @@ pub enum Config {
     Ascii,
     /// Generates a json encoded line
     Json,
+    /// Generates json encoded lines conforming to a user supplied JSON Schema
+    JsonSchema(crate::json_schema::Config),
     /// Generates a Apache Common log lines
     ApacheCommon,
     /// Generates OpenTelemetry traces
@@ -131,10 +165,76 @@ pub enum Config {
     /// Generates DogStatsD
     #[serde(rename = "dogstatsd")]
     DogStatsD(crate::dogstatsd::Config),
+    /// Generates Prometheus exposition text
+    Prometheus(crate::prometheus::Config),
+    /// Generates Prometheus remote write requests
+    PrometheusRemoteWrite(crate::prometheus_remote_write::Config),
+    /// Generates InfluxDB line protocol
+    InfluxLineProtocol(crate::influx_line_protocol::Config),
+    /// Generates Graphite carbon plaintext or pickle
+    Graphite(crate::graphite::Config),
+    /// Generates GELF messages
+    Gelf(crate::gelf::Config),
+    /// Generates ArcSight CEF security events
+    Cef(crate::security_event::Config),
+    /// Generates IBM LEEF security events
+    Leef(crate::security_event::Config),
+    /// Generates Kubernetes CRI container log lines
+    Cri(crate::container_log::Config),
+    /// Generates docker json-file container log lines
+    DockerJsonFile(crate::container_log::Config),
+    /// Generates lines from a user-defined template
+    Template(crate::template::Config),
     /// Generates TraceAgent payloads in JSON format
     TraceAgent(Encoding),
 }
 
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // `syslog5424` predates its configuration and is commonly given as a
+        // bare string, which serde will not deserialize into a variant with
+        // content. Everything else is handed to the derived implementation.
+        struct ConfigVisitor;
+
+        impl<'de> Visitor<'de> for ConfigVisitor {
+            type Value = Config;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a payload variant")
+            }
+
+            fn visit_str<E>(self, variant: &str) -> Result<Config, E>
+            where
+                E: de::Error,
+            {
+                if variant == "syslog5424" {
+                    return Ok(Config::Syslog5424(crate::syslog::Config::default()));
+                }
+                Config::deserialize(variant.into_deserializer())
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Config, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                Config::deserialize(MapAccessDeserializer::new(map))
+            }
+
+            fn visit_enum<A>(self, data: A) -> Result<Config, A::Error>
+            where
+                A: EnumAccess<'de>,
+            {
+                Config::deserialize(EnumAccessDeserializer::new(data))
+            }
+        }
+
+        deserializer.deserialize_any(ConfigVisitor)
+    }
+}
+
 #[derive(Debug)]
 #[allow(dead_code, clippy::large_enum_variant)]
 pub(crate) enum Payload {
@@ -143,13 +243,25 @@ pub(crate) enum Payload {
     DatadogLog(DatadogLog),
     Fluent(Fluent),
     Json(Json),
+    JsonSchema(JsonSchema),
     SplunkHec(splunk_hec::SplunkHec),
     Static(Static),
     Syslog(Syslog5424),
+    Syslog3164(Syslog3164),
     OtelTraces(OpentelemetryTraces),
     OtelLogs(OpentelemetryLogs),
     OtelMetrics(OpentelemetryMetrics),
     DogStatsdD(DogStatsD),
+    Prometheus(Prometheus),
+    PrometheusRemoteWrite(PrometheusRemoteWrite),
+    InfluxLineProtocol(InfluxLineProtocol),
+    Graphite(Graphite),
+    Gelf(Gelf),
+    Cef(Cef),
+    Leef(Leef),
+    Cri(Cri),
+    DockerJsonFile(DockerJsonFile),
+    Template(Template),
     TraceAgent(TraceAgent),
 }
 
@@ -165,13 +277,25 @@ impl Serialize for Payload {
             Payload::DatadogLog(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::Fluent(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::Json(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::JsonSchema(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::SplunkHec(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::Static(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::Syslog(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Syslog3164(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::OtelTraces(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::OtelLogs(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::OtelMetrics(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::DogStatsdD(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Prometheus(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::PrometheusRemoteWrite(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::InfluxLineProtocol(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Graphite(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Gelf(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Cef(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Leef(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Cri(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::DockerJsonFile(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Template(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::TraceAgent(ser) => ser.to_bytes(rng, max_bytes, writer),
         }
     }
@@ -0,0 +1,447 @@
+//! Prometheus exposition format payload.
+//!
+//! [Specification](https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format)
+//!
+//! A fixed set of metric families is generated up-front, each with a type,
+//! a set of label names and a set of series. Every block is a valid
+//! exposition: families in random order, each introduced by its `# HELP` and
+//! `# TYPE` lines and appearing at most once, with freshly generated values.
+
+use std::{fmt::Write as _, io::Write};
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use rustc_hash::FxHashSet;
+use serde::Deserialize;
+
+use crate::{common::strings, Error, Serialize};
+
+/// Characters used for metric and label names. Names may not begin with a
+/// digit and those beginning with `__` are reserved, so we stick to letters.
+pub(crate) const NAME_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+/// The quantiles reported by every summary.
+const QUANTILES: [&str; 3] = ["0.5", "0.9", "0.99"];
+
+fn families_minimum() -> u32 {
+    50
+}
+
+fn families_maximum() -> u32 {
+    500
+}
+
+fn series_per_family_minimum() -> u16 {
+    1
+}
+
+fn series_per_family_maximum() -> u16 {
+    20
+}
+
+fn labels_per_family_minimum() -> u16 {
+    0
+}
+
+fn labels_per_family_maximum() -> u16 {
+    8
+}
+
+fn name_length_minimum() -> u16 {
+    4
+}
+
+fn name_length_maximum() -> u16 {
+    40
+}
+
+fn label_name_length_minimum() -> u16 {
+    2
+}
+
+fn label_name_length_maximum() -> u16 {
+    20
+}
+
+fn label_value_length_minimum() -> u16 {
+    1
+}
+
+fn label_value_length_maximum() -> u16 {
+    32
+}
+
+fn buckets_minimum() -> u16 {
+    4
+}
+
+fn buckets_maximum() -> u16 {
+    12
+}
+
+/// Weights for Prometheus metric types: counters, gauges, etc
+///
+/// Defines the relative probability of each type of metric family.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct KindWeights {
+    counter: u8,
+    gauge: u8,
+    histogram: u8,
+    summary: u8,
+}
+
+impl Default for KindWeights {
+    fn default() -> Self {
+        KindWeights {
+            counter: 40,   // 40%
+            gauge: 40,     // 40%
+            histogram: 10, // 10%
+            summary: 10,   // 10%
+        }
+    }
+}
+
+/// Configure the Prometheus payload.
+///
+/// All ranges are inclusive. A minimum must not exceed its maximum.
+#[derive(Debug, Deserialize, Clone, PartialEq, Copy)]
+pub struct Config {
+    /// Minimum number of metric families to generate
+    #[serde(default = "families_minimum")]
+    pub families_minimum: u32,
+
+    /// Maximum number of metric families to generate
+    #[serde(default = "families_maximum")]
+    pub families_maximum: u32,
+
+    /// Minimum number of series, distinct label sets, per metric family
+    #[serde(default = "series_per_family_minimum")]
+    pub series_per_family_minimum: u16,
+
+    /// Maximum number of series, distinct label sets, per metric family
+    #[serde(default = "series_per_family_maximum")]
+    pub series_per_family_maximum: u16,
+
+    /// Minimum number of label names per metric family
+    #[serde(default = "labels_per_family_minimum")]
+    pub labels_per_family_minimum: u16,
+
+    /// Maximum number of label names per metric family
+    #[serde(default = "labels_per_family_maximum")]
+    pub labels_per_family_maximum: u16,
+
+    /// Minimum length of a metric family name
+    #[serde(default = "name_length_minimum")]
+    pub name_length_minimum: u16,
+
+    /// Maximum length of a metric family name
+    #[serde(default = "name_length_maximum")]
+    pub name_length_maximum: u16,
+
+    /// Minimum length of a label name
+    #[serde(default = "label_name_length_minimum")]
+    pub label_name_length_minimum: u16,
+
+    /// Maximum length of a label name
+    #[serde(default = "label_name_length_maximum")]
+    pub label_name_length_maximum: u16,
+
+    /// Minimum length of a label value
+    #[serde(default = "label_value_length_minimum")]
+    pub label_value_length_minimum: u16,
+
+    /// Maximum length of a label value
+    #[serde(default = "label_value_length_maximum")]
+    pub label_value_length_maximum: u16,
+
+    /// Minimum number of buckets, besides `+Inf`, per histogram
+    #[serde(default = "buckets_minimum")]
+    pub buckets_minimum: u16,
+
+    /// Maximum number of buckets, besides `+Inf`, per histogram
+    #[serde(default = "buckets_maximum")]
+    pub buckets_maximum: u16,
+
+    /// Defines the relative probability of each type of metric family.
+    #[serde(default)]
+    pub kind_weights: KindWeights,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            families_minimum: families_minimum(),
+            families_maximum: families_maximum(),
+            series_per_family_minimum: series_per_family_minimum(),
+            series_per_family_maximum: series_per_family_maximum(),
+            labels_per_family_minimum: labels_per_family_minimum(),
+            labels_per_family_maximum: labels_per_family_maximum(),
+            name_length_minimum: name_length_minimum(),
+            name_length_maximum: name_length_maximum(),
+            label_name_length_minimum: label_name_length_minimum(),
+            label_name_length_maximum: label_name_length_maximum(),
+            label_value_length_minimum: label_value_length_minimum(),
+            label_value_length_maximum: label_value_length_maximum(),
+            buckets_minimum: buckets_minimum(),
+            buckets_maximum: buckets_maximum(),
+            kind_weights: KindWeights::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+            Kind::Summary => "summary",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Family {
+    name: String,
+    kind: Kind,
+    help: String,
+    /// The label set of each series, rendered without braces.
+    series: Vec<String>,
+    /// Upper bounds of the finite histogram buckets, ascending.
+    bounds: Vec<f64>,
+}
+
+/// Render a label set of `series` and `extra`, braces included.
+fn labels(series: &str, extra: Option<(&str, &str)>) -> String {
+    match (series.is_empty(), extra) {
+        (true, None) => String::new(),
+        (true, Some((key, value))) => format!("{{{key}=\"{value}\"}}"),
+        (false, None) => format!("{{{series}}}"),
+        (false, Some((key, value))) => format!("{{{series},{key}=\"{value}\"}}"),
+    }
+}
+
+impl Family {
+    /// Render this family with fresh values into `buf`.
+    fn render<R>(&self, rng: &mut R, buf: &mut String)
+    where
+        R: Rng + ?Sized,
+    {
+        let name = &self.name;
+        // Writing to a String cannot fail.
+        let _ = writeln!(buf, "# HELP {name} {help}", help = self.help);
+        let _ = writeln!(buf, "# TYPE {name} {kind}", kind = self.kind.as_str());
+        for series in &self.series {
+            match self.kind {
+                Kind::Counter => {
+                    let value: u32 = rng.gen();
+                    let _ = writeln!(buf, "{name}{} {value}", labels(series, None));
+                }
+                Kind::Gauge => {
+                    let value: f64 = rng.gen_range(-1_000_000.0..1_000_000.0);
+                    let _ = writeln!(buf, "{name}{} {value}", labels(series, None));
+                }
+                Kind::Histogram => {
+                    let mut count: u64 = 0;
+                    for bound in &self.bounds {
+                        count += rng.gen_range(0..1_000);
+                        let le = bound.to_string();
+                        let labels = labels(series, Some(("le", &le)));
+                        let _ = writeln!(buf, "{name}_bucket{labels} {count}");
+                    }
+                    count += rng.gen_range(0..1_000);
+                    let inf = labels(series, Some(("le", "+Inf")));
+                    let sum = rng.gen_range(0.0..1_000.0) * count as f64;
+                    let _ = writeln!(buf, "{name}_bucket{inf} {count}");
+                    let _ = writeln!(buf, "{name}_sum{} {sum}", labels(series, None));
+                    let _ = writeln!(buf, "{name}_count{} {count}", labels(series, None));
+                }
+                Kind::Summary => {
+                    let mut values: [f64; QUANTILES.len()] = rng.gen();
+                    values.sort_by(f64::total_cmp);
+                    for (quantile, value) in QUANTILES.iter().zip(values) {
+                        let labels = labels(series, Some(("quantile", quantile)));
+                        let _ = writeln!(buf, "{name}{labels} {}", value * 1_000.0);
+                    }
+                    let count: u32 = rng.gen();
+                    let sum = rng.gen_range(0.0..1_000.0) * f64::from(count);
+                    let _ = writeln!(buf, "{name}_sum{} {sum}", labels(series, None));
+                    let _ = writeln!(buf, "{name}_count{} {count}", labels(series, None));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A generator for Prometheus exposition payloads
+pub struct Prometheus {
+    families: Vec<Family>,
+}
+
+impl Prometheus {
+    /// Create a new instance of `Prometheus`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if any minimum in `config` exceeds its maximum or if
+    /// all kind weights are zero.
+    pub fn new<R>(config: &Config, mut rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let names = strings::Pool::with_size_and_alphabet(&mut rng, 1_000_000, NAME_ALPHABET);
+        let values = strings::Pool::with_size(&mut rng, 1_000_000);
+        let kinds = [Kind::Counter, Kind::Gauge, Kind::Histogram, Kind::Summary];
+        let kind_weights = WeightedIndex::new([
+            config.kind_weights.counter,
+            config.kind_weights.gauge,
+            config.kind_weights.histogram,
+            config.kind_weights.summary,
+        ])
+        .expect("kind weights must not all be zero");
+
+        let name_length = config.name_length_minimum..=config.name_length_maximum;
+        let label_name_length = config.label_name_length_minimum..=config.label_name_length_maximum;
+        let label_value_length =
+            config.label_value_length_minimum..=config.label_value_length_maximum;
+        let total = rng.gen_range(config.families_minimum..=config.families_maximum);
+
+        let mut seen = FxHashSet::default();
+        let mut families = Vec::with_capacity(total as usize);
+        for _ in 0..total {
+            let kind = kinds[kind_weights.sample(&mut rng)];
+            let mut name = names.random_str(&mut rng, name_length.clone());
+            if kind == Kind::Counter {
+                name.push_str("_total");
+            }
+            // A name may only be used by one family, including the series a
+            // histogram or summary adds with suffixes.
+            let suffixed = [
+                format!("{name}_bucket"),
+                format!("{name}_sum"),
+                format!("{name}_count"),
+            ];
+            if seen.contains(&name) || suffixed.iter().any(|s| seen.contains(s)) {
+                continue;
+            }
+            seen.insert(name.clone());
+            if matches!(kind, Kind::Histogram | Kind::Summary) {
+                seen.extend(suffixed);
+            }
+
+            let mut keys: Vec<String> = (0..rng
+                .gen_range(config.labels_per_family_minimum..=config.labels_per_family_maximum))
+                .map(|_| names.random_str(&mut rng, label_name_length.clone()))
+                .filter(|key| key != "le" && key != "quantile")
+                .collect();
+            keys.sort();
+            keys.dedup();
+
+            // Without labels a family has a single series.
+            let total_series = if keys.is_empty() {
+                1
+            } else {
+                rng.gen_range(config.series_per_family_minimum..=config.series_per_family_maximum)
+            };
+            let mut series: Vec<String> = (0..total_series)
+                .map(|_| {
+                    keys.iter()
+                        .map(|key| {
+                            let value = values.random_str(&mut rng, label_value_length.clone());
+                            format!("{key}=\"{value}\"")
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .collect();
+            series.sort();
+            series.dedup();
+
+            let bounds = if kind == Kind::Histogram {
+                let start: f64 = rng.gen_range(0.001..1.0);
+                let factor: f64 = rng.gen_range(1.5..4.0);
+                let total = rng.gen_range(config.buckets_minimum..=config.buckets_maximum);
+                (0..total)
+                    .map(|i| start * factor.powi(i32::from(i)))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            families.push(Family {
+                help: format!("Generated {kind} {name}.", kind = kind.as_str()),
+                name,
+                kind,
+                series,
+                bounds,
+            });
+        }
+
+        Self { families }
+    }
+}
+
+impl Serialize for Prometheus {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut order: Vec<&Family> = self.families.iter().collect();
+        order.shuffle(&mut rng);
+
+        let mut bytes_remaining = max_bytes;
+        let mut buf = String::new();
+        for family in order {
+            buf.clear();
+            family.render(&mut rng, &mut buf);
+            match bytes_remaining.checked_sub(buf.len()) {
+                Some(remainder) => {
+                    writer.write_all(buf.as_bytes())?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use rustc_hash::FxHashSet;
+
+    use crate::{prometheus::Config, Prometheus, Serialize};
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes` and that no metric family appears twice.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let prometheus = Prometheus::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            prometheus.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes);
+
+            let text = std::str::from_utf8(&bytes).unwrap();
+            let mut families = FxHashSet::default();
+            for line in text.lines().filter(|l| l.starts_with("# TYPE ")) {
+                assert!(families.insert(line.split(' ').nth(2).unwrap()), "{line}");
+            }
+        }
+    }
+}
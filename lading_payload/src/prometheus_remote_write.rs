@@ -0,0 +1,362 @@
+//! Prometheus remote write payload.
+//!
+//! [Specification](https://prometheus.io/docs/concepts/remote_write_spec/)
+//!
+//! Each block is a single snappy-compressed, protobuf encoded `WriteRequest`,
+//! exactly the body a remote write sender POSTs. A fixed set of series is
+//! generated up-front and every `WriteRequest` carries a random selection of
+//! them with freshly generated samples.
+
+use std::{io::Write, ops::RangeInclusive};
+
+use prost::Message;
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::{
+    common::{strings, TIMESTAMP_BASE_SECONDS},
+    prometheus::NAME_ALPHABET,
+    Error, Serialize,
+};
+
+/// The label holding the metric name of a series.
+const METRIC_NAME_LABEL: &str = "__name__";
+
+/// A remote write `WriteRequest`, without metadata.
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    /// The series written
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+/// A remote write `TimeSeries`, a label set and its samples.
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    /// The labels of this series, `__name__` included, sorted by name
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    /// The samples of this series, ascending by timestamp
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+/// A remote write `Label`.
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    /// The label name
+    #[prost(string, tag = "1")]
+    pub name: String,
+    /// The label value
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+/// A remote write `Sample`.
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct Sample {
+    /// The sample value
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    /// The sample timestamp, milliseconds since the epoch
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+fn series_minimum() -> u32 {
+    100
+}
+
+fn series_maximum() -> u32 {
+    10_000
+}
+
+fn metric_names_minimum() -> u16 {
+    10
+}
+
+fn metric_names_maximum() -> u16 {
+    200
+}
+
+fn labels_per_series_minimum() -> u16 {
+    1
+}
+
+fn labels_per_series_maximum() -> u16 {
+    10
+}
+
+fn samples_per_series_minimum() -> u16 {
+    1
+}
+
+fn samples_per_series_maximum() -> u16 {
+    5
+}
+
+fn name_length_minimum() -> u16 {
+    4
+}
+
+fn name_length_maximum() -> u16 {
+    40
+}
+
+fn label_name_length_minimum() -> u16 {
+    2
+}
+
+fn label_name_length_maximum() -> u16 {
+    20
+}
+
+fn label_value_length_minimum() -> u16 {
+    1
+}
+
+fn label_value_length_maximum() -> u16 {
+    32
+}
+
+/// Configure the Prometheus remote write payload.
+///
+/// All ranges are inclusive. A minimum must not exceed its maximum.
+#[derive(Debug, Deserialize, Clone, PartialEq, Copy)]
+pub struct Config {
+    /// Minimum number of distinct series to generate
+    #[serde(default = "series_minimum")]
+    pub series_minimum: u32,
+
+    /// Maximum number of distinct series to generate
+    #[serde(default = "series_maximum")]
+    pub series_maximum: u32,
+
+    /// Minimum number of metric names shared among the series
+    #[serde(default = "metric_names_minimum")]
+    pub metric_names_minimum: u16,
+
+    /// Maximum number of metric names shared among the series
+    #[serde(default = "metric_names_maximum")]
+    pub metric_names_maximum: u16,
+
+    /// Minimum number of labels, besides `__name__`, per series
+    #[serde(default = "labels_per_series_minimum")]
+    pub labels_per_series_minimum: u16,
+
+    /// Maximum number of labels, besides `__name__`, per series
+    #[serde(default = "labels_per_series_maximum")]
+    pub labels_per_series_maximum: u16,
+
+    /// Minimum number of samples per series in a `WriteRequest`
+    #[serde(default = "samples_per_series_minimum")]
+    pub samples_per_series_minimum: u16,
+
+    /// Maximum number of samples per series in a `WriteRequest`
+    #[serde(default = "samples_per_series_maximum")]
+    pub samples_per_series_maximum: u16,
+
+    /// Minimum length of a metric name
+    #[serde(default = "name_length_minimum")]
+    pub name_length_minimum: u16,
+
+    /// Maximum length of a metric name
+    #[serde(default = "name_length_maximum")]
+    pub name_length_maximum: u16,
+
+    /// Minimum length of a label name
+    #[serde(default = "label_name_length_minimum")]
+    pub label_name_length_minimum: u16,
+
+    /// Maximum length of a label name
+    #[serde(default = "label_name_length_maximum")]
+    pub label_name_length_maximum: u16,
+
+    /// Minimum length of a label value
+    #[serde(default = "label_value_length_minimum")]
+    pub label_value_length_minimum: u16,
+
+    /// Maximum length of a label value
+    #[serde(default = "label_value_length_maximum")]
+    pub label_value_length_maximum: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            series_minimum: series_minimum(),
+            series_maximum: series_maximum(),
+            metric_names_minimum: metric_names_minimum(),
+            metric_names_maximum: metric_names_maximum(),
+            labels_per_series_minimum: labels_per_series_minimum(),
+            labels_per_series_maximum: labels_per_series_maximum(),
+            samples_per_series_minimum: samples_per_series_minimum(),
+            samples_per_series_maximum: samples_per_series_maximum(),
+            name_length_minimum: name_length_minimum(),
+            name_length_maximum: name_length_maximum(),
+            label_name_length_minimum: label_name_length_minimum(),
+            label_name_length_maximum: label_name_length_maximum(),
+            label_value_length_minimum: label_value_length_minimum(),
+            label_value_length_maximum: label_value_length_maximum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A generator for Prometheus remote write payloads
+pub struct PrometheusRemoteWrite {
+    /// The label set of each series, sorted by name.
+    series: Vec<Vec<Label>>,
+    samples_per_series: RangeInclusive<u16>,
+}
+
+impl PrometheusRemoteWrite {
+    /// Create a new instance of `PrometheusRemoteWrite`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if any minimum in `config` exceeds its maximum or if
+    /// there are no metric names.
+    pub fn new<R>(config: &Config, mut rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let names = strings::Pool::with_size_and_alphabet(&mut rng, 1_000_000, NAME_ALPHABET);
+        let values = strings::Pool::with_size(&mut rng, 1_000_000);
+
+        let name_length = config.name_length_minimum..=config.name_length_maximum;
+        let label_name_length = config.label_name_length_minimum..=config.label_name_length_maximum;
+        let label_value_length =
+            config.label_value_length_minimum..=config.label_value_length_maximum;
+
+        let total_names = rng.gen_range(config.metric_names_minimum..=config.metric_names_maximum);
+        let metric_names: Vec<String> = (0..total_names)
+            .map(|_| names.random_str(&mut rng, name_length.clone()))
+            .collect();
+        assert!(!metric_names.is_empty(), "there must be metric names");
+
+        let total_series = rng.gen_range(config.series_minimum..=config.series_maximum);
+        let mut series: Vec<Vec<Label>> = (0..total_series)
+            .map(|_| {
+                let mut labels: Vec<Label> = (0..rng.gen_range(
+                    config.labels_per_series_minimum..=config.labels_per_series_maximum,
+                ))
+                    .map(|_| Label {
+                        name: names.random_str(&mut rng, label_name_length.clone()),
+                        value: values.random_str(&mut rng, label_value_length.clone()),
+                    })
+                    .collect();
+                // Label names must be unique within a series and sorted, with
+                // `__name__` sorting ahead of any lowercase name.
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+                labels.dedup_by(|a, b| a.name == b.name);
+                labels.insert(
+                    0,
+                    Label {
+                        name: String::from(METRIC_NAME_LABEL),
+                        value: metric_names.choose(&mut rng).unwrap().clone(),
+                    },
+                );
+                labels
+            })
+            .collect();
+        series.sort_by(|a, b| {
+            a.iter()
+                .map(|l| (&l.name, &l.value))
+                .cmp(b.iter().map(|l| (&l.name, &l.value)))
+        });
+        series.dedup();
+
+        Self {
+            series,
+            samples_per_series: config.samples_per_series_minimum
+                ..=config.samples_per_series_maximum,
+        }
+    }
+}
+
+impl Serialize for PrometheusRemoteWrite {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut order: Vec<&Vec<Label>> = self.series.iter().collect();
+        order.shuffle(&mut rng);
+
+        // The compressed request must fit in `max_bytes`. We cannot know the
+        // compressed size ahead of time so we bound by the worst case snappy
+        // gives for the encoded size.
+        let mut request = WriteRequest {
+            timeseries: Vec::new(),
+        };
+        let mut encoded_len = 0;
+        for labels in order {
+            // Timestamps are in milliseconds.
+            let start = i64::from(TIMESTAMP_BASE_SECONDS) * 1_000 + rng.gen_range(0..3_600_000);
+            let total_samples = rng.gen_range(self.samples_per_series.clone());
+            let samples = (0..total_samples)
+                .map(|i| Sample {
+                    value: rng.gen_range(-1_000_000.0..1_000_000.0),
+                    timestamp: start + i64::from(i) * 15_000,
+                })
+                .collect();
+            let series = TimeSeries {
+                labels: labels.clone(),
+                samples,
+            };
+
+            // One byte of tag, the length delimiter and the series itself.
+            let len = series.encoded_len();
+            let len = 1 + prost::length_delimiter_len(len) + len;
+            if snap::raw::max_compress_len(encoded_len + len) > max_bytes {
+                break;
+            }
+            encoded_len += len;
+            request.timeseries.push(series);
+        }
+        if request.timeseries.is_empty() {
+            return Ok(());
+        }
+
+        let buf = snap::raw::Encoder::new().compress_vec(&request.encode_to_vec())?;
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use prost::Message;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::{Config, PrometheusRemoteWrite, WriteRequest};
+    use crate::Serialize;
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes` and that what we produce decodes.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                series_minimum: 10,
+                series_maximum: 100,
+                ..Config::default()
+            };
+            let remote_write = PrometheusRemoteWrite::new(&config, &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            remote_write.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes, "max len: {max_bytes}, actual: {}", bytes.len());
+
+            if !bytes.is_empty() {
+                let decoded = snap::raw::Decoder::new().decompress_vec(&bytes).unwrap();
+                let request = WriteRequest::decode(decoded.as_slice()).unwrap();
+                assert!(!request.timeseries.is_empty());
+            }
+        }
+    }
+}
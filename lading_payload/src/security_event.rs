@@ -0,0 +1,485 @@
+//! Security event payloads, ArcSight CEF and IBM QRadar LEEF.
+//!
+//! [CEF specification](https://www.microfocus.com/documentation/arcsight/arcsight-smartconnectors-8.4/pdfdoc/cef-implementation-standard/cef-implementation-standard.pdf),
+//! [LEEF specification](https://www.ibm.com/docs/en/dsm?topic=leef-overview)
+//!
+//! Vendors, products, signatures and their names are drawn from pools
+//! generated up-front. Header fields and CEF extension values deliberately
+//! contain characters the formats require escaping.
+
+use std::{io::Write, ops::RangeInclusive};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::{common::strings, Error, Serialize};
+
+/// Characters of header fields, among them the `|` and `\` that must be
+/// escaped.
+const HEADER_ALPHABET: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 -_.|\\";
+
+/// Characters of extension values, among them the `=` and `\` that CEF
+/// requires escaping. There are no tabs, the LEEF attribute delimiter.
+const VALUE_ALPHABET: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 -_.:/=\\";
+
+/// CEF extension keys from the CEF key dictionary.
+const CEF_KEYS: [&str; 16] = [
+    "act", "app", "cat", "cs1", "cs2", "dhost", "dpt", "dst", "duser", "fname", "msg", "outcome",
+    "proto", "request", "shost", "suser",
+];
+
+/// LEEF attribute keys from the LEEF predefined attributes.
+const LEEF_KEYS: [&str; 12] = [
+    "cat",
+    "devTimeFormat",
+    "dstMAC",
+    "dstPort",
+    "identHostName",
+    "policy",
+    "proto",
+    "resource",
+    "src",
+    "srcPort",
+    "url",
+    "usrName",
+];
+
+fn vendors() -> u16 {
+    8
+}
+
+fn products() -> u16 {
+    32
+}
+
+fn signatures() -> u16 {
+    512
+}
+
+fn severity_minimum() -> u8 {
+    0
+}
+
+fn severity_maximum() -> u8 {
+    10
+}
+
+fn extensions_minimum() -> u8 {
+    2
+}
+
+fn extensions_maximum() -> u8 {
+    10
+}
+
+fn value_length_minimum() -> u16 {
+    1
+}
+
+fn value_length_maximum() -> u16 {
+    64
+}
+
+/// Configure the CEF and LEEF payloads.
+///
+/// All ranges are inclusive. A minimum must not exceed its maximum.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// Number of distinct vendors in the pool
+    #[serde(default = "vendors")]
+    pub vendors: u16,
+
+    /// Number of distinct products in the pool, each belonging to a vendor
+    #[serde(default = "products")]
+    pub products: u16,
+
+    /// Number of distinct signature IDs in the pool, each with a name
+    #[serde(default = "signatures")]
+    pub signatures: u16,
+
+    /// Minimum event severity, CEF severities run from 0 to 10
+    #[serde(default = "severity_minimum")]
+    pub severity_minimum: u8,
+
+    /// Maximum event severity, CEF severities run from 0 to 10
+    #[serde(default = "severity_maximum")]
+    pub severity_maximum: u8,
+
+    /// Minimum number of extension key-value pairs per event
+    #[serde(default = "extensions_minimum")]
+    pub extensions_minimum: u8,
+
+    /// Maximum number of extension key-value pairs per event
+    #[serde(default = "extensions_maximum")]
+    pub extensions_maximum: u8,
+
+    /// Minimum length of an extension value, before escaping
+    #[serde(default = "value_length_minimum")]
+    pub value_length_minimum: u16,
+
+    /// Maximum length of an extension value, before escaping
+    #[serde(default = "value_length_maximum")]
+    pub value_length_maximum: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vendors: vendors(),
+            products: products(),
+            signatures: signatures(),
+            severity_minimum: severity_minimum(),
+            severity_maximum: severity_maximum(),
+            extensions_minimum: extensions_minimum(),
+            extensions_maximum: extensions_maximum(),
+            value_length_minimum: value_length_minimum(),
+            value_length_maximum: value_length_maximum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Product {
+    vendor: String,
+    name: String,
+    version: String,
+}
+
+#[derive(Debug, Clone)]
+struct Signature {
+    id: String,
+    name: String,
+}
+
+/// The pools events are drawn from, shared by CEF and LEEF.
+#[derive(Debug, Clone)]
+struct Events {
+    products: Vec<Product>,
+    signatures: Vec<Signature>,
+    severity: RangeInclusive<u8>,
+    extensions: RangeInclusive<u8>,
+    value_length: RangeInclusive<u16>,
+    values: strings::Pool,
+}
+
+/// A single event, its fields not yet escaped.
+struct Event<'a> {
+    product: &'a Product,
+    signature: &'a Signature,
+    severity: u8,
+    extensions: Vec<(&'static str, &'a str)>,
+}
+
+impl Events {
+    fn new<R>(config: &Config, mut rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let headers = strings::Pool::with_size_and_alphabet(&mut rng, 1_000_000, HEADER_ALPHABET);
+        let values = strings::Pool::with_size_and_alphabet(&mut rng, 1_000_000, VALUE_ALPHABET);
+
+        let vendors: Vec<String> = (0..config.vendors)
+            .map(|_| headers.random_str(&mut rng, 4_usize..=24))
+            .collect();
+        assert!(!vendors.is_empty(), "there must be vendors");
+        let products: Vec<Product> = (0..config.products)
+            .map(|_| Product {
+                vendor: vendors.choose(&mut rng).unwrap().clone(),
+                name: headers.random_str(&mut rng, 4_usize..=24),
+                version: format!("{}.{}", rng.gen_range(1..10), rng.gen_range(0..20)),
+            })
+            .collect();
+        assert!(!products.is_empty(), "there must be products");
+        let signatures: Vec<Signature> = (0..config.signatures)
+            .map(|_| Signature {
+                id: rng.gen_range(100..1_000_000).to_string(),
+                name: headers.random_str(&mut rng, 8_usize..=64),
+            })
+            .collect();
+        assert!(!signatures.is_empty(), "there must be signatures");
+
+        Self {
+            products,
+            signatures,
+            severity: config.severity_minimum..=config.severity_maximum,
+            extensions: config.extensions_minimum..=config.extensions_maximum,
+            value_length: config.value_length_minimum..=config.value_length_maximum,
+            values,
+        }
+    }
+
+    fn event<R>(&self, keys: &'static [&'static str], rng: &mut R) -> Event<'_>
+    where
+        R: Rng + ?Sized,
+    {
+        let total = rng.gen_range(self.extensions.clone());
+        let keys: Vec<&'static str> = keys
+            .choose_multiple(rng, usize::from(total))
+            .copied()
+            .collect();
+        let extensions = keys
+            .into_iter()
+            .map(|key| {
+                let length = rng.gen_range(self.value_length.clone());
+                let value = self
+                    .values
+                    .of_size(rng, usize::from(length))
+                    .expect("pool is larger than any value");
+                (key, value)
+            })
+            .collect();
+
+        Event {
+            product: self.products.choose(rng).unwrap(),
+            signature: self.signatures.choose(rng).unwrap(),
+            severity: rng.gen_range(self.severity.clone()),
+            extensions,
+        }
+    }
+}
+
+/// Escape a header field, where `|` separates fields.
+fn escape_header(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// Escape a CEF extension value, where `=` separates keys from values.
+fn escape_cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Write newline delimited lines from `line` into `writer` until `max_bytes`
+/// would be exceeded.
+fn write_lines<W, F>(max_bytes: usize, writer: &mut W, mut line: F) -> Result<(), Error>
+where
+    W: Write,
+    F: FnMut() -> String,
+{
+    let mut bytes_remaining = max_bytes;
+    loop {
+        let encoding = line();
+        let line_length = encoding.len() + 1; // add one for the newline
+        match bytes_remaining.checked_sub(line_length) {
+            Some(remainder) => {
+                writeln!(writer, "{encoding}")?;
+                bytes_remaining = remainder;
+            }
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+/// A generator for ArcSight Common Event Format payloads
+pub struct Cef {
+    events: Events,
+}
+
+impl Cef {
+    /// Create a new instance of `Cef`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if any minimum in `config` exceeds its maximum or if
+    /// any pool is empty.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self {
+            events: Events::new(config, rng),
+        }
+    }
+
+    fn line<R>(&self, rng: &mut R) -> String
+    where
+        R: Rng + ?Sized,
+    {
+        let event = self.events.event(&CEF_KEYS, rng);
+        let extension: Vec<String> = event
+            .extensions
+            .iter()
+            .map(|(key, value)| format!("{key}={}", escape_cef_value(value)))
+            .collect();
+        format!(
+            "CEF:0|{vendor}|{product}|{version}|{id}|{name}|{severity}|{extension}",
+            vendor = escape_header(&event.product.vendor),
+            product = escape_header(&event.product.name),
+            version = escape_header(&event.product.version),
+            id = escape_header(&event.signature.id),
+            name = escape_header(&event.signature.name),
+            severity = event.severity,
+            extension = extension.join(" "),
+        )
+    }
+}
+
+impl Serialize for Cef {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        write_lines(max_bytes, writer, || self.line(&mut rng))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A generator for IBM Log Event Extended Format payloads
+pub struct Leef {
+    events: Events,
+}
+
+impl Leef {
+    /// Create a new instance of `Leef`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if any minimum in `config` exceeds its maximum or if
+    /// any pool is empty.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self {
+            events: Events::new(config, rng),
+        }
+    }
+
+    fn line<R>(&self, rng: &mut R) -> String
+    where
+        R: Rng + ?Sized,
+    {
+        let event = self.events.event(&LEEF_KEYS, rng);
+        // LEEF 1.0 attributes are tab delimited and values need no escaping
+        // as long as they hold no tabs. LEEF severities run from 1 to 10.
+        let mut attributes = vec![format!("sev={}", event.severity.clamp(1, 10))];
+        attributes.extend(
+            event
+                .extensions
+                .iter()
+                .map(|(key, value)| format!("{key}={value}")),
+        );
+        format!(
+            "LEEF:1.0|{vendor}|{product}|{version}|{id}|{attributes}",
+            vendor = escape_header(&event.product.vendor),
+            product = escape_header(&event.product.name),
+            version = escape_header(&event.product.version),
+            id = escape_header(&event.signature.id),
+            attributes = attributes.join("\t"),
+        )
+    }
+}
+
+impl Serialize for Leef {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        write_lines(max_bytes, writer, || self.line(&mut rng))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::{escape_cef_value, escape_header};
+    use crate::{security_event::Config, Cef, Leef, Serialize};
+
+    /// Split `input` on unescaped occurrences of `separator`.
+    fn split_unescaped(input: &str, separator: char) -> Vec<String> {
+        let mut parts = vec![String::new()];
+        let mut chars = input.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                parts.last_mut().unwrap().push(c);
+                if let Some(next) = chars.next() {
+                    parts.last_mut().unwrap().push(next);
+                }
+            } else if c == separator {
+                parts.push(String::new());
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+        parts
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape_header(r"a|b\c"), r"a\|b\\c");
+        assert_eq!(escape_cef_value(r"a=b\c"), r"a\=b\\c");
+    }
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let cef = Cef::new(&Config::default(), &mut rng);
+            let leef = Leef::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            cef.to_bytes(&mut rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            leef.to_bytes(&mut rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes);
+        }
+    }
+
+    // We want to be sure that escaping leaves every CEF line with exactly
+    // seven header fields and an extension of well-formed pairs.
+    proptest! {
+        #[test]
+        fn cef_lines_are_well_formed(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let cef = Cef::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::new();
+            cef.to_bytes(rng, 8192, &mut bytes).unwrap();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let fields = split_unescaped(line, '|');
+                assert_eq!(fields.len(), 8, "{line}");
+                assert_eq!(fields[0], "CEF:0");
+                assert!(fields[6].parse::<u8>().unwrap() <= 10);
+                // Values may hold spaces but never an unescaped `=`.
+                for token in fields[7].split(' ') {
+                    assert!(split_unescaped(token, '=').len() <= 2, "{token}");
+                }
+            }
+        }
+    }
+
+    // We want to be sure that every LEEF line has five header fields and tab
+    // delimited attributes.
+    proptest! {
+        #[test]
+        fn leef_lines_are_well_formed(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let leef = Leef::new(&Config::default(), &mut rng);
+
+            let mut bytes = Vec::new();
+            leef.to_bytes(rng, 8192, &mut bytes).unwrap();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let fields = split_unescaped(line, '|');
+                assert_eq!(fields.len(), 6, "{line}");
+                assert_eq!(fields[0], "LEEF:1.0");
+                assert!(fields[5].split('\t').all(|attr| attr.contains('=')), "{line}");
+            }
+        }
+    }
+}
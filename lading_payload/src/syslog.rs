@@ -1,17 +1,89 @@
 //! Syslog payload.
+//!
+//! Messages are either [RFC 5424](https://datatracker.ietf.org/doc/html/rfc5424)
+//! or BSD [RFC 3164](https://datatracker.ietf.org/doc/html/rfc3164) syslog,
+//! framed per [RFC 6587](https://datatracker.ietf.org/doc/html/rfc6587) for
+//! stream transports.
 
 use std::{io::Write, time::SystemTime};
 
 use rand::{distributions::Standard, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::Deserialize;
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 use crate::Error;
 
+/// How messages are separated from one another in a stream
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+pub enum Framing {
+    /// Every message is followed by a newline, RFC 6587 non-transparent framing
+    NonTransparent,
+    /// Every message is preceded by its length in bytes and a space, RFC 6587
+    /// octet counting
+    OctetCounting,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self::NonTransparent
+    }
+}
+
+impl Framing {
+    /// Frame `message` for writing into a stream.
+    fn frame(self, message: &str) -> String {
+        match self {
+            Framing::NonTransparent => format!("{message}\n"),
+            Framing::OctetCounting => format!("{} {message}", message.len()),
+        }
+    }
+}
+
+/// Configure the syslog payloads.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// How messages are separated from one another in a stream
+    #[serde(default)]
+    pub framing: Framing,
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 #[allow(clippy::module_name_repetitions)]
 #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
 /// Syslog 5424 payload
-pub struct Syslog5424 {}
+pub struct Syslog5424 {
+    framing: Framing,
+}
+
+impl Syslog5424 {
+    /// Create a new instance of `Syslog5424` with the given framing.
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self {
+            framing: config.framing,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(clippy::module_name_repetitions)]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+/// Syslog 3164 payload
+pub struct Syslog3164 {
+    framing: Framing,
+}
+
+impl Syslog3164 {
+    /// Create a new instance of `Syslog3164` with the given framing.
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self {
+            framing: config.framing,
+        }
+    }
+}
 
 const HOSTNAMES: [&str; 4] = [
     "troutwine.us",
@@ -20,6 +92,9 @@ const HOSTNAMES: [&str; 4] = [
     "zombo.com",
 ];
 const APP_NAMES: [&str; 4] = ["vector", "cernan", "lading", "execsnoop"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
 
 #[derive(serde::Serialize)]
 struct Message {
@@ -99,6 +174,84 @@ impl Member {
     }
 }
 
+struct Member3164 {
+    priority: u8,      // 0 - 191
+    timestamp: String, // Mmm dd hh:mm:ss
+    hostname: String,  // name.tld
+    tag: String,       // shortish string
+    procid: u16,       // 100 - 9999
+    message: String,   // shortish structured string
+}
+
+impl Distribution<Member3164> for Standard {
+    fn sample<R>(&self, rng: &mut R) -> Member3164
+    where
+        R: Rng + ?Sized,
+    {
+        Member3164 {
+            priority: rng.gen_range(0..=191),
+            timestamp: to_rfc3164(SystemTime::now()),
+            hostname: (*HOSTNAMES.choose(rng).unwrap()).to_string(),
+            tag: (*APP_NAMES.choose(rng).unwrap()).to_string(),
+            procid: rng.gen_range(100..=9999),
+            message: serde_json::to_string(&rng.gen::<Message>()).unwrap(),
+        }
+    }
+}
+
+/// Format as the RFC 3164 timestamp, `Mmm dd hh:mm:ss` with the day padded by
+/// a space.
+fn to_rfc3164<T>(dt: T) -> String
+where
+    T: Into<OffsetDateTime>,
+{
+    let dt = dt.into();
+    format!(
+        "{} {:>2} {:02}:{:02}:{:02}",
+        MONTHS[usize::from(u8::from(dt.month())) - 1],
+        dt.day(),
+        dt.hour(),
+        dt.minute(),
+        dt.second()
+    )
+}
+
+impl Member3164 {
+    fn into_string(self) -> String {
+        format!(
+            "<{}>{} {} {}[{}]: {}",
+            self.priority, self.timestamp, self.hostname, self.tag, self.procid, self.message
+        )
+    }
+}
+
+/// Write framed messages from `messages` into `writer` until `max_bytes` would
+/// be exceeded.
+fn write_framed<W, I>(
+    framing: Framing,
+    messages: I,
+    max_bytes: usize,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    W: Write,
+    I: Iterator<Item = String>,
+{
+    let mut written_bytes = 0;
+    for message in messages {
+        let encoded = framing.frame(&message);
+
+        if encoded.len() + written_bytes > max_bytes {
+            break;
+        }
+
+        writer.write_all(encoded.as_bytes())?;
+        written_bytes += encoded.len();
+    }
+
+    Ok(())
+}
+
 impl crate::Serialize for Syslog5424 {
     fn to_bytes<W, R>(&self, rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
     where
@@ -110,21 +263,29 @@ impl crate::Serialize for Syslog5424 {
             return Ok(());
         }
 
-        let mut written_bytes = 0;
-        for member in rng.sample_iter::<Member, Standard>(Standard) {
-            let encoded = member.into_string();
-
-            if encoded.len() + 1 + written_bytes > max_bytes {
-                break;
-            }
-
-            writeln!(writer, "{encoded}")?;
-
-            written_bytes += 1; // newline
-            written_bytes += encoded.len();
-        }
+        let members = rng.sample_iter::<Member, Standard>(Standard);
+        write_framed(
+            self.framing,
+            members.map(Member::into_string),
+            max_bytes,
+            writer,
+        )
+    }
+}
 
-        Ok(())
+impl crate::Serialize for Syslog3164 {
+    fn to_bytes<W, R>(&self, rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let members = rng.sample_iter::<Member3164, Standard>(Standard);
+        write_framed(
+            self.framing,
+            members.map(Member3164::into_string),
+            max_bytes,
+            writer,
+        )
     }
 }
 
@@ -133,7 +294,8 @@ mod test {
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
-    use crate::{Serialize, Syslog5424};
+    use super::{Config, Framing};
+    use crate::{Serialize, Syslog3164, Syslog5424};
 
     // We want to be sure that the serialized size of the payload does not
     // exceed `max_bytes`.
@@ -153,4 +315,80 @@ mod test {
             );
         }
     }
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_3164_not_exceed_max_bytes(seed: u64, max_bytes: u16, syslog: Syslog3164) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            syslog.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes);
+        }
+    }
+
+    // We want to be sure that octet counted frames are exactly as long as
+    // their counts claim.
+    proptest! {
+        #[test]
+        fn octet_counts_are_exact(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let syslog = Syslog5424 { framing: Framing::OctetCounting };
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            syslog.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            let mut rest = std::str::from_utf8(&bytes).unwrap();
+            while !rest.is_empty() {
+                let (count, tail) = rest.split_once(' ').unwrap();
+                let count: usize = count.parse().unwrap();
+                assert!(tail[..count].starts_with('<'));
+                rest = &tail[count..];
+            }
+        }
+    }
+
+    #[test]
+    fn syslog5424_config_deserializes() {
+        // lading reads payload configuration with enums as single entry maps.
+        let parse = |yaml: &str| -> Result<crate::Config, serde_yaml::Error> {
+            serde_yaml::with::singleton_map_recursive::deserialize(
+                serde_yaml::Deserializer::from_str(yaml),
+            )
+        };
+        let octet_counting = crate::Config::Syslog5424(Config {
+            framing: Framing::OctetCounting,
+        });
+        for (yaml, expected) in [
+            ("syslog5424", crate::Config::Syslog5424(Config::default())),
+            (
+                "syslog5424: {}",
+                crate::Config::Syslog5424(Config::default()),
+            ),
+            ("syslog5424: { framing: octet_counting }", octet_counting),
+            ("fluent", crate::Config::Fluent),
+        ] {
+            assert_eq!(parse(yaml).unwrap(), expected, "{yaml}");
+        }
+        assert!(parse("syslog5424_framed: {}").is_err());
+
+        // Enums nested in a payload's configuration are unaffected.
+        let config = parse(
+            "template: { format: '{n}', fields: { n: { int: { minimum: 0, maximum: 9 } } } }",
+        )
+        .unwrap();
+        let crate::Config::Template(config) = config else {
+            panic!("not a template: {config:?}");
+        };
+        assert_eq!(
+            config.fields["n"],
+            crate::template::Field::Int {
+                minimum: 0,
+                maximum: 9
+            }
+        );
+    }
 }
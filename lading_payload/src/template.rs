@@ -0,0 +1,453 @@
+//! User-defined template payload.
+//!
+//! A template is a format string with named placeholders, `{name}`, each typed
+//! by an entry in `fields`. Literal braces are written `{{` and `}}`. For
+//! example:
+//!
+//! ```yaml
+//! template:
+//!   format: "{ts} {client} {method} /api/{id} {status}"
+//!   fields:
+//!     ts:
+//!       timestamp:
+//!         format: "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+//!     client: ipv4
+//!     method:
+//!       choice: [GET, POST, PUT]
+//!     id: uuid
+//!     status:
+//!       int: { minimum: 200, maximum: 599 }
+//! ```
+//!
+//! The template is compiled once, when the payload is created, and every
+//! rendering is followed by a newline.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+use time::{
+    format_description::{self, well_known::Rfc3339, OwnedFormatItem},
+    OffsetDateTime,
+};
+
+use crate::{
+    common::{strings, TIMESTAMP_BASE_SECONDS},
+    Serialize,
+};
+
+fn word_length_minimum() -> u16 {
+    1
+}
+
+fn word_length_maximum() -> u16 {
+    16
+}
+
+/// Errors produced when compiling a [`Template`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// A placeholder names a field absent from `fields`
+    #[error("placeholder {{{0}}} has no field")]
+    UnknownField(String),
+    /// A `{` was not closed by a `}`
+    #[error("unclosed placeholder at byte {0}")]
+    UnclosedPlaceholder(usize),
+    /// A `}` appeared outside a placeholder and was not doubled
+    #[error("unmatched '}}' at byte {0}")]
+    UnmatchedBrace(usize),
+    /// A field's minimum exceeds its maximum
+    #[error("field {0} has a minimum greater than its maximum")]
+    EmptyRange(String),
+    /// A choice field has no choices
+    #[error("field {0} has no choices")]
+    EmptyChoice(String),
+    /// A timestamp format could not be parsed
+    #[error("field {0} has an invalid timestamp format: {1}")]
+    TimestampFormat(String, time::error::InvalidFormatDescription),
+    /// A timestamp format could not be applied
+    #[error("field {0} has an unusable timestamp format: {1}")]
+    TimestampFormatting(String, time::error::Format),
+}
+
+/// A typed placeholder
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    /// A random integer in an inclusive range
+    Int {
+        /// The smallest integer generated
+        minimum: i64,
+        /// The largest integer generated
+        maximum: i64,
+    },
+    /// A random member of a list
+    Choice(Vec<String>),
+    /// A timestamp, RFC 3339 unless `format` is given
+    Timestamp {
+        /// A [`time` format description](https://time-rs.github.io/book/api/format-description.html)
+        #[serde(default)]
+        format: Option<String>,
+    },
+    /// A random version 4 UUID
+    Uuid,
+    /// A random IPv4 address
+    Ipv4,
+    /// A random IPv6 address
+    Ipv6,
+    /// A random alphanumeric word from the string pool
+    Word {
+        /// Minimum length of the word
+        #[serde(default = "word_length_minimum")]
+        length_minimum: u16,
+        /// Maximum length of the word
+        #[serde(default = "word_length_maximum")]
+        length_maximum: u16,
+    },
+}
+
+/// Configure the template payload.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The format string, with `{name}` placeholders
+    pub format: String,
+    /// The type of each placeholder, by name
+    #[serde(default)]
+    pub fields: BTreeMap<String, Field>,
+}
+
+#[derive(Debug, Clone)]
+enum TimestampFormat {
+    Rfc3339,
+    Custom(OwnedFormatItem),
+}
+
+#[derive(Debug, Clone)]
+enum Compiled {
+    Int(RangeInclusive<i64>),
+    Choice(Vec<String>),
+    Timestamp(TimestampFormat),
+    Uuid,
+    Ipv4,
+    Ipv6,
+    Word(RangeInclusive<u16>),
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Field(usize),
+}
+
+#[derive(Debug, Clone)]
+/// A generator for user-defined templates
+pub struct Template {
+    segments: Vec<Segment>,
+    fields: Vec<Compiled>,
+    pool: strings::Pool,
+}
+
+impl Template {
+    /// Create a new instance of `Template`, compiling `config`.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the format string is malformed, names a field
+    /// that is not configured or if a field is itself invalid.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let mut names: Vec<&str> = Vec::new();
+        let mut fields = Vec::new();
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+
+        let format = config.format.as_str();
+        let mut chars = format.char_indices().peekable();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|&(_, c)| c) == Some('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek().map(|&(_, c)| c) == Some('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(Error::UnmatchedBrace(idx)),
+                '{' => {
+                    let start = idx + 1;
+                    let end = loop {
+                        match chars.next() {
+                            Some((end, '}')) => break end,
+                            Some(_) => {}
+                            None => return Err(Error::UnclosedPlaceholder(idx)),
+                        }
+                    };
+                    let name = &format[start..end];
+                    let field = if let Some(field) = names.iter().position(|n| *n == name) {
+                        field
+                    } else {
+                        let field = config
+                            .fields
+                            .get(name)
+                            .ok_or_else(|| Error::UnknownField(name.to_string()))?;
+                        fields.push(compile(name, field)?);
+                        names.push(name);
+                        fields.len() - 1
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(field));
+                }
+                c => literal.push(c),
+            }
+        }
+        literal.push('\n');
+        segments.push(Segment::Literal(literal));
+
+        Ok(Self {
+            segments,
+            fields,
+            pool: strings::Pool::with_size(rng, 1_000_000),
+        })
+    }
+
+    fn render<R>(&self, rng: &mut R, buf: &mut String)
+    where
+        R: Rng + ?Sized,
+    {
+        use std::fmt::Write;
+
+        for segment in &self.segments {
+            let field = match segment {
+                Segment::Literal(literal) => {
+                    buf.push_str(literal);
+                    continue;
+                }
+                Segment::Field(idx) => &self.fields[*idx],
+            };
+            match field {
+                Compiled::Int(range) => write!(buf, "{}", rng.gen_range(range.clone())).unwrap(),
+                Compiled::Choice(choices) => buf.push_str(choices.choose(rng).unwrap()),
+                Compiled::Timestamp(format) => {
+                    let timestamp = timestamp(rng);
+                    // Formats are checked at compile time, every timestamp has
+                    // every component.
+                    let formatted = match format {
+                        TimestampFormat::Rfc3339 => timestamp.format(&Rfc3339),
+                        TimestampFormat::Custom(format) => timestamp.format(format),
+                    };
+                    buf.push_str(&formatted.unwrap());
+                }
+                Compiled::Uuid => strings::uuid_v4(rng, buf),
+                Compiled::Ipv4 => write!(buf, "{}", Ipv4Addr::from(rng.gen::<u32>())).unwrap(),
+                Compiled::Ipv6 => write!(buf, "{}", Ipv6Addr::from(rng.gen::<u128>())).unwrap(),
+                Compiled::Word(length) => {
+                    let length = rng.gen_range(length.clone());
+                    buf.push_str(
+                        self.pool
+                            .of_size(rng, usize::from(length))
+                            .expect("pool is larger than any word"),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn timestamp<R>(rng: &mut R) -> OffsetDateTime
+where
+    R: Rng + ?Sized,
+{
+    let seconds = i64::from(TIMESTAMP_BASE_SECONDS) + rng.gen_range(0..3_600);
+    OffsetDateTime::from_unix_timestamp(seconds).expect("timestamp is in range")
+        + time::Duration::milliseconds(rng.gen_range(0..1_000))
+}
+
+fn compile(name: &str, field: &Field) -> Result<Compiled, Error> {
+    let compiled = match field {
+        Field::Int { minimum, maximum } => {
+            if minimum > maximum {
+                return Err(Error::EmptyRange(name.to_string()));
+            }
+            Compiled::Int(*minimum..=*maximum)
+        }
+        Field::Choice(choices) => {
+            if choices.is_empty() {
+                return Err(Error::EmptyChoice(name.to_string()));
+            }
+            Compiled::Choice(choices.clone())
+        }
+        Field::Timestamp { format: None } => Compiled::Timestamp(TimestampFormat::Rfc3339),
+        Field::Timestamp {
+            format: Some(format),
+        } => {
+            let format = format_description::parse_owned::<1>(format)
+                .map_err(|err| Error::TimestampFormat(name.to_string(), err))?;
+            OffsetDateTime::UNIX_EPOCH
+                .format(&format)
+                .map_err(|err| Error::TimestampFormatting(name.to_string(), err))?;
+            Compiled::Timestamp(TimestampFormat::Custom(format))
+        }
+        Field::Uuid => Compiled::Uuid,
+        Field::Ipv4 => Compiled::Ipv4,
+        Field::Ipv6 => Compiled::Ipv6,
+        Field::Word {
+            length_minimum,
+            length_maximum,
+        } => {
+            if length_minimum > length_maximum {
+                return Err(Error::EmptyRange(name.to_string()));
+            }
+            Compiled::Word(*length_minimum..=*length_maximum)
+        }
+    };
+    Ok(compiled)
+}
+
+impl Serialize for Template {
+    fn to_bytes<W, R>(
+        &self,
+        mut rng: R,
+        max_bytes: usize,
+        writer: &mut W,
+    ) -> Result<(), crate::Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut bytes_remaining = max_bytes;
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            self.render(&mut rng, &mut buf);
+            match bytes_remaining.checked_sub(buf.len()) {
+                Some(remainder) => {
+                    writer.write_all(buf.as_bytes())?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::{
+        template::{Config, Error, Field},
+        Serialize, Template,
+    };
+
+    fn config(format: &str) -> Config {
+        let mut fields = BTreeMap::new();
+        fields.insert(
+            "status".to_string(),
+            Field::Int {
+                minimum: 200,
+                maximum: 599,
+            },
+        );
+        fields.insert(
+            "method".to_string(),
+            Field::Choice(vec!["GET".to_string(), "POST".to_string()]),
+        );
+        fields.insert(
+            "ts".to_string(),
+            Field::Timestamp {
+                format: Some("[year]-[month]-[day] [hour]:[minute]:[second]".to_string()),
+            },
+        );
+        fields.insert("id".to_string(), Field::Uuid);
+        fields.insert("v4".to_string(), Field::Ipv4);
+        fields.insert("v6".to_string(), Field::Ipv6);
+        fields.insert(
+            "word".to_string(),
+            Field::Word {
+                length_minimum: 1,
+                length_maximum: 16,
+            },
+        );
+        Config {
+            format: format.to_string(),
+            fields,
+        }
+    }
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = config("{ts} {v4} {v6} {method} /api/{id}/{word} {status}");
+            let template = Template::new(&config, &mut rng).unwrap();
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            template.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            assert!(bytes.len() <= max_bytes, "max len: {max_bytes}, actual: {}", bytes.len());
+        }
+    }
+
+    // We want to be sure that every placeholder is replaced by a value of its
+    // type and that literals survive, escaped braces included.
+    proptest! {
+        #[test]
+        fn placeholders_are_typed(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = config("{{{status}}} {method} {id} {v4} {status}");
+            let template = Template::new(&config, &mut rng).unwrap();
+
+            let mut bytes = Vec::new();
+            template.to_bytes(rng, 4096, &mut bytes).unwrap();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let parts: Vec<&str> = line.split(' ').collect();
+                prop_assert_eq!(parts.len(), 5);
+                let status: i64 = parts[0]
+                    .strip_prefix('{')
+                    .and_then(|status| status.strip_suffix('}'))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                prop_assert!((200..=599).contains(&status));
+                prop_assert!(parts[1] == "GET" || parts[1] == "POST");
+                prop_assert_eq!(parts[2].len(), 36);
+                prop_assert_eq!(&parts[2][14..15], "4");
+                prop_assert!(parts[3].parse::<std::net::Ipv4Addr>().is_ok());
+                prop_assert!(parts[4].parse::<i64>().is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(matches!(
+            Template::new(&config("{missing}"), &mut rng),
+            Err(Error::UnknownField(_))
+        ));
+        assert!(matches!(
+            Template::new(&config("{status"), &mut rng),
+            Err(Error::UnclosedPlaceholder(0))
+        ));
+        assert!(matches!(
+            Template::new(&config("status}"), &mut rng),
+            Err(Error::UnmatchedBrace(6))
+        ));
+    }
+}